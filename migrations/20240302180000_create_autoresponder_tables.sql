@@ -0,0 +1,29 @@
+create table autoresponder_sequences (
+  sequence_id uuid primary key,
+  name text not null,
+  created_at timestamptz not null
+);
+
+create table autoresponder_steps (
+  step_id uuid primary key,
+  sequence_id uuid not null references autoresponder_sequences(sequence_id),
+  delay_days integer not null,
+  title text not null,
+  text_content text not null,
+  html_content text not null,
+  created_at timestamptz not null
+);
+
+create table autoresponder_enrollments (
+  sequence_id uuid not null references autoresponder_sequences(sequence_id),
+  subscriber_id uuid not null references subscriptions(id),
+  enrolled_at timestamptz not null,
+  primary key (sequence_id, subscriber_id)
+);
+
+create table autoresponder_deliveries (
+  step_id uuid not null references autoresponder_steps(step_id),
+  subscriber_id uuid not null references subscriptions(id),
+  sent_at timestamptz not null,
+  primary key (step_id, subscriber_id)
+);
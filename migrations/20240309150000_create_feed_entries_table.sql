@@ -0,0 +1,11 @@
+-- Issues imported from a feed can be stored as drafts until an admin publishes them.
+alter table newsletter_issues alter column published_at drop not null;
+alter table newsletter_issues add column created_at timestamptz not null default now();
+
+create table feed_entries (
+  feed_url text not null,
+  entry_id text not null,
+  newsletter_issue_id uuid null references newsletter_issues(newsletter_issue_id),
+  seen_at timestamptz not null,
+  primary key (feed_url, entry_id)
+);
@@ -0,0 +1,9 @@
+alter table issue_delivery_queue add column execute_after timestamptz not null default now();
+
+-- One row per rate-limit bucket and minute, shared by every worker instance.
+create table send_rate_windows (
+  bucket text not null,
+  window_start timestamptz not null,
+  sent_count integer not null,
+  primary key (bucket, window_start)
+);
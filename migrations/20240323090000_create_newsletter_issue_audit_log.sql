@@ -0,0 +1,11 @@
+alter table newsletter_issues add column updated_at timestamptz null;
+
+-- No foreign key on the issue: entries must outlive the issues they describe.
+create table newsletter_issue_audit_log (
+  audit_id uuid primary key,
+  newsletter_issue_id uuid not null,
+  user_id uuid not null references users(user_id),
+  action text not null,
+  details text not null,
+  created_at timestamptz not null
+);
@@ -0,0 +1,16 @@
+-- Accounts that exist already were all-powerful until now.
+alter table users add column role text not null default 'owner'
+  check (role in ('owner', 'publisher', 'editor', 'viewer'));
+alter table users alter column role drop default;
+alter table users add column email text;
+alter table users add column disabled_at timestamptz;
+
+create table user_invitations (
+  invitation_token_hash text primary key,
+  email text not null,
+  role text not null check (role in ('owner', 'publisher', 'editor', 'viewer')),
+  invited_by uuid not null references users (user_id),
+  created_at timestamptz not null,
+  expires_at timestamptz not null,
+  accepted_at timestamptz
+);
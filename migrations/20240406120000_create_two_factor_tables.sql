@@ -0,0 +1,16 @@
+create table user_totp (
+  user_id uuid primary key references users (user_id),
+  secret text not null,
+  -- Enrolment only takes effect once the user proved their app generates valid codes.
+  confirmed_at timestamptz,
+  -- Codes are single-use: a step at or before this one is rejected.
+  last_used_step bigint not null default 0,
+  created_at timestamptz not null
+);
+
+create table user_recovery_codes (
+  user_id uuid not null references users (user_id),
+  code_hash text not null,
+  used_at timestamptz,
+  primary key (user_id, code_hash)
+);
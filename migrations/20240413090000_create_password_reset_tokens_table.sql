@@ -0,0 +1,10 @@
+-- Sessions authenticated before this point in time are no longer accepted.
+alter table users add column sessions_revoked_at timestamptz;
+
+create table password_reset_tokens (
+  reset_token_hash text primary key,
+  user_id uuid not null references users (user_id),
+  created_at timestamptz not null,
+  expires_at timestamptz not null,
+  used_at timestamptz
+);
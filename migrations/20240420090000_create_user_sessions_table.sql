@@ -0,0 +1,12 @@
+-- Authenticated sessions, so that users can see and revoke them. A session whose
+-- row is gone is no longer accepted.
+create table user_sessions (
+  session_id uuid primary key,
+  user_id uuid not null references users (user_id) on delete cascade,
+  created_at timestamptz not null,
+  last_seen_at timestamptz not null,
+  ip_address text,
+  user_agent text
+);
+
+create index user_sessions_user_id_idx on user_sessions (user_id);
@@ -0,0 +1,32 @@
+-- One trail for every audited action, replacing the one for newsletter issues.
+-- `target` is whatever the action was about (an issue id, a username, ...).
+create table audit_log (
+  audit_id uuid primary key,
+  occurred_at timestamptz not null,
+  actor_user_id uuid null references users(user_id),
+  ip_address text null,
+  action text not null,
+  target text null,
+  details text not null
+);
+create index audit_log_occurred_at on audit_log (occurred_at);
+
+insert into audit_log (audit_id, occurred_at, actor_user_id, action, target, details)
+select audit_id, created_at, user_id, 'issue.' || action, newsletter_issue_id::text, details
+from newsletter_issue_audit_log;
+drop table newsletter_issue_audit_log;
+
+-- Entries are never changed once written, whoever asks.
+create function reject_audit_log_changes() returns trigger as $$
+begin
+  raise exception 'audit_log is append-only';
+end;
+$$ language plpgsql;
+
+create trigger audit_log_is_append_only
+before update or delete on audit_log
+for each row execute function reject_audit_log_changes();
+
+create trigger audit_log_cannot_be_truncated
+before truncate on audit_log
+for each statement execute function reject_audit_log_changes();
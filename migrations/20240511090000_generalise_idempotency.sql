@@ -0,0 +1,17 @@
+-- Keys are no longer tied to a user: anonymous requests are keyed by a
+-- fingerprint of the client. Scopes look like `user:<user_id>` or
+-- `client:<fingerprint>`.
+alter table idempotency drop constraint idempotency_pkey;
+alter table idempotency add column scope text;
+update idempotency set scope = 'user:' || user_id;
+alter table idempotency alter column scope set not null;
+alter table idempotency drop column user_id;
+alter table idempotency add primary key (scope, idempotency_key);
+
+-- A digest of the request the key was first used for, to reject its reuse
+-- for another one. Missing for the requests saved before it was recorded.
+alter table idempotency add column request_hash bytea;
+
+-- Flash messages are not part of the saved response, they are sent again
+-- from here when it is replayed.
+alter table idempotency add column response_flash_messages jsonb;
@@ -0,0 +1,2 @@
+-- Expired records are pruned oldest first.
+create index idempotency_created_at_idx on idempotency (created_at);
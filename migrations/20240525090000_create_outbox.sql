@@ -0,0 +1,12 @@
+-- Messages to other systems, written in the same transaction as the change
+-- they announce and relayed by the background worker once it has committed.
+create table outbox (
+    message_id uuid primary key,
+    payload jsonb not null,
+    n_attempts integer not null default 0,
+    execute_after timestamptz not null,
+    last_error text,
+    created_at timestamptz not null
+);
+
+create index outbox_execute_after_idx on outbox (execute_after);
@@ -0,0 +1,57 @@
+create table jobs (
+    job_id uuid primary key,
+    queue text not null,
+    kind text not null,
+    payload jsonb not null,
+    priority smallint not null,
+    -- Unique jobs are only queued once until they have run.
+    unique_key text,
+    n_attempts integer not null default 0,
+    max_attempts integer not null,
+    run_after timestamptz not null,
+    last_error text,
+    -- Set once the job has run out of attempts, it is kept for inspection.
+    failed_at timestamptz,
+    created_at timestamptz not null
+);
+
+create unique index jobs_unique_key_idx on jobs (kind, unique_key) where failed_at is null;
+create index jobs_dequeue_idx on jobs (queue, priority desc, run_after) where failed_at is null;
+-- Deliveries are looked up by issue, to count or cancel them.
+create index jobs_deliver_issue_idx on jobs ((payload->>'newsletter_issue_id'))
+where kind = 'deliver_issue';
+
+-- When each recurring job is due next, shared between the worker instances.
+create table recurring_jobs (
+    name text primary key,
+    schedule text not null,
+    next_run_at timestamptz not null
+);
+
+insert into jobs (
+    job_id,
+    queue,
+    kind,
+    payload,
+    priority,
+    unique_key,
+    max_attempts,
+    run_after,
+    created_at
+)
+select
+    gen_random_uuid(),
+    'deliveries',
+    'deliver_issue',
+    jsonb_build_object(
+        'newsletter_issue_id', newsletter_issue_id,
+        'subscriber_email', subscriber_email
+    ),
+    0,
+    newsletter_issue_id || ':' || subscriber_email,
+    5,
+    execute_after,
+    now()
+from issue_delivery_queue;
+
+drop table issue_delivery_queue;
@@ -0,0 +1,6 @@
+-- When each kind of background worker was last seen alive, for the readiness
+-- check to tell whether queued work is being picked up.
+create table worker_heartbeats (
+    worker text primary key,
+    beat_at timestamptz not null
+);
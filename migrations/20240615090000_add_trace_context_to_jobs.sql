@@ -0,0 +1,3 @@
+-- The trace context of whatever queued the job (e.g. `traceparent`), so that
+-- running it shows up as part of the same trace.
+alter table jobs add column trace_context jsonb not null default '{}';
@@ -0,0 +1,208 @@
+use std::fmt::Display;
+
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::client_ip::client_ip;
+
+/// The security- and content-relevant actions that are recorded in `audit_log`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    LoginSucceeded,
+    LoginFailed,
+    PasswordChanged,
+    PasswordReset,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    IssueDrafted,
+    IssuePublished,
+    IssueEdited,
+    IssueHalted,
+    IssueDeleted,
+    UserInvited,
+    UserRoleChanged,
+    UserDisabled,
+    UserEnabled,
+    ApiTokenCreated,
+    ApiTokenRevoked,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 17] = [
+        AuditAction::LoginSucceeded,
+        AuditAction::LoginFailed,
+        AuditAction::PasswordChanged,
+        AuditAction::PasswordReset,
+        AuditAction::TwoFactorEnabled,
+        AuditAction::TwoFactorDisabled,
+        AuditAction::IssueDrafted,
+        AuditAction::IssuePublished,
+        AuditAction::IssueEdited,
+        AuditAction::IssueHalted,
+        AuditAction::IssueDeleted,
+        AuditAction::UserInvited,
+        AuditAction::UserRoleChanged,
+        AuditAction::UserDisabled,
+        AuditAction::UserEnabled,
+        AuditAction::ApiTokenCreated,
+        AuditAction::ApiTokenRevoked,
+    ];
+
+    /// Actions are grouped by what comes before the dot, so that filtering on
+    /// `login` finds both successful and failed logins.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::LoginSucceeded => "login.succeeded",
+            AuditAction::LoginFailed => "login.failed",
+            AuditAction::PasswordChanged => "password.changed",
+            AuditAction::PasswordReset => "password.reset",
+            AuditAction::TwoFactorEnabled => "two_factor.enabled",
+            AuditAction::TwoFactorDisabled => "two_factor.disabled",
+            AuditAction::IssueDrafted => "issue.drafted",
+            AuditAction::IssuePublished => "issue.published",
+            AuditAction::IssueEdited => "issue.edited",
+            AuditAction::IssueHalted => "issue.halted",
+            AuditAction::IssueDeleted => "issue.deleted",
+            AuditAction::UserInvited => "user.invited",
+            AuditAction::UserRoleChanged => "user.role_changed",
+            AuditAction::UserDisabled => "user.disabled",
+            AuditAction::UserEnabled => "user.enabled",
+            AuditAction::ApiTokenCreated => "api_token.created",
+            AuditAction::ApiTokenRevoked => "api_token.revoked",
+        }
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+/// An entry of the audit trail, waiting to be recorded.
+pub struct AuditEvent {
+    action: AuditAction,
+    actor_user_id: Option<Uuid>,
+    ip_address: Option<String>,
+    target: Option<String>,
+    details: String,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction, request: &HttpRequest) -> Self {
+        Self {
+            action,
+            actor_user_id: None,
+            ip_address: client_ip(request).map(|ip| ip.to_string()),
+            target: None,
+            details: String::new(),
+        }
+    }
+
+    /// The user who performed the action, if they are known.
+    pub fn actor(mut self, user_id: Uuid) -> Self {
+        self.actor_user_id = Some(user_id);
+        self
+    }
+
+    pub fn target(mut self, target: impl Display) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn details(mut self, details: impl Into<String>) -> Self {
+        self.details = details.into();
+        self
+    }
+
+    /// Record the event, in the transaction of the action itself when there is one.
+    #[tracing::instrument(
+        name = "Record audit event",
+        skip_all,
+        fields(action = %self.action)
+    )]
+    pub async fn record<'e>(self, executor: impl PgExecutor<'e>) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            insert into audit_log (
+                audit_id, occurred_at, actor_user_id, ip_address, action, target, details
+            )
+            values ($1, now(), $2, $3, $4, $5, $6)
+            "#,
+            Uuid::new_v4(),
+            self.actor_user_id,
+            self.ip_address,
+            self.action.as_str(),
+            self.target,
+            self.details
+        )
+        .execute(executor)
+        .await
+        .context("Failed to record an audit event")?;
+        Ok(())
+    }
+}
+
+pub struct AuditEntry {
+    pub occurred_at: DateTime<Utc>,
+    /// The username of the actor.
+    pub actor: Option<String>,
+    pub ip_address: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub details: String,
+}
+
+/// Criteria entries must all meet. Unset ones match everything.
+#[derive(Default, Debug)]
+pub struct AuditFilter {
+    /// An action, or the group of actions before the dot.
+    pub action: Option<String>,
+    /// The username of the actor.
+    pub actor: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// How many of the most recent entries to return, all of them when unset.
+    pub limit: Option<i64>,
+}
+
+/// The matching entries, most recent first.
+#[tracing::instrument(name = "Search audit log", skip(pool))]
+pub async fn search_audit_log(
+    filter: &AuditFilter,
+    pool: &PgPool,
+) -> Result<Vec<AuditEntry>, anyhow::Error> {
+    let entries = sqlx::query_as!(
+        AuditEntry,
+        r#"
+        select
+            a.occurred_at,
+            u.username as "actor?",
+            a.ip_address,
+            a.action,
+            a.target,
+            a.details
+        from audit_log a
+        left join users u on u.user_id = a.actor_user_id
+        where ($1::text is null or a.action = $1 or a.action like $1 || '.%')
+        and ($2::text is null or u.username = $2)
+        and ($3::timestamptz is null or a.occurred_at >= $3)
+        and ($4::timestamptz is null or a.occurred_at < $4)
+        order by a.occurred_at desc, a.audit_id
+        limit $5
+        "#,
+        filter.action,
+        filter.actor,
+        filter.since,
+        filter.until,
+        filter.limit
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to search the audit log")?;
+
+    Ok(entries)
+}
@@ -0,0 +1,191 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use super::{generate_token, hash_token, Role};
+
+/// What an API token can be used for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ApiScope {
+    DraftsWrite,
+    IssuesPublish,
+    SubscribersRead,
+    MetricsRead,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 4] = [
+        ApiScope::DraftsWrite,
+        ApiScope::IssuesPublish,
+        ApiScope::SubscribersRead,
+        ApiScope::MetricsRead,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::DraftsWrite => "drafts:write",
+            ApiScope::IssuesPublish => "issues:publish",
+            ApiScope::SubscribersRead => "subscribers:read",
+            ApiScope::MetricsRead => "metrics:read",
+        }
+    }
+
+    /// The role the token owner needs for the scope to be of any use, the same
+    /// as for the matching admin pages.
+    pub fn required_role(&self) -> Role {
+        match self {
+            ApiScope::DraftsWrite => Role::Editor,
+            ApiScope::IssuesPublish => Role::Publisher,
+            ApiScope::SubscribersRead => Role::Viewer,
+            ApiScope::MetricsRead => Role::Owner,
+        }
+    }
+}
+
+impl TryFrom<String> for ApiScope {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        ApiScope::ALL
+            .into_iter()
+            .find(|s| s.as_str() == value)
+            .with_context(|| format!("{value} is not a valid API scope"))
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+pub struct ApiToken {
+    pub token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Who is calling the API, and what for.
+#[derive(Clone, Debug)]
+pub struct ApiTokenOwner {
+    pub user_id: Uuid,
+    pub role: Role,
+    pub scopes: Vec<ApiScope>,
+}
+
+/// Create a token for `user_id`. The token itself is only returned here, only
+/// its hash is stored.
+#[tracing::instrument(name = "Create API token", skip(executor))]
+pub async fn create_api_token(
+    user_id: Uuid,
+    name: &str,
+    scopes: &[ApiScope],
+    expires_at: Option<DateTime<Utc>>,
+    executor: impl PgExecutor<'_>,
+) -> Result<String, anyhow::Error> {
+    let token = generate_token();
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_owned()).collect();
+    sqlx::query!(
+        r#"
+        insert into api_tokens (token_id, user_id, name, token_hash, scopes, created_at, expires_at)
+        values ($1, $2, $3, $4, $5, now(), $6)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        hash_token(&token),
+        &scopes,
+        expires_at
+    )
+    .execute(executor)
+    .await
+    .context("Failed to store a new API token")?;
+
+    Ok(token)
+}
+
+/// The user's tokens that have not been revoked, newest first.
+#[tracing::instrument(name = "List API tokens", skip(pool))]
+pub async fn list_api_tokens(user_id: Uuid, pool: &PgPool) -> Result<Vec<ApiToken>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiToken,
+        r#"
+        select token_id, name, scopes, created_at, expires_at, last_used_at
+        from api_tokens
+        where user_id = $1 and revoked_at is null
+        order by created_at desc
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the API tokens of a user")?;
+
+    Ok(tokens)
+}
+
+/// Returns `false` if the user has no such token.
+#[tracing::instrument(name = "Revoke API token", skip(executor))]
+pub async fn revoke_api_token(
+    user_id: Uuid,
+    token_id: Uuid,
+    executor: impl PgExecutor<'_>,
+) -> Result<bool, anyhow::Error> {
+    let n_revoked = sqlx::query!(
+        r#"
+        update api_tokens
+        set revoked_at = now()
+        where user_id = $1 and token_id = $2 and revoked_at is null
+        "#,
+        user_id,
+        token_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to revoke an API token")?
+    .rows_affected();
+
+    Ok(n_revoked == 1)
+}
+
+/// Look up the owner of a token that is still valid, recording that it was used.
+#[tracing::instrument(name = "Authenticate API token", skip_all)]
+pub async fn authenticate_api_token(
+    token: &str,
+    pool: &PgPool,
+) -> Result<Option<ApiTokenOwner>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        update api_tokens
+        set last_used_at = now()
+        from users
+        where api_tokens.user_id = users.user_id
+        and api_tokens.token_hash = $1
+        and api_tokens.revoked_at is null
+        and (api_tokens.expires_at is null or api_tokens.expires_at > now())
+        and users.disabled_at is null
+        returning api_tokens.user_id, api_tokens.scopes, users.role
+        "#,
+        hash_token(token)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up an API token")?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    Ok(Some(ApiTokenOwner {
+        user_id: row.user_id,
+        role: row.role.try_into()?,
+        scopes: row
+            .scopes
+            .into_iter()
+            .map(ApiScope::try_from)
+            .collect::<Result<_, _>>()?,
+    }))
+}
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use redis::aio::ConnectionManager;
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+use crate::configuration::LoginThrottleSettings;
+
+/// Whether a login attempt may go ahead.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoginGate {
+    /// The attempt can be checked once `delay` has elapsed. It is the
+    /// `attempt`-th in a row for the account, the earlier ones all failed.
+    Open { delay: Duration, attempt: u32 },
+    /// Too many recent failures for the account or the client IP.
+    Locked { retry_after: Duration },
+}
+
+/// Counts failed logins per username and per client IP in Redis, so that the
+/// counters are shared by every application instance.
+///
+/// Every attempt is counted before it is checked, so that concurrent guesses
+/// cannot slip in under the limit. Successful ones are taken back afterwards.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    redis: ConnectionManager,
+    settings: LoginThrottleSettings,
+}
+
+impl LoginThrottle {
+    pub async fn new(
+        redis_uri: &Secret<String>,
+        settings: LoginThrottleSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let client =
+            redis::Client::open(redis_uri.expose_secret().as_str()).context("Invalid Redis URI")?;
+        let redis = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis")?;
+        Ok(Self { redis, settings })
+    }
+
+    pub fn max_failures_per_username(&self) -> u32 {
+        self.settings.max_failures_per_username
+    }
+
+    /// How long a username or IP over its limit stays locked.
+    pub fn lockout(&self) -> Duration {
+        Duration::from_secs(self.settings.lockout_seconds)
+    }
+
+    /// Count a password attempt for `username` from `ip`.
+    #[tracing::instrument(skip(self))]
+    pub async fn attempt(&self, username: &str, ip: &str) -> Result<LoginGate, anyhow::Error> {
+        self.gate([
+            (
+                self.username_key(username),
+                self.settings.max_failures_per_username,
+            ),
+            (self.ip_key(ip), self.settings.max_failures_per_ip),
+        ])
+        .await
+    }
+
+    /// Count a second factor attempt for `user_id` from `ip`. Failures are kept
+    /// across password logins, so that they do not start the count afresh.
+    #[tracing::instrument(skip(self))]
+    pub async fn attempt_second_factor(
+        &self,
+        user_id: Uuid,
+        ip: &str,
+    ) -> Result<LoginGate, anyhow::Error> {
+        self.gate([
+            (
+                self.second_factor_key(user_id),
+                self.settings.max_failures_per_username,
+            ),
+            (self.ip_key(ip), self.settings.max_failures_per_ip),
+        ])
+        .await
+    }
+
+    /// Forget the failures for `username`, and take back the attempt from `ip`.
+    /// The other failures of the client IP are kept, one valid account must not
+    /// unlock guesses against the others.
+    #[tracing::instrument(skip(self))]
+    pub async fn record_success(&self, username: &str, ip: &str) -> Result<(), anyhow::Error> {
+        let mut redis = self.redis.clone();
+        redis::pipe()
+            .atomic()
+            .del(self.username_key(username))
+            .ignore()
+            .decr(self.ip_key(ip), 1)
+            .ignore()
+            .query_async::<_, ()>(&mut redis)
+            .await
+            .context("Failed to reset a failed login counter")?;
+        Ok(())
+    }
+
+    /// Forget the second factor failures for `user_id`, and take back the
+    /// attempt from `ip`.
+    #[tracing::instrument(skip(self))]
+    pub async fn record_second_factor_success(
+        &self,
+        user_id: Uuid,
+        ip: &str,
+    ) -> Result<(), anyhow::Error> {
+        let mut redis = self.redis.clone();
+        redis::pipe()
+            .atomic()
+            .del(self.second_factor_key(user_id))
+            .ignore()
+            .decr(self.ip_key(ip), 1)
+            .ignore()
+            .query_async::<_, ()>(&mut redis)
+            .await
+            .context("Failed to reset a failed login counter")?;
+        Ok(())
+    }
+
+    /// Count an attempt against each of `counters`, then compare them with
+    /// their maximum number of failures.
+    async fn gate<const N: usize>(
+        &self,
+        counters: [(String, u32); N],
+    ) -> Result<LoginGate, anyhow::Error> {
+        let mut redis = self.redis.clone();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (key, _) in &counters {
+            pipe.incr(key, 1)
+                .expire(key, self.settings.lockout_seconds as usize)
+                .ignore();
+        }
+        let attempts: Vec<u32> = pipe
+            .query_async(&mut redis)
+            .await
+            .context("Failed to increment a failed login counter")?;
+
+        let over_limit = counters
+            .iter()
+            .zip(&attempts)
+            .any(|((_, max_failures), attempts)| attempts > max_failures);
+        if over_limit {
+            return Ok(LoginGate::Locked {
+                retry_after: self.lockout(),
+            });
+        }
+        let most_failures = attempts.iter().max().map_or(0, |attempts| attempts - 1);
+        Ok(LoginGate::Open {
+            delay: self.delay_after(most_failures),
+            attempt: attempts[0],
+        })
+    }
+
+    fn delay_after(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+        let delay = self
+            .settings
+            .base_delay_millis
+            .saturating_mul(1 << (failures - 1).min(16));
+        Duration::from_millis(delay.min(self.settings.max_delay_millis))
+    }
+
+    fn username_key(&self, username: &str) -> String {
+        format!("{}:username:{username}", self.settings.key_prefix)
+    }
+
+    fn second_factor_key(&self, user_id: Uuid) -> String {
+        format!("{}:second_factor:{user_id}", self.settings.key_prefix)
+    }
+
+    fn ip_key(&self, ip: &str) -> String {
+        format!("{}:ip:{ip}", self.settings.key_prefix)
+    }
+}
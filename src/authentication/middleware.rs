@@ -3,40 +3,278 @@ use std::ops::Deref;
 use actix_web::{
     body::MessageBody,
     dev::{ServiceRequest, ServiceResponse},
-    error::InternalError,
-    FromRequest, HttpMessage,
+    error::{ErrorForbidden, InternalError},
+    http::{
+        header::{ContentType, AUTHORIZATION},
+        Method,
+    },
+    web, FromRequest, HttpMessage, HttpResponse,
 };
+use actix_web_flash_messages::FlashMessage;
 use actix_web_lab::middleware::Next;
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
 use uuid::Uuid;
 
+use super::{authenticate_api_token, generate_token, hash_token, touch_session, ApiScope, Role};
 use crate::{
+    routes::api::ApiError,
     session_state::TypedSession,
-    utils::{e500, see_other},
+    utils::{buffer_body, e500, see_other},
 };
 
 pub async fn reject_anonymous_users(
     mut req: ServiceRequest,
-    next: Next<impl MessageBody>,
+    next: Next<impl MessageBody + 'static>,
 ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
     let session = {
         let (http_request, payload) = req.parts_mut();
         TypedSession::from_request(http_request, payload).await
     }?;
 
-    match session.get_user_id().map_err(e500)? {
-        Some(user_id) => {
-            req.extensions_mut().insert(UserId(user_id));
-            next.call(req).await
+    let Some(user_id) = session.get_user_id().map_err(e500)? else {
+        let response = see_other("/login");
+        let e = anyhow!("The user has not logged in");
+        return Err(InternalError::from_response(e, response).into());
+    };
+
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .context("The database pool is not registered as application data")
+        .map_err(e500)?;
+    let rejection = match get_active_account(user_id, pool).await.map_err(e500)? {
+        None => "Your account has been disabled.",
+        Some(account)
+            if account.sessions_revoked_at
+                > Some(session.get_authenticated_at().map_err(e500)?) =>
+        {
+            "Your session has expired, please log in again."
         }
+        Some(account) => {
+            let is_tracked = match session.get_session_id().map_err(e500)? {
+                Some(session_id) => touch_session(session_id, user_id, pool)
+                    .await
+                    .map_err(e500)?,
+                None => false,
+            };
+            if is_tracked {
+                req.extensions_mut().insert(UserId(user_id));
+                req.extensions_mut().insert(account.role);
+                return Ok(next.call(req).await?.map_into_left_body());
+            }
+            "This session has been logged out, please log in again."
+        }
+    };
+
+    // This is not returned as an error, since flash messages are only sent with responses.
+    session.log_out();
+    FlashMessage::error(rejection).send();
+    Ok(req.into_response(see_other("/login")).map_into_right_body())
+}
+
+/// Reject requests to admin routes that need a higher role than the user's.
+///
+/// Must run after `reject_anonymous_users`, which looks the role up.
+pub async fn reject_unauthorized_users(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let role = req
+        .extensions()
+        .get::<Role>()
+        .copied()
+        .context("The user role is missing from the request extensions")
+        .map_err(e500)?;
+    let route = req.match_pattern().unwrap_or_else(|| req.path().to_owned());
+
+    if role < required_role(req.method(), &route) {
+        return Err(ErrorForbidden(
+            "You are not allowed to perform this action.",
+        ));
+    }
+    next.call(req).await
+}
+
+/// Anything that is not listed explicitly is reserved to owners.
+fn required_role(method: &Method, route: &str) -> Role {
+    if route.starts_with("/admin/users") || route.starts_with("/admin/audit") {
+        return Role::Owner;
+    }
+    if method == Method::GET {
+        return Role::Viewer;
+    }
+    match route {
+        "/admin/logout"
+        | "/admin/api-tokens"
+        | "/admin/api-tokens/{token_id}/revoke"
+        | "/admin/sessions/revoke-others"
+        | "/admin/sessions/{session_id}/revoke"
+        | "/admin/password"
+        | "/admin/password/totp"
+        | "/admin/password/totp/confirm"
+        | "/admin/password/totp/disable" => Role::Viewer,
+        "/admin/newsletters/drafts/new" | "/admin/issues/{issue_id}/edit" => Role::Editor,
+        "/admin/newsletters"
+        | "/admin/newsletters/drafts"
+        | "/admin/issues/{issue_id}/halt"
+        | "/admin/issues/{issue_id}/delete"
+        | "/admin/autoresponders"
+        | "/admin/autoresponders/steps" => Role::Publisher,
+        _ => Role::Owner,
+    }
+}
+
+/// Reject state-changing requests whose form does not carry the CSRF token of
+/// the session, and hand the token to the handlers that render forms.
+///
+/// Must run after `reject_anonymous_users`, only logged in users get a token.
+pub async fn reject_forged_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let csrf_token = match session.get_csrf_token().map_err(e500)? {
+        Some(token) => token,
+        // Sessions opened before tokens were issued at login.
         None => {
-            let response = see_other("/login");
-            let e = anyhow!("The user has not logged in");
-            Err(InternalError::from_response(e, response).into())
+            let token = generate_token();
+            session.insert_csrf_token(&token).map_err(e500)?;
+            token
+        }
+    };
+
+    if !req.method().is_safe() {
+        let body = buffer_body(&mut req).await?;
+        let submitted_token = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+            .unwrap_or_default()
+            .into_iter()
+            .find_map(|(name, value)| (name == "csrf_token").then_some(value));
+
+        // Compare digests, so that the time taken does not tell how much of the token was right.
+        if submitted_token.map(|t| hash_token(&t)) != Some(hash_token(&csrf_token)) {
+            let e = anyhow!("The CSRF token is missing or does not match the session");
+            return Err(InternalError::from_response(e, stale_form_page()).into());
+        }
+    }
+
+    req.extensions_mut().insert(CsrfToken(csrf_token));
+    next.call(req).await
+}
+
+fn stale_form_page() -> HttpResponse {
+    HttpResponse::Forbidden()
+        .content_type(ContentType::html())
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html" charset="UTF-8" />
+        <meta name="viewport" content="width=device-width, initial-scale=1.0" />
+        <title>Form expired</title>
+    </head>
+    <body>
+        <p>This form has expired or did not come from this site, nothing was changed.</p>
+        <p>Go back, reload the page and submit the form again.</p>
+        <p><a href="/admin/dashboard">Back to the dashboard</a></p>
+    </body>
+</html>
+"#,
+        )
+}
+
+/// Authenticate calls to the JSON API with a bearer token, and check that the
+/// token has the scope the route needs and that its owner's role allows it.
+pub async fn reject_invalid_api_tokens(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or_else(|| ApiError::Unauthorized("An API token is required.".into()))?;
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .context("The database pool is not registered as application data")
+        .map_err(ApiError::UnexpectedError)?;
+    let owner = authenticate_api_token(token, pool)
+        .await
+        .map_err(ApiError::UnexpectedError)?
+        .ok_or_else(|| {
+            ApiError::Unauthorized("The API token is invalid, expired or revoked.".into())
+        })?;
+
+    // Unknown routes go through, to get a 404.
+    if let Some(route) = req.match_pattern() {
+        let scope = required_scope(req.method(), &route).ok_or_else(|| {
+            ApiError::Forbidden("No API token can be used for this endpoint.".into())
+        })?;
+        if !owner.scopes.contains(&scope) {
+            return Err(
+                ApiError::Forbidden(format!("The API token lacks the {scope} scope.")).into(),
+            );
+        }
+        if owner.role < scope.required_role() {
+            return Err(ApiError::Forbidden(format!(
+                "The {} role does not allow the {scope} scope.",
+                owner.role
+            ))
+            .into());
+        }
+    }
+
+    req.extensions_mut().insert(UserId(owner.user_id));
+    req.extensions_mut().insert(owner);
+    next.call(req).await
+}
+
+fn required_scope(method: &Method, route: &str) -> Option<ApiScope> {
+    match (method.as_str(), route) {
+        ("POST", "/api/v1/drafts") => Some(ApiScope::DraftsWrite),
+        ("POST", "/api/v1/drafts/{newsletter_issue_id}/publish") | ("POST", "/api/v1/issues") => {
+            Some(ApiScope::IssuesPublish)
         }
+        ("GET", "/api/v1/subscribers/count") => Some(ApiScope::SubscribersRead),
+        ("GET", "/metrics") => Some(ApiScope::MetricsRead),
+        _ => None,
     }
 }
 
+struct ActiveAccount {
+    role: Role,
+    sessions_revoked_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Get active account", skip(pool))]
+async fn get_active_account(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<ActiveAccount>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        select role, sessions_revoked_at from users
+        where user_id = $1 and disabled_at is null
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the account of a user")?;
+
+    row.map(|r| {
+        Ok(ActiveAccount {
+            role: r.role.try_into()?,
+            sessions_revoked_at: r.sessions_revoked_at,
+        })
+    })
+    .transpose()
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct UserId(Uuid);
 
@@ -53,3 +291,17 @@ impl Deref for UserId {
         &self.0
     }
 }
+
+/// The CSRF token of the session, to embed in every admin form.
+#[derive(Clone, Debug)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// The hidden input carrying the token.
+    pub fn form_field(&self) -> String {
+        format!(
+            r#"<input type="hidden" name="csrf_token" value="{}" />"#,
+            self.0
+        )
+    }
+}
@@ -1,5 +1,39 @@
+mod api_token;
+mod login_throttle;
 mod middleware;
+mod oidc;
 mod password;
+mod password_policy;
+mod password_reset;
+mod role;
+mod sessions;
+mod token;
+mod totp;
+mod two_factor;
 
-pub use middleware::{reject_anonymous_users, UserId};
-pub use password::{change_password, validate_credentials, AuthError, Credentials};
+pub use api_token::{
+    authenticate_api_token, create_api_token, list_api_tokens, revoke_api_token, ApiScope,
+    ApiToken, ApiTokenOwner,
+};
+pub use login_throttle::{LoginGate, LoginThrottle};
+pub use middleware::{
+    reject_anonymous_users, reject_forged_requests, reject_invalid_api_tokens,
+    reject_unauthorized_users, CsrfToken, UserId,
+};
+pub use oidc::{OidcClient, SsoError, SsoLogin};
+pub use password::{
+    change_password, check_new_password, create_user, validate_credentials, AuthError, Credentials,
+};
+pub use password_policy::check_password_policy;
+pub use password_reset::{get_password_reset_username, issue_password_reset_token, reset_password};
+pub use role::Role;
+pub use sessions::{
+    list_sessions, revoke_other_sessions, revoke_session, start_session, touch_session,
+    ActiveSession, SESSION_TTL,
+};
+pub use token::{generate_token, hash_token};
+pub use totp::Totp;
+pub use two_factor::{
+    confirm_totp_enrolment, disable_totp, get_totp_enrolment, is_totp_enabled,
+    start_totp_enrolment, verify_second_factor, TotpEnrolment,
+};
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use reqwest::{Client, Url};
+use secrecy::{ExposeSecret, Secret};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::generate_token;
+use crate::configuration::{OidcSettings, OidcUserField};
+
+#[derive(thiserror::Error, Debug)]
+pub enum SsoError {
+    #[error("The identity provider did not vouch for the user")]
+    InvalidIdentity(#[source] anyhow::Error),
+    #[error("No active account matches {0}")]
+    UnknownUser(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// What the callback needs to check that it completes a login we started.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SsoLogin {
+    pub state: String,
+    nonce: String,
+    code_verifier: String,
+}
+
+/// Logs users in with the authorization code flow of an OpenID Connect
+/// provider, using PKCE.
+pub struct OidcClient {
+    http_client: Client,
+    issuer_url: String,
+    client_id: String,
+    client_secret: Option<Secret<String>>,
+    redirect_uri: String,
+    claim: String,
+    user_field: OidcUserField,
+}
+
+/// The part of the provider's discovery document we use.
+#[derive(serde::Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+impl OidcClient {
+    pub fn new(issuer_url: String, base_url: &str, settings: OidcSettings) -> Self {
+        let http_client = Client::builder()
+            .timeout(settings.timeout())
+            .build()
+            .unwrap();
+
+        Self {
+            http_client,
+            issuer_url: issuer_url.trim_end_matches('/').to_owned(),
+            client_id: settings.client_id,
+            client_secret: settings.client_secret,
+            redirect_uri: format!("{base_url}/login/sso/callback"),
+            claim: settings.claim,
+            user_field: settings.user_field,
+        }
+    }
+
+    /// Where to send the browser to log in, and what to remember until it comes back.
+    #[tracing::instrument(name = "Start single sign-on", skip(self))]
+    pub async fn start_login(&self) -> Result<(Url, SsoLogin), anyhow::Error> {
+        let metadata = self.discover().await?;
+        let login = SsoLogin {
+            state: generate_token(),
+            nonce: generate_token(),
+            code_verifier: URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>()),
+        };
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(&login.code_verifier));
+        let url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_uri),
+                ("scope", "openid email profile"),
+                ("state", &login.state),
+                ("nonce", &login.nonce),
+                ("code_challenge", &code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .context("The authorization endpoint of the identity provider is not a valid URL")?;
+        Ok((url, login))
+    }
+
+    /// Redeem the code the provider sent back and find the user it identifies.
+    #[tracing::instrument(name = "Complete single sign-on", skip_all)]
+    pub async fn complete_login(
+        &self,
+        code: &str,
+        login: &SsoLogin,
+        pool: &PgPool,
+    ) -> Result<Uuid, SsoError> {
+        let metadata = self.discover().await?;
+        let id_token = self.redeem_code(&metadata, code, login).await?;
+        let identity = self
+            .verify_id_token(&metadata, &id_token, &login.nonce)
+            .await?;
+        tracing::debug!(identity, "The identity provider vouched for the user");
+
+        find_active_user(&identity, self.user_field, pool)
+            .await?
+            .ok_or(SsoError::UnknownUser(identity))
+    }
+
+    async fn discover(&self) -> Result<ProviderMetadata, anyhow::Error> {
+        let metadata: ProviderMetadata = self
+            .http_client
+            .get(format!(
+                "{}/.well-known/openid-configuration",
+                self.issuer_url
+            ))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .context("Failed to fetch the discovery document of the identity provider")?
+            .json()
+            .await
+            .context("The discovery document of the identity provider is invalid")?;
+        // Otherwise whoever serves the document could vouch for anyone.
+        if metadata.issuer.trim_end_matches('/') != self.issuer_url {
+            return Err(anyhow!(
+                "The discovery document is for {}, not {}",
+                metadata.issuer,
+                self.issuer_url
+            ));
+        }
+        Ok(metadata)
+    }
+
+    async fn redeem_code(
+        &self,
+        metadata: &ProviderMetadata,
+        code: &str,
+        login: &SsoLogin,
+    ) -> Result<String, SsoError> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_uri),
+            ("client_id", &self.client_id),
+            ("code_verifier", &login.code_verifier),
+        ];
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret.expose_secret()));
+        }
+        let response = self
+            .http_client
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .context("Failed to reach the token endpoint of the identity provider")?;
+        if response.status().is_client_error() {
+            return Err(SsoError::InvalidIdentity(anyhow!(
+                "The identity provider refused the authorization code with {}",
+                response.status()
+            )));
+        }
+        let response: TokenResponse = response
+            .error_for_status()
+            .context("The token endpoint of the identity provider failed")?
+            .json()
+            .await
+            .context("The token response of the identity provider is invalid")?;
+        Ok(response.id_token)
+    }
+
+    /// Check the ID token the way OpenID Connect Core 3.1.3.7 asks, returning
+    /// the value of the configured claim.
+    async fn verify_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<String, SsoError> {
+        let header = decode_header(id_token)
+            .context("The ID token is malformed")
+            .map_err(SsoError::InvalidIdentity)?;
+        let keys: JwkSet = self
+            .http_client
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .context("Failed to fetch the signing keys of the identity provider")?
+            .json()
+            .await
+            .context("The signing keys of the identity provider are invalid")?;
+        let key = match &header.kid {
+            Some(kid) => keys.find(kid),
+            None if keys.keys.len() == 1 => keys.keys.first(),
+            None => None,
+        }
+        .context("The ID token was not signed with a key of the identity provider")
+        .map_err(SsoError::InvalidIdentity)?;
+        let key = DecodingKey::from_jwk(key)
+            .context("Unsupported signing key")
+            .map_err(SsoError::InvalidIdentity)?;
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.client_id]);
+        let claims = decode::<HashMap<String, Value>>(id_token, &key, &validation)
+            .context("The ID token is invalid")
+            .map_err(SsoError::InvalidIdentity)?
+            .claims;
+
+        if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+            return Err(SsoError::InvalidIdentity(anyhow!(
+                "The ID token was issued for another login"
+            )));
+        }
+        if self.user_field == OidcUserField::Email
+            && claims.get("email_verified") != Some(&Value::Bool(true))
+        {
+            return Err(SsoError::InvalidIdentity(anyhow!(
+                "The email address has not been verified by the identity provider"
+            )));
+        }
+        claims
+            .get(&self.claim)
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .with_context(|| format!("The ID token has no {} claim", self.claim))
+            .map_err(SsoError::InvalidIdentity)
+    }
+}
+
+/// Identities matching several users are ignored, we cannot tell which one is meant.
+#[tracing::instrument(name = "Find single sign-on user", skip(identity, pool))]
+async fn find_active_user(
+    identity: &str,
+    user_field: OidcUserField,
+    pool: &PgPool,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let user_ids = match user_field {
+        OidcUserField::Username => {
+            sqlx::query_scalar!(
+                r#"
+                select user_id from users
+                where username = $1 and disabled_at is null
+                "#,
+                identity
+            )
+            .fetch_all(pool)
+            .await
+        }
+        OidcUserField::Email => {
+            sqlx::query_scalar!(
+                r#"
+                select user_id from users
+                where lower(email) = lower($1) and disabled_at is null
+                "#,
+                identity
+            )
+            .fetch_all(pool)
+            .await
+        }
+    }
+    .context("Failed to look up the user of a single sign-on identity")?;
+
+    Ok(match user_ids.as_slice() {
+        [user_id] => Some(*user_id),
+        _ => None,
+    })
+}
@@ -4,8 +4,10 @@ use argon2::{
     PasswordVerifier, Version,
 };
 use secrecy::{ExposeSecret, Secret};
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 
+use super::{check_password_policy, Role};
+use crate::configuration::{PasswordHashingSettings, PasswordPolicySettings};
 use crate::telemetry::{self, spawn_blocking_with_tracing};
 
 #[derive(thiserror::Error, Debug)]
@@ -21,18 +23,21 @@ pub struct Credentials {
     pub password: Secret<String>,
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+#[tracing::instrument(name = "Validate credentials", skip(credentials, hashing, pool))]
 pub async fn validate_credentials(
     credentials: Credentials,
+    hashing: &PasswordHashingSettings,
     pool: &PgPool,
 ) -> Result<uuid::Uuid, AuthError> {
     let mut user_id = None;
-    let mut expected_password_hash = Secret::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
+    // Verified against unknown usernames, with the current costs so that it
+    // takes as long as for a freshly hashed password.
+    let mut expected_password_hash = Secret::new(format!(
+        "$argon2id$v=19$m={},t={},p={}$\
         gZiV/M1gPc22ElAH/Jh1Hw$\
-        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-            .to_string(),
-    );
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+        hashing.memory_size_kib, hashing.iterations, hashing.parallelism
+    ));
     if let Some((stored_user_id, stored_password_hash)) =
         get_stored_credentials(&credentials.username, pool)
             .await
@@ -42,14 +47,83 @@ pub async fn validate_credentials(
         expected_password_hash = stored_password_hash;
     }
 
-    telemetry::spawn_blocking_with_tracing(move || {
-        verify_password_hash(expected_password_hash, credentials.password)
+    let stored_password_hash = expected_password_hash.clone();
+    let password = telemetry::spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, &credentials.password)
+            .map(|()| credentials.password)
     })
     .await
     .context("Failed to spawn blocking task")
     .map_err(AuthError::UnexpectedError)??;
 
-    user_id.ok_or_else(|| AuthError::InvalidCredentials(anyhow!("unknown username")))
+    let user_id =
+        user_id.ok_or_else(|| AuthError::InvalidCredentials(anyhow!("unknown username")))?;
+
+    if is_weaker_than(&stored_password_hash, hashing) {
+        // The password is right either way, failing to upgrade must not lock the user out.
+        if let Err(e) =
+            upgrade_password_hash(user_id, stored_password_hash, password, hashing, pool).await
+        {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to upgrade a password hash"
+            );
+        }
+    }
+
+    Ok(user_id)
+}
+
+/// Whether `password_hash` was computed with a different algorithm or lower
+/// costs than the ones currently configured.
+fn is_weaker_than(password_hash: &Secret<String>, hashing: &PasswordHashingSettings) -> bool {
+    let Ok(password_hash) = PasswordHash::new(password_hash.expose_secret()) else {
+        return false;
+    };
+    let Ok(params) = Params::try_from(&password_hash) else {
+        return false;
+    };
+    password_hash.algorithm != Algorithm::Argon2id.ident()
+        || password_hash.version != Some(Version::V0x13.into())
+        || params.m_cost() < hashing.memory_size_kib
+        || params.t_cost() < hashing.iterations
+        || params.p_cost() < hashing.parallelism
+}
+
+#[tracing::instrument(
+    name = "Upgrade password hash",
+    skip(old_password_hash, password, hashing, pool)
+)]
+async fn upgrade_password_hash(
+    user_id: uuid::Uuid,
+    old_password_hash: Secret<String>,
+    password: Secret<String>,
+    hashing: &PasswordHashingSettings,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let hashing = hashing.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, &hashing))
+            .await?
+            .context("Failed to hash password")?;
+
+    // Leave the password alone if it has been changed in the meantime.
+    sqlx::query!(
+        r#"
+        update users
+        set password_hash = $3
+        where user_id = $1 and password_hash = $2
+        "#,
+        user_id,
+        old_password_hash.expose_secret(),
+        password_hash.expose_secret()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the upgraded password hash")?;
+
+    Ok(())
 }
 
 #[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
@@ -61,7 +135,7 @@ async fn get_stored_credentials(
         r#"
         select user_id, password_hash
         from users
-        where username = $1
+        where username = $1 and disabled_at is null
         "#,
         username
     )
@@ -79,7 +153,7 @@ async fn get_stored_credentials(
 )]
 fn verify_password_hash(
     expected_password_hash: Secret<String>,
-    password_candidate: Secret<String>,
+    password_candidate: &Secret<String>,
 ) -> Result<(), AuthError> {
     let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
         .context("Failed to parse hash in PHC string format")
@@ -94,15 +168,35 @@ fn verify_password_hash(
         .map_err(AuthError::InvalidCredentials)
 }
 
-#[tracing::instrument(name = "Change password", skip(password, pool))]
+/// Everything that is wrong with a new password for `username`, empty if it can be used.
+pub fn check_new_password(
+    new_password: &Secret<String>,
+    new_password_check: &Secret<String>,
+    username: &str,
+    policy: &PasswordPolicySettings,
+) -> Vec<String> {
+    let mut violations = Vec::new();
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        violations.push(
+            "You entered two different new passwords - the field values must match.".to_string(),
+        );
+    }
+    violations.extend(check_password_policy(new_password, username, policy));
+    violations
+}
+
+#[tracing::instrument(name = "Change password", skip(password, hashing, tx))]
 pub async fn change_password(
     user_id: uuid::Uuid,
     password: Secret<String>,
-    pool: &PgPool,
+    hashing: &PasswordHashingSettings,
+    tx: &mut Transaction<'_, Postgres>,
 ) -> Result<(), anyhow::Error> {
-    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
-        .await?
-        .context("Failed to hash password")?;
+    let hashing = hashing.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, &hashing))
+            .await?
+            .context("Failed to hash password")?;
 
     sqlx::query!(
         r#"
@@ -113,23 +207,102 @@ pub async fn change_password(
         password_hash.expose_secret(),
         user_id
     )
-    .execute(pool)
+    .execute(tx.as_mut())
     .await
     .context("Failed to change user's password")?;
 
     Ok(())
 }
 
-fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+/// Store a new admin account. Returns `None` if the username is already taken.
+#[tracing::instrument(name = "Create user", skip(tx, password, email, hashing))]
+pub async fn create_user(
+    tx: &mut Transaction<'_, Postgres>,
+    username: &str,
+    password: Secret<String>,
+    email: &str,
+    role: Role,
+    hashing: &PasswordHashingSettings,
+) -> Result<Option<uuid::Uuid>, anyhow::Error> {
+    let hashing = hashing.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, &hashing))
+            .await?
+            .context("Failed to hash password")?;
+
+    let row = sqlx::query!(
+        r#"
+        insert into users (user_id, username, password_hash, email, role)
+        values ($1, $2, $3, $4, $5)
+        on conflict (username) do nothing
+        returning user_id
+        "#,
+        uuid::Uuid::new_v4(),
+        username,
+        password_hash.expose_secret(),
+        email,
+        role.as_str()
+    )
+    .fetch_optional(tx.as_mut())
+    .await
+    .context("Failed to store a new user")?;
+
+    Ok(row.map(|r| r.user_id))
+}
+
+pub(super) fn compute_password_hash(
+    password: Secret<String>,
+    hashing: &PasswordHashingSettings,
+) -> Result<Secret<String>, anyhow::Error> {
     let salt = SaltString::generate(&mut rand::thread_rng());
 
     let password_hash = Argon2::new(
         Algorithm::Argon2id,
         Version::V0x13,
-        Params::new(15000, 2, 1, None).unwrap(),
+        hashing
+            .params()
+            .context("Invalid password hashing parameters")?,
     )
     .hash_password(password.expose_secret().as_bytes(), &salt)?
     .to_string();
 
     Ok(Secret::new(password_hash))
 }
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use super::{compute_password_hash, is_weaker_than};
+    use crate::configuration::PasswordHashingSettings;
+
+    fn hashing(memory_size_kib: u32, iterations: u32) -> PasswordHashingSettings {
+        PasswordHashingSettings {
+            memory_size_kib,
+            iterations,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn hashes_with_lower_costs_are_weaker() {
+        let password_hash =
+            compute_password_hash(Secret::new("password".into()), &hashing(4096, 1)).unwrap();
+
+        assert!(is_weaker_than(&password_hash, &hashing(8192, 1)));
+        assert!(is_weaker_than(&password_hash, &hashing(4096, 2)));
+        assert!(!is_weaker_than(&password_hash, &hashing(4096, 1)));
+        assert!(!is_weaker_than(&password_hash, &hashing(2048, 1)));
+    }
+
+    #[test]
+    fn other_algorithms_are_weaker() {
+        let password_hash = Secret::new(
+            "$argon2i$v=19$m=15000,t=2,p=1$\
+            gZiV/M1gPc22ElAH/Jh1Hw$\
+            CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+                .to_string(),
+        );
+        assert!(is_weaker_than(&password_hash, &hashing(15000, 2)));
+    }
+}
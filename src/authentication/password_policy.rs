@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::configuration::PasswordPolicySettings;
+
+static COMMON_PASSWORDS: Lazy<Vec<&'static str>> = Lazy::new(|| {
+    include_str!("common_passwords.txt")
+        .lines()
+        .filter(|l| !l.is_empty())
+        .collect()
+});
+
+/// Everything that makes `password` unfit for the account of `username`.
+pub fn check_password_policy(
+    password: &Secret<String>,
+    username: &str,
+    policy: &PasswordPolicySettings,
+) -> Vec<String> {
+    let password = password.expose_secret();
+    let length = password.chars().count();
+    let mut violations = Vec::new();
+
+    if length < policy.min_length {
+        violations.push(format!(
+            "The new password must be at least {} characters long.",
+            policy.min_length
+        ));
+    }
+    if length > policy.max_length {
+        violations.push(format!(
+            "The new password must be at most {} characters long.",
+            policy.max_length
+        ));
+    }
+    if estimate_entropy_bits(password) < policy.min_entropy_bits {
+        violations.push(
+            "The new password is too predictable, use more varied characters or make it longer."
+                .to_string(),
+        );
+    }
+    let lowercase_password = password.to_lowercase();
+    let username = username.trim().to_lowercase();
+    if !username.is_empty() && lowercase_password.contains(&username) {
+        violations.push("The new password must not contain your username.".to_string());
+    }
+    if COMMON_PASSWORDS.contains(&lowercase_password.as_str()) {
+        violations.push("The new password is too common, please choose another one.".to_string());
+    }
+    violations
+}
+
+/// Shannon entropy of the characters of `password`, times its length.
+///
+/// It is a rough lower bound for random-looking passwords, but it does punish
+/// repetitions such as `aaaaaaaaaaaa` or `abababababab`.
+fn estimate_entropy_bits(password: &str) -> f64 {
+    let mut counts = HashMap::new();
+    for c in password.chars() {
+        *counts.entry(c).or_insert(0u32) += 1;
+    }
+    let length = password.chars().count() as f64;
+    let bits_per_char: f64 = counts
+        .values()
+        .map(|&n| {
+            let p = n as f64 / length;
+            -p * p.log2()
+        })
+        .sum();
+    bits_per_char * length
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_lt;
+    use secrecy::Secret;
+
+    use super::{check_password_policy, estimate_entropy_bits};
+    use crate::configuration::PasswordPolicySettings;
+
+    fn policy() -> PasswordPolicySettings {
+        PasswordPolicySettings {
+            min_length: 12,
+            max_length: 128,
+            min_entropy_bits: 36.0,
+        }
+    }
+
+    fn violations(password: &str, username: &str) -> Vec<String> {
+        check_password_policy(&Secret::new(password.to_string()), username, &policy())
+    }
+
+    #[test]
+    fn a_long_varied_password_is_accepted() {
+        assert!(violations("my cat eats seven olives", "ursula").is_empty());
+    }
+
+    #[test]
+    fn a_short_password_is_rejected() {
+        assert!(violations("xk9#mP2", "ursula")
+            .contains(&"The new password must be at least 12 characters long.".to_string()));
+    }
+
+    #[test]
+    fn a_password_longer_than_the_maximum_is_rejected() {
+        let password = "correct horse battery staple ".repeat(5);
+        assert_eq!(
+            violations(&password, "ursula"),
+            vec!["The new password must be at most 128 characters long."]
+        );
+    }
+
+    #[test]
+    fn repetitive_passwords_have_low_entropy() {
+        assert_eq!(estimate_entropy_bits("aaaaaaaaaaaaaaaa"), 0.0);
+        assert_lt!(estimate_entropy_bits("abababababababab"), 36.0);
+        assert_eq!(violations("abababababababab", "ursula").len(), 1);
+    }
+
+    #[test]
+    fn passwords_containing_the_username_are_rejected() {
+        assert_eq!(
+            violations("my name is Ursula, hi!", "ursula"),
+            vec!["The new password must not contain your username."]
+        );
+    }
+
+    #[test]
+    fn common_passwords_are_rejected_whatever_their_case() {
+        assert_eq!(
+            violations("Password1234", "ursula"),
+            vec!["The new password is too common, please choose another one."]
+        );
+    }
+
+    #[test]
+    fn each_violation_is_reported() {
+        assert_eq!(violations("ursula", "ursula").len(), 3);
+    }
+}
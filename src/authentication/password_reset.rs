@@ -0,0 +1,147 @@
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use super::{generate_token, hash_token, password::compute_password_hash};
+use crate::{
+    configuration::PasswordHashingSettings, domain::SubscriberEmail,
+    telemetry::spawn_blocking_with_tracing,
+};
+
+/// Reset links are only good for a short while, they grant access to the account.
+const RESET_TOKEN_VALIDITY: chrono::Duration = chrono::Duration::hours(1);
+
+/// Create a reset token for `username`. Returns `None` for unknown or disabled
+/// accounts, and for accounts without an email address to send the token to.
+#[tracing::instrument(name = "Issue password reset token", skip(pool))]
+pub async fn issue_password_reset_token(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(SubscriberEmail, String)>, anyhow::Error> {
+    let Some(row) = sqlx::query!(
+        r#"
+        select user_id, email as "email!"
+        from users
+        where username = $1 and disabled_at is null and email is not null
+        "#,
+        username
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the account to reset")?
+    else {
+        return Ok(None);
+    };
+    let email = SubscriberEmail::parse(row.email).map_err(anyhow::Error::msg)?;
+
+    let reset_token = generate_token();
+    sqlx::query!(
+        r#"
+        insert into password_reset_tokens (reset_token_hash, user_id, created_at, expires_at)
+        values ($1, $2, now(), $3)
+        "#,
+        hash_token(&reset_token),
+        row.user_id,
+        chrono::Utc::now() + RESET_TOKEN_VALIDITY
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the password reset token")?;
+
+    Ok(Some((email, reset_token)))
+}
+
+/// The username of the account the reset token is for, if it can still be
+/// redeemed. The token is not used up.
+#[tracing::instrument(name = "Check password reset token", skip_all)]
+pub async fn get_password_reset_username(
+    reset_token: &str,
+    pool: &PgPool,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        select users.username
+        from password_reset_tokens
+        join users on users.user_id = password_reset_tokens.user_id
+        where reset_token_hash = $1 and used_at is null and expires_at > now()
+        "#,
+        hash_token(reset_token)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the password reset token")?;
+
+    Ok(row.map(|r| r.username))
+}
+
+/// Set a new password using a reset token. All of the user's pending reset tokens
+/// and open sessions stop working. Returns `false` if the token cannot be redeemed.
+#[tracing::instrument(name = "Reset password", skip_all)]
+pub async fn reset_password(
+    reset_token: &str,
+    password: Secret<String>,
+    hashing: &PasswordHashingSettings,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let hashing = hashing.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, &hashing))
+            .await?
+            .context("Failed to hash password")?;
+
+    let mut tx = pool.begin().await?;
+    let Some(row) = sqlx::query!(
+        r#"
+        update password_reset_tokens
+        set used_at = now()
+        where reset_token_hash = $1 and used_at is null and expires_at > now()
+        returning user_id
+        "#,
+        hash_token(reset_token)
+    )
+    .fetch_optional(tx.as_mut())
+    .await
+    .context("Failed to redeem the password reset token")?
+    else {
+        return Ok(false);
+    };
+
+    sqlx::query!(
+        r#"
+        update users
+        set password_hash = $2, sessions_revoked_at = now()
+        where user_id = $1
+        "#,
+        row.user_id,
+        password_hash.expose_secret()
+    )
+    .execute(tx.as_mut())
+    .await
+    .context("Failed to reset the user's password")?;
+
+    sqlx::query!(
+        r#"
+        update password_reset_tokens
+        set used_at = now()
+        where user_id = $1 and used_at is null
+        "#,
+        row.user_id
+    )
+    .execute(tx.as_mut())
+    .await
+    .context("Failed to invalidate the other password reset tokens")?;
+
+    sqlx::query!(
+        r#"
+        delete from user_sessions
+        where user_id = $1
+        "#,
+        row.user_id
+    )
+    .execute(tx.as_mut())
+    .await
+    .context("Failed to revoke the sessions of the user")?;
+    tx.commit().await?;
+
+    Ok(true)
+}
@@ -0,0 +1,177 @@
+use actix_web::{http::header::USER_AGENT, HttpRequest};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use super::generate_token;
+use crate::{client_ip::client_ip, session_state::TypedSession};
+
+/// How long Redis keeps a session after it was last changed. Sessions are only
+/// changed when logging in, so this is also their maximum lifetime.
+pub const SESSION_TTL: chrono::Duration = chrono::Duration::days(1);
+
+/// How stale the last activity of a session may get, so that not every request
+/// has to write it.
+const LAST_SEEN_RESOLUTION: chrono::Duration = chrono::Duration::minutes(1);
+
+pub struct ActiveSession {
+    pub session_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Authenticate `session` as `user_id` and record it in the list of the user's
+/// sessions.
+#[tracing::instrument(name = "Start session", skip(session, request, pool))]
+pub async fn start_session(
+    session: &TypedSession,
+    user_id: Uuid,
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let session_id = Uuid::new_v4();
+    let ip_address = client_ip(request).map(|ip| ip.to_string());
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_owned);
+
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        delete from user_sessions
+        where user_id = $1 and created_at < $2
+        "#,
+        user_id,
+        Utc::now() - SESSION_TTL
+    )
+    .execute(tx.as_mut())
+    .await
+    .context("Failed to delete expired sessions")?;
+    sqlx::query!(
+        r#"
+        insert into user_sessions (
+            session_id, user_id, created_at, last_seen_at, ip_address, user_agent
+        )
+        values ($1, $2, now(), now(), $3, $4)
+        "#,
+        session_id,
+        user_id,
+        ip_address,
+        user_agent
+    )
+    .execute(tx.as_mut())
+    .await
+    .context("Failed to record a new session")?;
+    tx.commit().await?;
+
+    session.insert_user_id(user_id)?;
+    session.insert_session_id(session_id)?;
+    session.insert_csrf_token(&generate_token())?;
+    Ok(())
+}
+
+/// Mark the session as seen now, unless it was a moment ago. Returns `false`
+/// if it has been revoked.
+#[tracing::instrument(name = "Touch session", skip(pool))]
+pub async fn touch_session(
+    session_id: Uuid,
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let is_active = sqlx::query_scalar!(
+        r#"
+        with touched as (
+            update user_sessions
+            set last_seen_at = now()
+            where session_id = $1 and user_id = $2 and last_seen_at < $3
+        )
+        select exists(
+            select 1 from user_sessions where session_id = $1 and user_id = $2
+        ) as "exists!"
+        "#,
+        session_id,
+        user_id,
+        Utc::now() - LAST_SEEN_RESOLUTION
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to update the last activity of a session")?;
+
+    Ok(is_active)
+}
+
+/// The user's sessions that have not expired yet, most recently used first.
+#[tracing::instrument(name = "List sessions", skip(pool))]
+pub async fn list_sessions(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<ActiveSession>, anyhow::Error> {
+    let sessions = sqlx::query_as!(
+        ActiveSession,
+        r#"
+        select session_id, created_at, last_seen_at, ip_address, user_agent
+        from user_sessions
+        where user_id = $1 and created_at >= $2
+        order by last_seen_at desc
+        "#,
+        user_id,
+        Utc::now() - SESSION_TTL
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the sessions of a user")?;
+
+    Ok(sessions)
+}
+
+/// Returns `false` if the user has no such session.
+#[tracing::instrument(name = "Revoke session", skip(pool))]
+pub async fn revoke_session(
+    user_id: Uuid,
+    session_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let n_deleted = sqlx::query!(
+        r#"
+        delete from user_sessions
+        where user_id = $1 and session_id = $2
+        "#,
+        user_id,
+        session_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke a session")?
+    .rows_affected();
+
+    Ok(n_deleted == 1)
+}
+
+/// Revoke every session of the user but `current_session_id`, returning how many
+/// were revoked.
+#[tracing::instrument(name = "Revoke other sessions", skip(executor))]
+pub async fn revoke_other_sessions(
+    user_id: Uuid,
+    current_session_id: Uuid,
+    executor: impl PgExecutor<'_>,
+) -> Result<u64, anyhow::Error> {
+    let n_deleted = sqlx::query!(
+        r#"
+        delete from user_sessions
+        where user_id = $1 and session_id != $2
+        "#,
+        user_id,
+        current_session_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to revoke the other sessions of a user")?
+    .rows_affected();
+
+    Ok(n_deleted)
+}
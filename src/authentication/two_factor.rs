@@ -0,0 +1,218 @@
+use anyhow::Context;
+use rand::Rng;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::{hash_token, Totp};
+
+const N_RECOVERY_CODES: usize = 10;
+
+pub struct TotpEnrolment {
+    pub totp: Totp,
+    pub confirmed: bool,
+}
+
+#[tracing::instrument(name = "Get TOTP enrolment", skip(executor))]
+pub async fn get_totp_enrolment(
+    user_id: Uuid,
+    executor: impl PgExecutor<'_>,
+) -> Result<Option<TotpEnrolment>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        select secret, confirmed_at is not null as "confirmed!"
+        from user_totp
+        where user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to retrieve the TOTP enrolment")?;
+
+    row.map(|r| {
+        Ok(TotpEnrolment {
+            totp: Totp::from_base32(&r.secret)?,
+            confirmed: r.confirmed,
+        })
+    })
+    .transpose()
+}
+
+/// Generate a new secret, replacing any enrolment that was started but not confirmed.
+/// Two-factor authentication is not enforced until the enrolment is confirmed.
+#[tracing::instrument(name = "Start TOTP enrolment", skip(pool))]
+pub async fn start_totp_enrolment(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        insert into user_totp (user_id, secret, created_at)
+        values ($1, $2, now())
+        on conflict (user_id) do update
+        set secret = excluded.secret, created_at = excluded.created_at
+        where user_totp.confirmed_at is null
+        "#,
+        user_id,
+        Totp::generate().to_base32()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the TOTP secret")?;
+
+    Ok(())
+}
+
+/// Turn two-factor authentication on if `code` matches the pending secret.
+///
+/// Returns the recovery codes to show to the user. They are only stored hashed, so
+/// this is the one and only time they can be displayed.
+#[tracing::instrument(name = "Confirm TOTP enrolment", skip(tx, code))]
+pub async fn confirm_totp_enrolment(
+    user_id: Uuid,
+    code: &str,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Option<Vec<String>>, anyhow::Error> {
+    let Some(enrolment) = get_totp_enrolment(user_id, tx.as_mut()).await? else {
+        return Ok(None);
+    };
+    if enrolment.confirmed {
+        return Ok(None);
+    }
+    let Some(step) = enrolment.totp.verify(code, Totp::current_step()) else {
+        return Ok(None);
+    };
+
+    sqlx::query!(
+        r#"
+        update user_totp
+        set confirmed_at = now(), last_used_step = $2
+        where user_id = $1
+        "#,
+        user_id,
+        step as i64
+    )
+    .execute(tx.as_mut())
+    .await
+    .context("Failed to confirm the TOTP enrolment")?;
+
+    sqlx::query!(
+        "delete from user_recovery_codes where user_id = $1",
+        user_id
+    )
+    .execute(tx.as_mut())
+    .await
+    .context("Failed to delete old recovery codes")?;
+    let recovery_codes: Vec<String> = (0..N_RECOVERY_CODES)
+        .map(|_| generate_recovery_code())
+        .collect();
+    for recovery_code in &recovery_codes {
+        sqlx::query!(
+            r#"
+            insert into user_recovery_codes (user_id, code_hash)
+            values ($1, $2)
+            "#,
+            user_id,
+            hash_token(recovery_code)
+        )
+        .execute(tx.as_mut())
+        .await
+        .context("Failed to store a recovery code")?;
+    }
+
+    Ok(Some(recovery_codes))
+}
+
+#[tracing::instrument(name = "Disable TOTP", skip(tx))]
+pub async fn disable_totp(
+    user_id: Uuid,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "delete from user_recovery_codes where user_id = $1",
+        user_id
+    )
+    .execute(tx.as_mut())
+    .await
+    .context("Failed to delete recovery codes")?;
+    sqlx::query!("delete from user_totp where user_id = $1", user_id)
+        .execute(tx.as_mut())
+        .await
+        .context("Failed to delete the TOTP secret")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Check if TOTP is enabled", skip(pool))]
+pub async fn is_totp_enabled(user_id: Uuid, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    Ok(get_totp_enrolment(user_id, pool)
+        .await?
+        .is_some_and(|e| e.confirmed))
+}
+
+/// Accept either a code from the authenticator app or an unused recovery code.
+/// Both can only be used once.
+#[tracing::instrument(name = "Verify second factor", skip(pool, code))]
+pub async fn verify_second_factor(
+    user_id: Uuid,
+    code: &str,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    // The account may have been disabled since the password was checked.
+    let is_enabled = sqlx::query!(
+        "select 1 as exists from users where user_id = $1 and disabled_at is null",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to check whether a user is disabled")?
+    .is_some();
+    if !is_enabled {
+        return Ok(false);
+    }
+    let Some(enrolment) = get_totp_enrolment(user_id, pool).await? else {
+        return Ok(false);
+    };
+    if !enrolment.confirmed {
+        return Ok(false);
+    }
+
+    if let Some(step) = enrolment.totp.verify(code, Totp::current_step()) {
+        let n_updated = sqlx::query!(
+            r#"
+            update user_totp
+            set last_used_step = $2
+            where user_id = $1 and last_used_step < $2
+            "#,
+            user_id,
+            step as i64
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record the TOTP step")?
+        .rows_affected();
+        return Ok(n_updated == 1);
+    }
+
+    let n_used = sqlx::query!(
+        r#"
+        update user_recovery_codes
+        set used_at = now()
+        where user_id = $1 and code_hash = $2 and used_at is null
+        "#,
+        user_id,
+        hash_token(&code.trim().to_lowercase())
+    )
+    .execute(pool)
+    .await
+    .context("Failed to use a recovery code")?
+    .rows_affected();
+
+    Ok(n_used == 1)
+}
+
+fn generate_recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    format!(
+        "{:05x}-{:05x}",
+        rng.gen_range(0..0x100000),
+        rng.gen_range(0..0x100000)
+    )
+}
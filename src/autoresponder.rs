@@ -0,0 +1,216 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberEmail,
+    jobs::{self, Job, JobContext, JobOutcome, NewJob},
+    send_rate_limit::SendRateLimiter,
+};
+
+/// Enroll a freshly confirmed subscriber into every autoresponder sequence.
+#[tracing::instrument(skip(transaction))]
+pub async fn enroll_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        insert into autoresponder_enrollments (
+            sequence_id,
+            subscriber_id,
+            enrolled_at
+        )
+        select sequence_id, $1, now() from autoresponder_sequences
+        on conflict do nothing
+        "#,
+        subscriber_id
+    )
+    .execute(transaction.as_mut())
+    .await?;
+    Ok(())
+}
+
+/// Send an autoresponder step to one subscriber. The delivery is only recorded
+/// once the email is out, failures are retried like any other job.
+#[derive(Serialize, Deserialize)]
+pub struct DeliverAutoresponderStep {
+    pub step_id: Uuid,
+    pub subscriber_id: Uuid,
+}
+
+impl Job for DeliverAutoresponderStep {
+    const KIND: &'static str = "deliver_autoresponder_step";
+    const QUEUE: &'static str = "deliveries";
+
+    fn unique_key(&self) -> Option<String> {
+        Some(format!("{}:{}", self.step_id, self.subscriber_id))
+    }
+
+    #[tracing::instrument(skip_all, fields(
+        step_id=%self.step_id,
+        subscriber_id=%self.subscriber_id,
+    ))]
+    async fn run(self, context: &JobContext<'_>) -> Result<JobOutcome, anyhow::Error> {
+        let Some(step) = get_undelivered_step(context.pool, &self).await? else {
+            return Ok(JobOutcome::Completed);
+        };
+        match SubscriberEmail::parse(step.subscriber_email) {
+            Ok(email) => {
+                if !context
+                    .rate_limiter
+                    .try_acquire(context.pool, &email.domain())
+                    .await?
+                {
+                    return Ok(JobOutcome::Deferred(SendRateLimiter::next_window()));
+                }
+                context
+                    .email_client
+                    .send_email(&email, &step.title, &step.html_content, &step.text_content)
+                    .await
+                    .context("Failed to deliver an autoresponder step to a confirmed subscriber")?;
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.message = %e,
+                    "Skipping a confirmed subscriber. \
+                    Their stored contact details are invalid"
+                );
+            }
+        }
+        record_delivery(context.pool, &self).await?;
+
+        Ok(JobOutcome::Completed)
+    }
+}
+
+/// Run [`enqueue_due_steps`] on a schedule.
+#[derive(Serialize, Deserialize)]
+pub struct EnqueueDueAutoresponderSteps;
+
+impl Job for EnqueueDueAutoresponderSteps {
+    const KIND: &'static str = "enqueue_due_autoresponder_steps";
+
+    fn unique_key(&self) -> Option<String> {
+        Some(Self::KIND.into())
+    }
+
+    async fn run(self, context: &JobContext<'_>) -> Result<JobOutcome, anyhow::Error> {
+        let n_queued = enqueue_due_steps(context.pool).await?;
+        if n_queued > 0 {
+            tracing::info!(n_queued, "Queued {n_queued} autoresponder steps");
+        }
+        Ok(JobOutcome::Completed)
+    }
+}
+
+/// Queue the steps that have become due. Returns how many were queued.
+///
+/// Steps that were queued before, even if they were set aside after failing too
+/// many times, are not queued again.
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_due_steps(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let mut tx = pool.begin().await?;
+    let due_steps = sqlx::query!(
+        r#"
+        select st.step_id, e.subscriber_id
+        from autoresponder_enrollments e
+        join autoresponder_steps st on st.sequence_id = e.sequence_id
+        join subscriptions s on s.id = e.subscriber_id
+        where s.status = 'confirmed'
+        and e.enrolled_at + make_interval(days => st.delay_days) <= now()
+        -- steps added later must not backfill subscribers whose day has passed
+        and e.enrolled_at + make_interval(days => st.delay_days) >= st.created_at
+        and not exists (
+            select 1 from autoresponder_deliveries d
+            where d.step_id = st.step_id
+            and d.subscriber_id = e.subscriber_id
+        )
+        and not exists (
+            select 1 from jobs j
+            where j.kind = $1
+            and j.unique_key = st.step_id || ':' || e.subscriber_id
+        )
+        "#,
+        DeliverAutoresponderStep::KIND
+    )
+    .fetch_all(tx.as_mut())
+    .await?;
+    let n_queued = jobs::enqueue_all(
+        &mut tx,
+        due_steps.into_iter().map(|r| {
+            NewJob::new(DeliverAutoresponderStep {
+                step_id: r.step_id,
+                subscriber_id: r.subscriber_id,
+            })
+        }),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(n_queued)
+}
+
+struct UndeliveredStep {
+    subscriber_email: String,
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+/// `None` if the step has been delivered already, or should no longer be.
+#[tracing::instrument(skip_all)]
+async fn get_undelivered_step(
+    pool: &PgPool,
+    delivery: &DeliverAutoresponderStep,
+) -> Result<Option<UndeliveredStep>, anyhow::Error> {
+    let step = sqlx::query_as!(
+        UndeliveredStep,
+        r#"
+        select
+            s.email as subscriber_email,
+            st.title,
+            st.text_content,
+            st.html_content
+        from autoresponder_steps st, subscriptions s
+        where st.step_id = $1
+        and s.id = $2
+        and s.status = 'confirmed'
+        and not exists (
+            select 1 from autoresponder_deliveries d
+            where d.step_id = st.step_id
+            and d.subscriber_id = s.id
+        )
+        "#,
+        delivery.step_id,
+        delivery.subscriber_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(step)
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_delivery(
+    pool: &PgPool,
+    delivery: &DeliverAutoresponderStep,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        insert into autoresponder_deliveries (
+            step_id,
+            subscriber_id,
+            sent_at
+        ) values ($1, $2, now())
+        on conflict do nothing
+        "#,
+        delivery.step_id,
+        delivery.subscriber_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+//! Where requests come from.
+//!
+//! The `X-Forwarded-For` header is only believed when the connection comes
+//! from one of our own proxies, anyone else could write whatever they like in it.
+
+use std::net::IpAddr;
+
+use actix_web::{web, HttpRequest};
+
+/// The proxies allowed to tell us who their clients are.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+/// The address of the client that sent `request`, if it is known.
+pub fn client_ip(request: &HttpRequest) -> Option<IpAddr> {
+    let peer = request.peer_addr()?.ip();
+    let trusted = request
+        .app_data::<web::Data<TrustedProxies>>()
+        .map(|proxies| proxies.0.as_slice())
+        .unwrap_or_default();
+    let forwarded_for = request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|h| h.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    Some(resolve_client_ip(peer, &forwarded_for, trusted))
+}
+
+/// Walk back the chain of proxies, from the one that connected to us, up to
+/// the first hop that is not one of ours.
+fn resolve_client_ip(peer: IpAddr, forwarded_for: &str, trusted: &[IpAddr]) -> IpAddr {
+    let mut client = peer;
+    let mut hops = forwarded_for.rsplit(',').map(str::trim);
+    while trusted.contains(&client) {
+        match hops.next().and_then(|hop| hop.parse().ok()) {
+            Some(hop) => client = hop,
+            None => break,
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::resolve_client_ip;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn the_header_is_ignored_from_untrusted_peers() {
+        let client = resolve_client_ip(ip("203.0.113.7"), "10.0.0.1", &[ip("10.0.0.254")]);
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn trusted_proxies_are_skipped() {
+        let trusted = [ip("10.0.0.254"), ip("10.0.0.253")];
+        let client = resolve_client_ip(
+            ip("10.0.0.254"),
+            "198.51.100.1, 203.0.113.7, 10.0.0.253",
+            &trusted,
+        );
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn a_trusted_proxy_without_the_header_is_the_client() {
+        let client = resolve_client_ip(ip("10.0.0.254"), "", &[ip("10.0.0.254")]);
+        assert_eq!(client, ip("10.0.0.254"));
+    }
+
+    #[test]
+    fn garbage_in_the_header_stops_the_walk() {
+        let client = resolve_client_ip(
+            ip("10.0.0.254"),
+            "203.0.113.7, nonsense",
+            &[ip("10.0.0.254")],
+        );
+        assert_eq!(client, ip("10.0.0.254"));
+    }
+}
@@ -1,3 +1,5 @@
+use std::{net::IpAddr, num::NonZeroU32};
+
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
 use serde_aux::field_attributes::deserialize_number_from_string;
@@ -6,7 +8,10 @@ use sqlx::{
     ConnectOptions,
 };
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::{
+    authentication::OidcClient, domain::SubscriberEmail, email_client::EmailClient,
+    send_rate_limit::SendRateLimiter,
+};
 
 #[derive(Deserialize, Clone)]
 pub struct DatabaseSettings {
@@ -48,6 +53,10 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// The reverse proxies in front of the application, whose `X-Forwarded-For`
+    /// headers are trusted to tell who the client is.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 impl ApplicationSettings {
@@ -86,11 +95,265 @@ impl EmailSettings {
     }
 }
 
+#[derive(Deserialize, Clone)]
+pub struct DeliverySettings {
+    /// Upper bound across every worker instance. Unlimited when unset.
+    pub max_emails_per_minute: Option<u32>,
+    pub domain_rate_limits: Vec<DomainRateLimit>,
+}
+
+impl DeliverySettings {
+    pub fn rate_limiter(&self) -> SendRateLimiter {
+        SendRateLimiter::new(
+            self.max_emails_per_minute,
+            self.domain_rate_limits
+                .iter()
+                .map(|l| (l.domain.clone(), l.max_emails_per_minute)),
+        )
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct DomainRateLimit {
+    pub domain: String,
+    pub max_emails_per_minute: u32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct JobsSettings {
+    /// The queues this worker takes jobs from.
+    pub queues: Vec<String>,
+    /// How often idle workers look for jobs that became due, when they are not
+    /// woken up by new ones.
+    pub poll_interval_seconds: u64,
+    /// Delay before the first retry of a failed job, doubled after every further
+    /// failure.
+    pub base_retry_delay_seconds: u64,
+}
+
+impl JobsSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+
+    pub fn retry_delay(&self, n_attempts: u32) -> chrono::Duration {
+        let factor = 2i64.saturating_pow(n_attempts.saturating_sub(1));
+        chrono::Duration::seconds((self.base_retry_delay_seconds as i64).saturating_mul(factor))
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct OutboxSettings {
+    /// How often the relay looks for due messages when it is not woken up
+    /// by new ones.
+    pub poll_interval_millis: u64,
+    /// Messages that still fail after this many attempts are given up on.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every further failure.
+    pub base_retry_delay_seconds: u64,
+}
+
+impl OutboxSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.poll_interval_millis)
+    }
+
+    pub fn retry_delay(&self, n_attempts: u32) -> std::time::Duration {
+        let factor = 2u64.saturating_pow(n_attempts.saturating_sub(1));
+        std::time::Duration::from_secs(self.base_retry_delay_seconds.saturating_mul(factor))
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct FeedImportSettings {
+    /// Feed to turn into newsletter issues. Importing is disabled when unset.
+    pub feed_url: Option<String>,
+    /// When the feed is polled, as a cron expression with seconds.
+    pub schedule: String,
+    pub timeout_millis: u64,
+    /// Publish imported issues straight away instead of keeping them as drafts.
+    pub auto_publish: bool,
+    pub title_template: String,
+    pub text_template: String,
+    pub html_template: String,
+}
+
+impl FeedImportSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_millis)
+    }
+
+    pub fn client(&self) -> reqwest::Client {
+        reqwest::Client::builder()
+            .timeout(self.timeout())
+            .build()
+            .unwrap()
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LoginThrottleSettings {
+    /// Namespace of the counters, for when Redis is shared with other apps.
+    pub key_prefix: String,
+    pub max_failures_per_username: u32,
+    pub max_failures_per_ip: u32,
+    /// Failures are forgotten this long after the last one, which is also how
+    /// long a username or IP over its limit stays locked.
+    pub lockout_seconds: u64,
+    /// Delay before checking a password after the first failure, doubled for
+    /// every further one.
+    pub base_delay_millis: u64,
+    pub max_delay_millis: u64,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct IdempotencySettings {
+    /// How long a request waits for a concurrent one with the same idempotency
+    /// key to finish, before being told to retry later.
+    pub wait_timeout_millis: u64,
+    /// How long a key is remembered. Afterwards it can be used for a new request.
+    pub ttl_hours: u64,
+    /// When expired keys are deleted, as a cron expression with seconds.
+    pub prune_schedule: String,
+    /// How many expired records are deleted per statement, to keep locks short.
+    pub prune_batch_size: NonZeroU32,
+}
+
+impl IdempotencySettings {
+    pub fn wait_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.wait_timeout_millis)
+    }
+
+    pub fn ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.ttl_hours as i64)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PasswordPolicySettings {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Estimated from how varied the characters are, 12 distinct characters are
+    /// worth 43 bits while `abababababab` is worth 12.
+    pub min_entropy_bits: f64,
+}
+
+/// Argon2id cost parameters for new password hashes. Stored hashes with lower
+/// costs are upgraded the next time their owner logs in.
+#[derive(Deserialize, Clone)]
+pub struct PasswordHashingSettings {
+    pub memory_size_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(
+            self.memory_size_kib,
+            self.iterations,
+            self.parallelism,
+            None,
+        )
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct OidcSettings {
+    /// Identity provider admins can log in with. Single sign-on is disabled when unset.
+    pub issuer_url: Option<String>,
+    pub client_id: String,
+    /// Not needed if the provider treats us as a public client, PKCE protects
+    /// the code exchange either way.
+    pub client_secret: Option<Secret<String>>,
+    /// The ID token claim identifying the user, e.g. `email` or `preferred_username`.
+    pub claim: String,
+    /// The `users` column the claim is matched against.
+    pub user_field: OidcUserField,
+    pub timeout_millis: u64,
+}
+
+impl OidcSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_millis)
+    }
+
+    /// `None` when single sign-on is disabled.
+    pub fn client(self, base_url: &str) -> Option<OidcClient> {
+        let issuer_url = self.issuer_url.clone()?;
+        Some(OidcClient::new(issuer_url, base_url, self))
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OidcUserField {
+    Username,
+    Email,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct HealthSettings {
+    /// How long each readiness check may take before it counts as failed.
+    pub check_timeout_millis: u64,
+    /// Workers that have not been seen alive for longer are deemed down. Must
+    /// be well above the poll intervals of the job worker and the outbox relay.
+    pub max_heartbeat_age_seconds: i64,
+}
+
+impl HealthSettings {
+    pub fn check_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.check_timeout_millis)
+    }
+
+    pub fn max_heartbeat_age(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.max_heartbeat_age_seconds)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct MetricsSettings {
+    /// The interface the metrics port is bound to, the loopback one unless
+    /// the scraper runs on another host.
+    #[serde(default = "MetricsSettings::default_host")]
+    pub host: String,
+    /// Also serve `/metrics` on this port, without an API token. It must not
+    /// be reachable from the outside.
+    pub port: Option<u16>,
+}
+
+impl MetricsSettings {
+    fn default_host() -> String {
+        "127.0.0.1".into()
+    }
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            host: Self::default_host(),
+            port: None,
+        }
+    }
+}
+
 #[derive(Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email: EmailSettings,
+    pub delivery: DeliverySettings,
+    pub jobs: JobsSettings,
+    pub outbox: OutboxSettings,
+    pub feed_import: FeedImportSettings,
+    pub login_throttle: LoginThrottleSettings,
+    pub password_policy: PasswordPolicySettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub oidc: OidcSettings,
+    pub idempotency: IdempotencySettings,
+    pub health: HealthSettings,
+    #[serde(default)]
+    pub metrics: MetricsSettings,
     pub redis_uri: Secret<String>,
 }
 
@@ -7,6 +7,14 @@ impl SubscriberEmail {
             Err(_) => Err(format!("{} is not a valid subscriber email.", s)),
         }
     }
+
+    /// The part after the `@`, lowercased.
+    pub fn domain(&self) -> String {
+        self.0
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase())
+            .unwrap_or_default()
+    }
 }
 
 impl AsRef<str> for SubscriberEmail {
@@ -20,3 +28,14 @@ impl std::fmt::Display for SubscriberEmail {
         self.0.fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+
+    #[test]
+    fn domain_is_lowercased() {
+        let email = SubscriberEmail::parse("ursula@GMail.com".to_string()).unwrap();
+        assert_eq!(email.domain(), "gmail.com");
+    }
+}
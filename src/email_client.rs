@@ -1,7 +1,9 @@
+use std::time::Instant;
+
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
 
-use crate::domain::SubscriberEmail;
+use crate::{domain::SubscriberEmail, metrics, telemetry::current_trace_context};
 
 pub struct EmailClient {
     http_client: Client,
@@ -27,12 +29,28 @@ impl EmailClient {
         }
     }
 
+    #[tracing::instrument(name = "Send email", skip_all)]
     pub async fn send_email(
         &self,
         recipient: &SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
+    ) -> Result<(), reqwest::Error> {
+        let started = Instant::now();
+        let outcome = self
+            .post_email(recipient, subject, html_content, text_content)
+            .await;
+        metrics::record_email_sent(started.elapsed(), outcome.is_ok());
+        outcome
+    }
+
+    async fn post_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
     ) -> Result<(), reqwest::Error> {
         let url = format!("{}/email", self.base_url);
         let request_body = SendEmailRequest {
@@ -42,16 +60,15 @@ impl EmailClient {
             html_body: html_content,
             text_body: text_content,
         };
-        self.http_client
-            .post(&url)
-            .json(&request_body)
-            .header(
-                "X-Postmark-Server-Token",
-                self.authorization_token.expose_secret(),
-            )
-            .send()
-            .await?
-            .error_for_status()?;
+        let mut request = self.http_client.post(&url).json(&request_body).header(
+            "X-Postmark-Server-Token",
+            self.authorization_token.expose_secret(),
+        );
+        // Let the email API carry on with our trace.
+        for (name, value) in current_trace_context() {
+            request = request.header(name, value);
+        }
+        request.send().await?.error_for_status()?;
         Ok(())
     }
 }
@@ -0,0 +1,275 @@
+use anyhow::Context;
+use feed_rs::model::Entry;
+use htmlescape::encode_minimal;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    configuration::FeedImportSettings,
+    jobs::{Job, JobContext, JobOutcome},
+    routes::{insert_newsletter_issue, publish_newsletter_issue},
+};
+
+/// Run [`import_new_entries`] on a schedule, for the configured feed.
+#[derive(Serialize, Deserialize)]
+pub struct ImportFeed;
+
+impl Job for ImportFeed {
+    const KIND: &'static str = "import_feed";
+
+    fn unique_key(&self) -> Option<String> {
+        Some(Self::KIND.into())
+    }
+
+    async fn run(self, context: &JobContext<'_>) -> Result<JobOutcome, anyhow::Error> {
+        let Some(feed_url) = &context.feed_import.feed_url else {
+            tracing::info!("No feed url configured, feed import is disabled");
+            return Ok(JobOutcome::Completed);
+        };
+        let n_imported = import_new_entries(
+            context.pool,
+            context.feed_client,
+            feed_url,
+            context.feed_import,
+        )
+        .await?;
+        if n_imported > 0 {
+            tracing::info!("Imported {n_imported} new feed entries");
+        }
+        Ok(JobOutcome::Completed)
+    }
+}
+
+/// Fetch the feed and create an issue for every entry we have not seen before.
+///
+/// The first time a feed is polled its current entries are only recorded as seen,
+/// so that pointing the importer at an existing blog doesn't mail out its whole
+/// history. Returns the number of issues created.
+#[tracing::instrument(skip(pool, http_client, settings))]
+pub async fn import_new_entries(
+    pool: &PgPool,
+    http_client: &reqwest::Client,
+    feed_url: &str,
+    settings: &FeedImportSettings,
+) -> Result<usize, anyhow::Error> {
+    let body = http_client
+        .get(feed_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let mut feed = feed_rs::parser::parse(body.as_ref()).context("Failed to parse the feed")?;
+    // Oldest first, so issues are created in the order the entries were published.
+    feed.entries.sort_by_key(|e| e.published.or(e.updated));
+
+    let mut tx = pool.begin().await?;
+    let is_first_poll = sqlx::query!(
+        r#"select not exists(select 1 from feed_entries where feed_url = $1) as "first!""#,
+        feed_url
+    )
+    .fetch_one(tx.as_mut())
+    .await?
+    .first;
+
+    let mut n_imported = 0;
+    for entry in &feed.entries {
+        // A concurrent importer inserting the same entry blocks here until it commits,
+        // then we skip it: every entry results in at most one issue.
+        let n_inserted_rows = sqlx::query!(
+            r#"
+            insert into feed_entries (feed_url, entry_id, seen_at)
+            values ($1, $2, now())
+            on conflict do nothing
+            "#,
+            feed_url,
+            entry.id
+        )
+        .execute(tx.as_mut())
+        .await?
+        .rows_affected();
+        if n_inserted_rows == 0 || is_first_poll {
+            continue;
+        }
+
+        let fields = EntryFields::from(entry);
+        let issue_id = insert_newsletter_issue(
+            &mut tx,
+            &fields.render_text(&settings.title_template),
+            &fields.render_text(&settings.text_template),
+            &fields.render_html(&settings.html_template),
+        )
+        .await
+        .context("Failed to store newsletter issue details")?;
+
+        sqlx::query!(
+            r#"
+            update feed_entries
+            set newsletter_issue_id = $3
+            where feed_url = $1
+            and entry_id = $2
+            "#,
+            feed_url,
+            entry.id,
+            issue_id
+        )
+        .execute(tx.as_mut())
+        .await?;
+
+        if settings.auto_publish {
+            publish_newsletter_issue(&mut tx, issue_id)
+                .await
+                .context("Failed to publish newsletter issue")?;
+        }
+        n_imported += 1;
+    }
+    tx.commit().await?;
+
+    Ok(n_imported)
+}
+
+struct EntryFields {
+    title: String,
+    link: String,
+    summary: String,
+}
+
+impl From<&Entry> for EntryFields {
+    fn from(entry: &Entry) -> Self {
+        let summary = entry
+            .summary
+            .as_ref()
+            .map(|t| t.content.clone())
+            .or_else(|| entry.content.as_ref().and_then(|c| c.body.clone()))
+            .unwrap_or_default();
+
+        Self {
+            title: entry
+                .title
+                .as_ref()
+                .map(|t| t.content.clone())
+                .unwrap_or_default(),
+            // No `javascript:` or other surprises behind "Read more".
+            link: entry
+                .links
+                .first()
+                .and_then(|l| reqwest::Url::parse(&l.href).ok())
+                .filter(|url| matches!(url.scheme(), "http" | "https"))
+                .map(String::from)
+                .unwrap_or_default(),
+            summary,
+        }
+    }
+}
+
+impl EntryFields {
+    /// Fill in the placeholders of a plain text template, with the markup of the
+    /// summary stripped.
+    fn render_text(&self, template: &str) -> String {
+        template
+            .replace("{{title}}", &self.title)
+            .replace("{{link}}", &self.link)
+            .replace("{{summary}}", &html_to_text(&self.summary))
+    }
+
+    /// Fill in the placeholders of an HTML template. Feeds carry the summary as
+    /// HTML, but whoever writes the feed is not to be trusted with our pages and
+    /// emails: only an allowlist of harmless tags and attributes is kept.
+    fn render_html(&self, template: &str) -> String {
+        template
+            .replace("{{title}}", &encode_minimal(&self.title))
+            .replace("{{link}}", &encode_minimal(&self.link))
+            .replace("{{summary}}", &ammonia::clean(&self.summary))
+    }
+}
+
+/// The text of an HTML fragment: tags are dropped, paragraphs and line breaks
+/// become new lines and entities are decoded.
+fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        push_text(&mut text, &rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match name.as_str() {
+            // Their content is not text.
+            "script" | "style" if !tag.starts_with('/') => {
+                let closing_tag = format!("</{name}");
+                rest = match rest.to_ascii_lowercase().find(&closing_tag) {
+                    Some(i) => &rest[i..],
+                    None => "",
+                };
+            }
+            "br" => text.push('\n'),
+            "p" | "div" | "li" | "tr" | "blockquote" | "pre" | "ul" | "ol" | "h1" | "h2" | "h3"
+            | "h4" | "h5" | "h6" => text.push_str("\n\n"),
+            _ => {}
+        }
+    }
+    push_text(&mut text, rest);
+
+    let text = htmlescape::decode_html(&text).unwrap_or(text);
+    let mut paragraphs = Vec::new();
+    for paragraph in text.split("\n\n") {
+        // Runs of whitespace are only a separator in HTML.
+        let lines: Vec<String> = paragraph
+            .lines()
+            .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|l| !l.is_empty())
+            .collect();
+        if !lines.is_empty() {
+            paragraphs.push(lines.join("\n"));
+        }
+    }
+    paragraphs.join("\n\n")
+}
+
+/// Line breaks in the source are not line breaks in the text.
+fn push_text(text: &mut String, fragment: &str) {
+    text.extend(fragment.chars().map(|c| if c == '\n' { ' ' } else { c }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::html_to_text;
+
+    #[test]
+    fn tags_are_stripped() {
+        assert_eq!(
+            html_to_text("<p>A <em>very</em> short <a href=\"/x\">summary</a>.</p>"),
+            "A very short summary."
+        );
+    }
+
+    #[test]
+    fn paragraphs_and_line_breaks_are_kept() {
+        assert_eq!(
+            html_to_text("<p>First\n  paragraph</p>\n<p>Second<br/>line</p>"),
+            "First paragraph\n\nSecond\nline"
+        );
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        assert_eq!(html_to_text("Fish &amp; chips &lt;3"), "Fish & chips <3");
+    }
+
+    #[test]
+    fn scripts_and_styles_are_dropped() {
+        assert_eq!(
+            html_to_text("<style>p { color: red }</style>Text<script>alert(1)</script>"),
+            "Text"
+        );
+    }
+}
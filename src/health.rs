@@ -0,0 +1,227 @@
+//! Readiness checks, and the heartbeats background workers leave behind for
+//! them.
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context};
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use serde::Serialize;
+use sqlx::{migrate::Migrate, PgPool};
+
+use crate::configuration::HealthSettings;
+
+pub const JOBS_WORKER: &str = "jobs_worker";
+pub const OUTBOX_RELAY: &str = "outbox_relay";
+
+/// How often a busy worker records that it is alive.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
+/// Record that `worker` is alive.
+#[tracing::instrument(skip(pool))]
+pub async fn record_heartbeat(pool: &PgPool, worker: &str) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        insert into worker_heartbeats (worker, beat_at)
+        values ($1, now())
+        on conflict (worker) do update set beat_at = excluded.beat_at
+        "#,
+        worker
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Records the heartbeats of a worker loop, whichever way each turn goes.
+pub struct Heartbeat {
+    worker: &'static str,
+    last_beat: Option<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new(worker: &'static str) -> Self {
+        Self {
+            worker,
+            last_beat: None,
+        }
+    }
+
+    /// Call on every turn of the loop, only some of the calls are recorded.
+    pub async fn beat(&mut self, pool: &PgPool) {
+        if self
+            .last_beat
+            .is_some_and(|t| t.elapsed() < HEARTBEAT_INTERVAL)
+        {
+            return;
+        }
+        match record_heartbeat(pool, self.worker).await {
+            Ok(()) => self.last_beat = Some(Instant::now()),
+            Err(e) => tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to record a worker heartbeat"
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Healthy,
+    Unhealthy,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CheckResult {
+    pub status: Status,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The outcome of every check, ready only if all of them passed.
+#[derive(Serialize, Debug)]
+pub struct Readiness {
+    pub status: Status,
+    pub checks: BTreeMap<&'static str, CheckResult>,
+}
+
+/// What the application needs to do its job, beyond being up.
+pub struct HealthChecks {
+    redis: redis::Client,
+    settings: HealthSettings,
+}
+
+impl HealthChecks {
+    pub fn new(
+        redis_uri: &Secret<String>,
+        settings: HealthSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let redis =
+            redis::Client::open(redis_uri.expose_secret().as_str()).context("Invalid Redis URI")?;
+        Ok(Self { redis, settings })
+    }
+
+    #[tracing::instrument(name = "Check readiness", skip_all)]
+    pub async fn run(&self, pool: &PgPool) -> Readiness {
+        let max_age = self.settings.max_heartbeat_age();
+        let (database, migrations, redis, jobs_worker, outbox_relay) = tokio::join!(
+            self.timed(check_database(pool)),
+            self.timed(check_migrations(pool)),
+            self.timed(check_redis(&self.redis)),
+            self.timed(check_heartbeat(pool, JOBS_WORKER, max_age)),
+            self.timed(check_heartbeat(pool, OUTBOX_RELAY, max_age)),
+        );
+        let checks = BTreeMap::from([
+            ("database", database),
+            ("migrations", migrations),
+            ("redis", redis),
+            (JOBS_WORKER, jobs_worker),
+            (OUTBOX_RELAY, outbox_relay),
+        ]);
+        let status = if checks.values().all(|c| c.status == Status::Healthy) {
+            Status::Healthy
+        } else {
+            Status::Unhealthy
+        };
+        Readiness { status, checks }
+    }
+
+    async fn timed(&self, check: impl Future<Output = Result<(), anyhow::Error>>) -> CheckResult {
+        let started = Instant::now();
+        let outcome = tokio::time::timeout(self.settings.check_timeout(), check)
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("The check timed out")));
+        let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+        match outcome {
+            Ok(()) => CheckResult {
+                status: Status::Healthy,
+                latency_ms,
+                error: None,
+            },
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "A readiness check failed"
+                );
+                CheckResult {
+                    status: Status::Unhealthy,
+                    latency_ms,
+                    error: Some(e.to_string()),
+                }
+            }
+        }
+    }
+}
+
+async fn check_database(pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!("select 1 as one")
+        .fetch_one(pool)
+        .await
+        .context("The database is unreachable")?;
+    Ok(())
+}
+
+/// The schema must be at least as recent as the code.
+async fn check_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("The database is unreachable")?;
+    let applied = connection
+        .list_applied_migrations()
+        .await
+        .context("Failed to list the applied migrations")?;
+    let n_pending = MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .filter(|m| !applied.iter().any(|a| a.version == m.version))
+        .count();
+    if n_pending > 0 {
+        bail!("Migrations pending: {n_pending}");
+    }
+    Ok(())
+}
+
+async fn check_redis(client: &redis::Client) -> Result<(), anyhow::Error> {
+    let mut connection = client
+        .get_async_connection()
+        .await
+        .context("Redis is unreachable")?;
+    redis::cmd("PING")
+        .query_async::<_, String>(&mut connection)
+        .await
+        .context("Redis did not answer")?;
+    Ok(())
+}
+
+async fn check_heartbeat(
+    pool: &PgPool,
+    worker: &str,
+    max_age: chrono::Duration,
+) -> Result<(), anyhow::Error> {
+    let beat_at = sqlx::query_scalar!(
+        "select beat_at from worker_heartbeats where worker = $1",
+        worker
+    )
+    .fetch_optional(pool)
+    .await?
+    .with_context(|| format!("The {worker} has never been seen alive"))?;
+    let age = Utc::now() - beat_at;
+    if age > max_age {
+        bail!(
+            "The {worker} was last seen alive {}s ago",
+            age.num_seconds()
+        );
+    }
+    Ok(())
+}
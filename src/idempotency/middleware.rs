@@ -0,0 +1,219 @@
+use std::sync::{Arc, Mutex};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{RETRY_AFTER, USER_AGENT},
+        StatusCode,
+    },
+    web, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+
+use super::{
+    save_response, try_processing, FlashMessagesToReplay, IdempotencyKey, IdempotencyScope,
+    NextAction,
+};
+use crate::{
+    authentication::UserId,
+    client_ip::client_ip,
+    configuration::IdempotencySettings,
+    routes::api::ApiError,
+    utils::{buffer_body, e500},
+};
+
+/// Answer retries of a request carrying the same idempotency key, taken from
+/// the `Idempotency-Key` header or the `idempotency_key` form field, with the
+/// response saved the first time around instead of performing it again.
+///
+/// Requests without a key go through untouched, handlers that need one can
+/// look for the `IdempotencyKey` in the request data. Must run after the
+/// authentication middleware, so that the keys of users are theirs alone.
+///
+/// Retries that arrive while the first request is still being processed wait
+/// for its response, and are told to come back later if it takes too long.
+///
+/// Handlers must make their changes with [`begin_transaction`] and
+/// [`commit_transaction`], so that they are committed along with the saved
+/// response, or not at all.
+pub async fn idempotent(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    if req.method().is_safe() {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+    let body = buffer_body(&mut req).await?;
+    let Some(idempotency_key) = find_idempotency_key(&req, &body) else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    let idempotency_key: IdempotencyKey = match idempotency_key.try_into() {
+        Ok(idempotency_key) => idempotency_key,
+        Err(e) => {
+            let response = rejection(&req, StatusCode::BAD_REQUEST, e.to_string());
+            return Ok(req.into_response(response));
+        }
+    };
+    let scope = match req.extensions().get::<UserId>() {
+        Some(user_id) => IdempotencyScope::User(**user_id),
+        None => IdempotencyScope::Client(client_fingerprint(&req)),
+    };
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .context("The database pool is not registered as application data")
+        .map_err(e500)?
+        .clone();
+    let settings = req
+        .app_data::<web::Data<IdempotencySettings>>()
+        .context("The idempotency settings are not registered as application data")
+        .map_err(e500)?
+        .clone();
+
+    let request_hash = request_hash(&req, &body);
+    let tx = match try_processing(&pool, &idempotency_key, &scope, &request_hash, &settings)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(tx) => tx,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            if let Some(flash_messages) = saved_response.extensions().get::<FlashMessagesToReplay>()
+            {
+                for message in &flash_messages.0 {
+                    message.clone().send();
+                }
+            }
+            return Ok(req.into_response(saved_response));
+        }
+        NextAction::RejectReusedKey => {
+            let message = "The idempotency key has already been used for a different request.";
+            let response = rejection(&req, StatusCode::UNPROCESSABLE_ENTITY, message);
+            return Ok(req.into_response(response));
+        }
+        NextAction::RetryLater => {
+            let message = "A request with this idempotency key is still being processed, \
+                please try again later.";
+            let mut response = rejection(&req, StatusCode::CONFLICT, message);
+            let retry_after = settings.wait_timeout().as_secs().max(1);
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, retry_after.into());
+            return Ok(req.into_response(response));
+        }
+    };
+
+    let claimed = ClaimedTransaction(Arc::new(Mutex::new(Some(tx))));
+    req.extensions_mut().insert(idempotency_key.clone());
+    req.extensions_mut().insert(claimed.clone());
+    let response = next.call(req).await?;
+    // Rolling back lets a retry have another go, the failure may not last.
+    if response.status().is_server_error() {
+        return Ok(response.map_into_boxed_body());
+    }
+    // The handler gave up on its changes, so there is nothing to replay.
+    let Some(tx) = claimed.take() else {
+        return Ok(response.map_into_boxed_body());
+    };
+    let (request, response) = response.into_parts();
+    let response = save_response(tx, &idempotency_key, &scope, response.map_into_boxed_body())
+        .await
+        .map_err(e500)?;
+    Ok(ServiceResponse::new(request, response))
+}
+
+/// The transaction that claimed the idempotency key of a request.
+#[derive(Clone)]
+struct ClaimedTransaction(Arc<Mutex<Option<Transaction<'static, Postgres>>>>);
+
+impl ClaimedTransaction {
+    fn take(&self) -> Option<Transaction<'static, Postgres>> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// Begin the transaction a handler makes its changes in. For a request with an
+/// idempotency key, that is the transaction that claimed the key.
+pub async fn begin_transaction(
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let claimed = request
+        .extensions()
+        .get::<ClaimedTransaction>()
+        .and_then(ClaimedTransaction::take);
+    match claimed {
+        Some(tx) => Ok(tx),
+        None => pool.begin().await,
+    }
+}
+
+/// Commit the changes of a handler. For a request with an idempotency key,
+/// they are committed later on, along with the saved response.
+pub async fn commit_transaction(
+    request: &HttpRequest,
+    tx: Transaction<'static, Postgres>,
+) -> Result<(), sqlx::Error> {
+    let claimed = request.extensions().get::<ClaimedTransaction>().cloned();
+    match claimed {
+        Some(claimed) => {
+            *claimed.0.lock().unwrap() = Some(tx);
+            Ok(())
+        }
+        None => tx.commit().await,
+    }
+}
+
+fn find_idempotency_key(req: &ServiceRequest, body: &[u8]) -> Option<String> {
+    if let Some(header) = req.headers().get("Idempotency-Key") {
+        return Some(header.to_str().unwrap_or_default().to_owned());
+    }
+    serde_urlencoded::from_bytes::<Vec<(String, String)>>(body)
+        .unwrap_or_default()
+        .into_iter()
+        .find_map(|(name, value)| (name == "idempotency_key").then_some(value))
+}
+
+/// Tells apart the requests a key may be used for.
+fn request_hash(req: &ServiceRequest, body: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str());
+    hasher.update(b"\n");
+    hasher.update(req.uri().to_string());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hasher.finalize().to_vec()
+}
+
+fn client_fingerprint(req: &ServiceRequest) -> String {
+    let ip_address = client_ip(req.request())
+        .map(|ip| ip.to_string())
+        .unwrap_or_default();
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .map(|h| h.as_bytes())
+        .unwrap_or_default();
+    let mut hasher = Sha256::new();
+    hasher.update(ip_address);
+    hasher.update(b"\n");
+    hasher.update(user_agent);
+    hex::encode(hasher.finalize())
+}
+
+/// The JSON API reports errors its own way.
+fn rejection(req: &ServiceRequest, status: StatusCode, message: impl Into<String>) -> HttpResponse {
+    let message = message.into();
+    if req.path().starts_with("/api/") {
+        match status {
+            StatusCode::UNPROCESSABLE_ENTITY => ApiError::UnprocessableEntity(message),
+            StatusCode::CONFLICT => ApiError::Conflict(message),
+            _ => ApiError::ValidationError(message),
+        }
+        .error_response()
+    } else {
+        HttpResponse::build(status).body(message)
+    }
+}
@@ -1,10 +1,15 @@
+mod middleware;
 mod persistence;
+mod pruning;
 
+pub use middleware::*;
 pub use persistence::*;
+pub use pruning::*;
 
 use anyhow::Ok;
+use uuid::Uuid;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IdempotencyKey(String);
 
 impl TryFrom<String> for IdempotencyKey {
@@ -34,3 +39,21 @@ impl AsRef<str> for IdempotencyKey {
         &self.0
     }
 }
+
+/// Whose keys a key is checked against, so that clients cannot replay each
+/// other's responses.
+#[derive(Debug)]
+pub enum IdempotencyScope {
+    User(Uuid),
+    /// Anonymous clients, told apart by a fingerprint of their address and user agent.
+    Client(String),
+}
+
+impl std::fmt::Display for IdempotencyScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdempotencyScope::User(user_id) => write!(f, "user:{user_id}"),
+            IdempotencyScope::Client(fingerprint) => write!(f, "client:{fingerprint}"),
+        }
+    }
+}
@@ -1,9 +1,14 @@
+use std::time::{Duration, Instant};
+
 use actix_web::{body::to_bytes, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
 use reqwest::StatusCode;
 use sqlx::{postgres::PgHasArrayType, PgPool, Postgres, Transaction};
-use uuid::Uuid;
 
-use super::IdempotencyKey;
+use super::{IdempotencyKey, IdempotencyScope};
+use crate::configuration::IdempotencySettings;
 
 #[derive(Debug, sqlx::Type)]
 #[sqlx(type_name = "header_pair")]
@@ -18,22 +23,42 @@ impl PgHasArrayType for HeaderPairRecord {
     }
 }
 
+/// The flash messages a handler sent along with its response. They are stored
+/// with the response, since they only become a cookie once it has been saved.
+#[derive(Clone, Default)]
+pub struct FlashMessagesToReplay(pub Vec<FlashMessage>);
+
+/// Send `message`, and send it again whenever `response` is replayed.
+pub fn send_replayable_flash_message(response: &mut HttpResponse, message: FlashMessage) {
+    let mut extensions = response.extensions_mut();
+    if !extensions.contains::<FlashMessagesToReplay>() {
+        extensions.insert(FlashMessagesToReplay::default());
+    }
+    extensions
+        .get_mut::<FlashMessagesToReplay>()
+        .unwrap()
+        .0
+        .push(message.clone());
+    message.send();
+}
+
 pub async fn get_saved_response(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope: &IdempotencyScope,
 ) -> Result<Option<HttpResponse>, anyhow::Error> {
     let saved_response = sqlx::query!(
         r#"
         select
             response_status_code as "response_status_code!",
             response_headers as "response_headers!: Vec<HeaderPairRecord>",
-            response_body as "response_body!"
+            response_body as "response_body!",
+            response_flash_messages::text
         from idempotency
-        where user_id = $1
+        where scope = $1
         and idempotency_key = $2
         "#,
-        user_id,
+        scope.to_string(),
         idempotency_key.as_ref()
     )
     .fetch_optional(pool)
@@ -45,7 +70,15 @@ pub async fn get_saved_response(
         for HeaderPairRecord { name, value } in r.response_headers {
             response.append_header((name, value));
         }
-        Ok(Some(response.body(r.response_body)))
+        let mut response = response.body(r.response_body);
+        if let Some(flash_messages) = r.response_flash_messages {
+            let flash_messages = serde_json::from_str(&flash_messages)
+                .context("Failed to read the flash messages of a saved response")?;
+            response
+                .extensions_mut()
+                .insert(FlashMessagesToReplay(flash_messages));
+        }
+        Ok(Some(response))
     } else {
         Ok(None)
     }
@@ -54,7 +87,7 @@ pub async fn get_saved_response(
 pub async fn save_response(
     mut tx: Transaction<'static, Postgres>,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope: &IdempotencyScope,
     http_response: HttpResponse,
 ) -> Result<HttpResponse, anyhow::Error> {
     let (response_head, body) = http_response.into_parts();
@@ -69,6 +102,11 @@ pub async fn save_response(
         }
         h
     };
+    let flash_messages = response_head
+        .extensions()
+        .get::<FlashMessagesToReplay>()
+        .map(|m| serde_json::to_string(&m.0))
+        .transpose()?;
 
     sqlx::query_unchecked!(
         r#"
@@ -76,15 +114,17 @@ pub async fn save_response(
         set
             response_status_code = $3,
             response_headers = $4,
-            response_body = $5 
-        where user_id = $1
+            response_body = $5,
+            response_flash_messages = $6::text::jsonb
+        where scope = $1
         and idempotency_key = $2
         "#,
-        user_id,
+        scope.to_string(),
         idempotency_key.as_ref(),
         status_code,
         headers,
-        body.as_ref()
+        body.as_ref(),
+        flash_messages
     )
     .execute(tx.as_mut())
     .await?;
@@ -95,40 +135,97 @@ pub async fn save_response(
     Ok(http_response)
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum NextAction {
     StartProcessing(Transaction<'static, Postgres>),
     ReturnSavedResponse(HttpResponse),
+    /// The key was already used for a different request.
+    RejectReusedKey,
+    /// A concurrent request with the key is still being processed.
+    RetryLater,
 }
 
+/// How often a request checks whether a concurrent one with the same key is done.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Claim the key, or find out what became of the request that claimed it.
+///
+/// The claim is an advisory lock on the key, held until the response is
+/// saved. A request with a key that is still being processed polls for it,
+/// for up to the wait timeout, without holding on to a connection meanwhile.
+/// Expired keys are claimed again, as if they had never been used.
 pub async fn try_processing(
     pool: &PgPool,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope: &IdempotencyScope,
+    request_hash: &[u8],
+    settings: &IdempotencySettings,
 ) -> Result<NextAction, anyhow::Error> {
-    let mut tx = pool.begin().await?;
+    let started = Instant::now();
+    let mut tx = loop {
+        let mut tx = pool.begin().await?;
+        let claimed = sqlx::query_scalar!(
+            r#"select pg_try_advisory_xact_lock(hashtextextended($1, 0)) as "claimed!""#,
+            format!("{scope}:{}", idempotency_key.as_ref())
+        )
+        .fetch_one(tx.as_mut())
+        .await?;
+        if claimed {
+            break tx;
+        }
+        tx.rollback().await?;
+        let waited = started.elapsed();
+        if waited >= settings.wait_timeout() {
+            return Ok(NextAction::RetryLater);
+        }
+        tokio::time::sleep(POLL_INTERVAL.min(settings.wait_timeout() - waited)).await;
+    };
     let n_inserted_rows = sqlx::query!(
         r#"
         insert into idempotency (
-            user_id,
+            scope,
             idempotency_key,
+            request_hash,
             created_at
-        ) values ($1, $2, now())
-        on conflict do nothing
+        ) values ($1, $2, $3, now())
+        on conflict (scope, idempotency_key) do update
+        set
+            request_hash = excluded.request_hash,
+            created_at = excluded.created_at,
+            response_status_code = null,
+            response_headers = null,
+            response_body = null,
+            response_flash_messages = null
+        where idempotency.created_at < $4
         "#,
-        user_id,
-        idempotency_key.as_ref()
+        scope.to_string(),
+        idempotency_key.as_ref(),
+        request_hash,
+        Utc::now() - settings.ttl()
     )
     .execute(tx.as_mut())
     .await?
     .rows_affected();
 
     if n_inserted_rows > 0 {
-        Ok(NextAction::StartProcessing(tx))
-    } else {
-        let saved_response = get_saved_response(pool, idempotency_key, user_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
+        return Ok(NextAction::StartProcessing(tx));
+    }
+    // Let go of the claim, the request has already been processed.
+    tx.rollback().await?;
 
-        Ok(NextAction::ReturnSavedResponse(saved_response))
+    let saved_request_hash = sqlx::query_scalar!(
+        "select request_hash from idempotency where scope = $1 and idempotency_key = $2",
+        scope.to_string(),
+        idempotency_key.as_ref()
+    )
+    .fetch_one(pool)
+    .await?;
+    if saved_request_hash.is_some_and(|h| h != request_hash) {
+        return Ok(NextAction::RejectReusedKey);
     }
+
+    let saved_response = get_saved_response(pool, idempotency_key, scope)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
+    Ok(NextAction::ReturnSavedResponse(saved_response))
 }
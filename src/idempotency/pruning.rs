@@ -0,0 +1,71 @@
+use anyhow::Context;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    configuration::IdempotencySettings,
+    jobs::{Job, JobContext, JobOutcome},
+    metrics,
+};
+
+/// Run [`prune_expired_records`] on a schedule.
+#[derive(Serialize, Deserialize)]
+pub struct PruneIdempotencyRecords;
+
+impl Job for PruneIdempotencyRecords {
+    const KIND: &'static str = "prune_idempotency_records";
+
+    fn unique_key(&self) -> Option<String> {
+        Some(Self::KIND.into())
+    }
+
+    async fn run(self, context: &JobContext<'_>) -> Result<JobOutcome, anyhow::Error> {
+        let n_pruned = prune_expired_records(context.pool, context.idempotency).await?;
+        if n_pruned > 0 {
+            tracing::info!(n_pruned, "Pruned {n_pruned} expired idempotency records");
+        }
+        Ok(JobOutcome::Completed)
+    }
+}
+
+/// Delete the records that have expired, in batches of `batch_size`.
+/// Returns how many were deleted.
+#[tracing::instrument(
+    name = "Prune expired idempotency records",
+    skip(pool, settings),
+    fields(n_pruned=tracing::field::Empty)
+)]
+pub async fn prune_expired_records(
+    pool: &PgPool,
+    settings: &IdempotencySettings,
+) -> Result<u64, anyhow::Error> {
+    let expired_before = Utc::now() - settings.ttl();
+    let mut n_pruned = 0;
+    loop {
+        let n_deleted = sqlx::query!(
+            r#"
+            delete from idempotency
+            where (scope, idempotency_key) in (
+                select scope, idempotency_key
+                from idempotency
+                where created_at < $1
+                limit $2
+                for update skip locked
+            )
+            "#,
+            expired_before,
+            i64::from(settings.prune_batch_size.get())
+        )
+        .execute(pool)
+        .await
+        .context("Failed to delete expired idempotency records")?
+        .rows_affected();
+        metrics::record_idempotency_records_pruned(n_deleted);
+        n_pruned += n_deleted;
+        if n_deleted < u64::from(settings.prune_batch_size.get()) {
+            tracing::Span::current().record("n_pruned", n_pruned);
+            return Ok(n_pruned);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberEmail,
+    jobs::{self, Job, JobContext, JobOutcome, NewJob},
+    send_rate_limit::SendRateLimiter,
+};
+
+/// Send a published issue to one of the confirmed subscribers.
+#[derive(Serialize, Deserialize)]
+pub struct DeliverIssue {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_email: String,
+}
+
+impl Job for DeliverIssue {
+    const KIND: &'static str = "deliver_issue";
+    const QUEUE: &'static str = "deliveries";
+
+    fn unique_key(&self) -> Option<String> {
+        Some(format!(
+            "{}:{}",
+            self.newsletter_issue_id, self.subscriber_email
+        ))
+    }
+
+    #[tracing::instrument(skip_all, fields(
+        newsletter_issue_id=%self.newsletter_issue_id,
+        subscriber_email=%self.subscriber_email,
+    ))]
+    async fn run(self, context: &JobContext<'_>) -> Result<JobOutcome, anyhow::Error> {
+        let email = match SubscriberEmail::parse(self.subscriber_email) {
+            Ok(email) => email,
+            Err(e) => {
+                tracing::error!(
+                    error.message = %e,
+                    "Skipping a confirmed subscriber. \
+                    Their stored contact details are invalid"
+                );
+                return Ok(JobOutcome::Completed);
+            }
+        };
+        if !context
+            .rate_limiter
+            .try_acquire(context.pool, &email.domain())
+            .await?
+        {
+            return Ok(JobOutcome::Deferred(SendRateLimiter::next_window()));
+        }
+        let Some(issue) = get_issue(context.pool, self.newsletter_issue_id).await? else {
+            tracing::warn!("Skipping the delivery of an issue that no longer exists");
+            return Ok(JobOutcome::Completed);
+        };
+
+        context
+            .email_client
+            .send_email(
+                &email,
+                &issue.title,
+                &issue.html_content,
+                &issue.text_content,
+            )
+            .await
+            .context("Failed to deliver issue to a confirmed subscriber")?;
+
+        Ok(JobOutcome::Completed)
+    }
+}
+
+/// Queue the delivery of an issue to every confirmed subscriber.
+#[tracing::instrument(skip(transaction))]
+pub async fn enqueue_deliveries(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let subscriber_emails =
+        sqlx::query_scalar!("select email from subscriptions where status = 'confirmed'")
+            .fetch_all(transaction.as_mut())
+            .await?;
+    let deliveries = subscriber_emails.into_iter().map(|subscriber_email| {
+        NewJob::new(DeliverIssue {
+            newsletter_issue_id,
+            subscriber_email,
+        })
+    });
+    jobs::enqueue_all(transaction, deliveries).await?;
+    Ok(())
+}
+
+/// Drop the deliveries of an issue that have not happened yet. Returns how
+/// many were cancelled.
+#[tracing::instrument(skip(transaction))]
+pub async fn cancel_pending_deliveries(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<u64, anyhow::Error> {
+    let n_cancelled = sqlx::query!(
+        r#"
+        delete from jobs
+        where kind = $1
+        and payload->>'newsletter_issue_id' = $2
+        and failed_at is null
+        "#,
+        DeliverIssue::KIND,
+        newsletter_issue_id.to_string()
+    )
+    .execute(transaction.as_mut())
+    .await?
+    .rows_affected();
+
+    Ok(n_cancelled)
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        select title, text_content, html_content
+        from newsletter_issues
+        where newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(issue)
+}
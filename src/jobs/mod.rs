@@ -0,0 +1,231 @@
+//! Background jobs, queued in Postgres.
+//!
+//! A job is a serializable value that knows how to run itself. Queue one with
+//! [`NewJob`], as part of the transaction that calls for it, and any worker
+//! serving its queue picks it up once the transaction commits.
+
+mod schedule;
+mod worker;
+
+pub use schedule::*;
+pub use worker::*;
+
+use std::{future::Future, pin::Pin};
+
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    autoresponder::{DeliverAutoresponderStep, EnqueueDueAutoresponderSteps},
+    configuration::{FeedImportSettings, IdempotencySettings, Settings},
+    email_client::EmailClient,
+    feed_importer::ImportFeed,
+    idempotency::PruneIdempotencyRecords,
+    issue_delivery::DeliverIssue,
+    send_rate_limit::{PruneSendRateWindows, SendRateLimiter},
+    telemetry::current_trace_context,
+    wakeup::{self, JOBS_CHANNEL},
+};
+
+pub const DEFAULT_QUEUE: &str = "default";
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// What running jobs can make use of.
+pub struct JobContext<'a> {
+    pub pool: &'a PgPool,
+    pub email_client: &'a EmailClient,
+    pub rate_limiter: &'a SendRateLimiter,
+    pub idempotency: &'a IdempotencySettings,
+    pub feed_import: &'a FeedImportSettings,
+    pub feed_client: &'a reqwest::Client,
+}
+
+pub enum JobOutcome {
+    Completed,
+    /// Run the job again at the given time, without counting it as a failed attempt.
+    Deferred(DateTime<Utc>),
+}
+
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    /// Tells apart the jobs in the queue, must never change once jobs are queued.
+    const KIND: &'static str;
+    const QUEUE: &'static str = DEFAULT_QUEUE;
+    /// Jobs with a higher priority run first.
+    const PRIORITY: i16 = 0;
+    /// Jobs that still fail after this many attempts are set aside.
+    const MAX_ATTEMPTS: i32 = 5;
+
+    /// Jobs with the same key are only queued once until they have run.
+    fn unique_key(&self) -> Option<String> {
+        None
+    }
+
+    /// Errors are retried later, with an exponential backoff.
+    fn run(
+        self,
+        context: &JobContext<'_>,
+    ) -> impl Future<Output = Result<JobOutcome, anyhow::Error>> + Send;
+}
+
+/// The jobs this application knows how to run.
+pub fn job_registry() -> JobRegistry {
+    JobRegistry::default()
+        .register::<DeliverIssue>()
+        .register::<DeliverAutoresponderStep>()
+        .register::<EnqueueDueAutoresponderSteps>()
+        .register::<PruneSendRateWindows>()
+        .register::<PruneIdempotencyRecords>()
+        .register::<ImportFeed>()
+}
+
+/// The jobs run on a schedule.
+pub fn recurring_jobs(configuration: &Settings) -> Result<Vec<RecurringJob>, anyhow::Error> {
+    let mut recurring_jobs = vec![
+        RecurringJob::new(
+            "prune_send_rate_windows",
+            // Every ten minutes.
+            "0 */10 * * * *",
+            &PruneSendRateWindows,
+        )?,
+        RecurringJob::new(
+            "prune_idempotency_records",
+            &configuration.idempotency.prune_schedule,
+            &PruneIdempotencyRecords,
+        )?,
+        RecurringJob::new(
+            "enqueue_due_autoresponder_steps",
+            // Every minute.
+            "0 * * * * *",
+            &EnqueueDueAutoresponderSteps,
+        )?,
+    ];
+    if configuration.feed_import.feed_url.is_some() {
+        recurring_jobs.push(RecurringJob::new(
+            "import_feed",
+            &configuration.feed_import.schedule,
+            &ImportFeed,
+        )?);
+    }
+    Ok(recurring_jobs)
+}
+
+/// A job about to be queued, with the options its type does not set.
+pub struct NewJob<J> {
+    job: J,
+    priority: i16,
+    run_after: Option<DateTime<Utc>>,
+}
+
+impl<J: Job> NewJob<J> {
+    pub fn new(job: J) -> Self {
+        Self {
+            job,
+            priority: J::PRIORITY,
+            run_after: None,
+        }
+    }
+
+    pub fn priority(mut self, priority: i16) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Don't run the job before `run_after`, rather than as soon as possible.
+    pub fn run_after(mut self, run_after: DateTime<Utc>) -> Self {
+        self.run_after = Some(run_after);
+        self
+    }
+
+    /// Returns `false` if an equivalent unique job was already queued.
+    pub async fn enqueue(
+        self,
+        transaction: &mut Transaction<'_, Postgres>,
+    ) -> Result<bool, anyhow::Error> {
+        Ok(enqueue_all(transaction, [self]).await? > 0)
+    }
+}
+
+/// Queue `jobs` in a single statement. Returns how many were queued, unique
+/// jobs that were already queued are skipped.
+///
+/// The jobs run as part of the current trace.
+#[tracing::instrument(skip_all, fields(kind = J::KIND))]
+pub async fn enqueue_all<J: Job>(
+    transaction: &mut Transaction<'_, Postgres>,
+    jobs: impl IntoIterator<Item = NewJob<J>>,
+) -> Result<u64, anyhow::Error> {
+    let mut job_ids = Vec::new();
+    let mut payloads = Vec::new();
+    let mut unique_keys = Vec::new();
+    let mut priorities = Vec::new();
+    let mut run_after = Vec::new();
+    for new_job in jobs {
+        job_ids.push(Uuid::new_v4());
+        payloads.push(serde_json::to_string(&new_job.job)?);
+        unique_keys.push(new_job.job.unique_key());
+        priorities.push(new_job.priority);
+        run_after.push(new_job.run_after);
+    }
+    if job_ids.is_empty() {
+        return Ok(0);
+    }
+    let trace_context = serde_json::to_string(&current_trace_context())?;
+
+    let n_queued = sqlx::query!(
+        r#"
+        insert into jobs (
+            job_id,
+            queue,
+            kind,
+            payload,
+            priority,
+            unique_key,
+            max_attempts,
+            run_after,
+            trace_context,
+            created_at
+        )
+        select
+            job_id,
+            $1,
+            $2,
+            payload::jsonb,
+            priority,
+            unique_key,
+            $3,
+            coalesce(run_after, now()),
+            $9::text::jsonb,
+            now()
+        from unnest($4::uuid[], $5::text[], $6::int2[], $7::text[], $8::timestamptz[])
+            as t(job_id, payload, priority, unique_key, run_after)
+        on conflict do nothing
+        "#,
+        J::QUEUE,
+        J::KIND,
+        J::MAX_ATTEMPTS,
+        &job_ids,
+        &payloads,
+        &priorities,
+        &unique_keys as &[Option<String>],
+        &run_after as &[Option<DateTime<Utc>>],
+        trace_context,
+    )
+    .execute(transaction.as_mut())
+    .await?
+    .rows_affected();
+
+    if n_queued > 0 {
+        wakeup::notify(transaction, JOBS_CHANNEL).await?;
+    }
+    Ok(n_queued)
+}
+
+/// The outcome of a worker's attempt at picking up work.
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    TaskDeferred,
+    EmptyQueue,
+}
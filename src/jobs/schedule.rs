@@ -0,0 +1,94 @@
+use std::str::FromStr;
+
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use super::{BoxFuture, Job, NewJob};
+
+type EnqueueFn = for<'a> fn(
+    &'a mut Transaction<'static, Postgres>,
+    &'a str,
+) -> BoxFuture<'a, Result<bool, anyhow::Error>>;
+
+/// A job queued on a cron schedule, e.g. `0 30 9 * * Mon-Fri` (with seconds).
+///
+/// Runs missed while no worker was around are made up for once, not once each.
+pub struct RecurringJob {
+    name: &'static str,
+    schedule: cron::Schedule,
+    payload: String,
+    enqueue: EnqueueFn,
+}
+
+impl RecurringJob {
+    pub fn new<J: Job>(name: &'static str, schedule: &str, job: &J) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            name,
+            schedule: cron::Schedule::from_str(schedule)?,
+            payload: serde_json::to_string(job)?,
+            enqueue: enqueue_payload::<J>,
+        })
+    }
+}
+
+fn enqueue_payload<'a, J: Job>(
+    transaction: &'a mut Transaction<'static, Postgres>,
+    payload: &'a str,
+) -> BoxFuture<'a, Result<bool, anyhow::Error>> {
+    Box::pin(async move {
+        let job: J = serde_json::from_str(payload)?;
+        NewJob::new(job).enqueue(transaction).await
+    })
+}
+
+/// Queue the recurring jobs that are due. Safe to call from every worker, each
+/// run is only queued once.
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_due_recurring_jobs(
+    pool: &PgPool,
+    recurring_jobs: &[RecurringJob],
+) -> Result<(), anyhow::Error> {
+    for recurring_job in recurring_jobs {
+        let now = Utc::now();
+        let Some(next_run_at) = recurring_job.schedule.after(&now).next() else {
+            continue;
+        };
+        let schedule = recurring_job.schedule.to_string();
+        let mut tx = pool.begin().await?;
+        let last_seen = sqlx::query!(
+            r#"
+            select schedule, next_run_at <= now() as "is_due!"
+            from recurring_jobs
+            where name = $1
+            for update
+            "#,
+            recurring_job.name
+        )
+        .fetch_optional(tx.as_mut())
+        .await?;
+        match last_seen {
+            // New schedules start from now on.
+            Some(r) if r.schedule == schedule && r.is_due => {
+                (recurring_job.enqueue)(&mut tx, &recurring_job.payload).await?;
+                tracing::info!(name = recurring_job.name, "Queued a recurring job");
+            }
+            Some(r) if r.schedule == schedule => continue,
+            _ => {}
+        }
+        sqlx::query!(
+            r#"
+            insert into recurring_jobs (name, schedule, next_run_at)
+            values ($1, $2, $3)
+            on conflict (name) do update
+            set schedule = excluded.schedule, next_run_at = excluded.next_run_at
+            "#,
+            recurring_job.name,
+            schedule,
+            next_run_at
+        )
+        .execute(tx.as_mut())
+        .await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
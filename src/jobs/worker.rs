@@ -0,0 +1,274 @@
+use std::{collections::HashMap, time::Instant};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Instrument, Span};
+use uuid::Uuid;
+
+use super::{
+    enqueue_due_recurring_jobs, job_registry, recurring_jobs, BoxFuture, ExecutionOutcome, Job,
+    JobContext, JobOutcome, RecurringJob,
+};
+use crate::{
+    configuration::{JobsSettings, Settings},
+    health::{Heartbeat, JOBS_WORKER},
+    startup::get_connection_pool,
+    telemetry::continue_trace,
+    wakeup::{Wakeup, JOBS_CHANNEL},
+};
+
+type PgTx = Transaction<'static, Postgres>;
+type JobRunner =
+    for<'a> fn(&str, &'a JobContext<'a>) -> BoxFuture<'a, Result<JobOutcome, anyhow::Error>>;
+
+/// Maps the kind of the queued jobs to the code that runs them.
+#[derive(Default)]
+pub struct JobRegistry {
+    runners: HashMap<&'static str, JobRunner>,
+}
+
+impl JobRegistry {
+    pub fn register<J: Job>(mut self) -> Self {
+        self.runners.insert(J::KIND, run_job::<J>);
+        self
+    }
+}
+
+fn run_job<'a, J: Job>(
+    payload: &str,
+    context: &'a JobContext<'a>,
+) -> BoxFuture<'a, Result<JobOutcome, anyhow::Error>> {
+    match serde_json::from_str::<J>(payload) {
+        Ok(job) => Box::pin(job.run(context)),
+        Err(e) => {
+            let e = anyhow::Error::new(e).context("The job payload is unreadable");
+            Box::pin(std::future::ready(Err(e)))
+        }
+    }
+}
+
+struct QueuedJob {
+    job_id: Uuid,
+    kind: String,
+    payload: String,
+    trace_context: String,
+    n_attempts: i32,
+    max_attempts: i32,
+}
+
+#[tracing::instrument(skip_all, fields(
+    job_id=tracing::field::Empty,
+    kind=tracing::field::Empty,
+    n_attempts=tracing::field::Empty,
+))]
+pub async fn try_execute_job(
+    registry: &JobRegistry,
+    context: &JobContext<'_>,
+    settings: &JobsSettings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((tx, job)) = dequeue_job(context.pool, &settings.queues).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("job_id", display(job.job_id))
+        .record("kind", display(&job.kind))
+        .record("n_attempts", job.n_attempts);
+
+    // The job runs as part of the trace it was queued from.
+    let run_span = tracing::info_span!("Run job");
+    continue_trace(
+        &run_span,
+        &serde_json::from_str(&job.trace_context).unwrap_or_default(),
+    );
+    let outcome = match registry.runners.get(job.kind.as_str()) {
+        Some(run) => run(&job.payload, context).instrument(run_span).await,
+        None => Err(anyhow::anyhow!("No job of this kind is registered")),
+    };
+    match outcome {
+        Ok(JobOutcome::Completed) => {
+            delete_job(tx, job.job_id).await?;
+            Ok(ExecutionOutcome::TaskCompleted)
+        }
+        Ok(JobOutcome::Deferred(run_after)) => {
+            defer_job(tx, job.job_id, run_after).await?;
+            Ok(ExecutionOutcome::TaskDeferred)
+        }
+        Err(e) => {
+            let n_attempts = job.n_attempts + 1;
+            if n_attempts >= job.max_attempts {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "A job failed for the last time, setting it aside"
+                );
+                fail_job(tx, job.job_id, None, &e).await?;
+            } else {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "A job failed, it will be retried"
+                );
+                let run_after = Utc::now() + settings.retry_delay(n_attempts as u32);
+                fail_job(tx, job.job_id, Some(run_after), &e).await?;
+            }
+            Ok(ExecutionOutcome::TaskCompleted)
+        }
+    }
+}
+
+/// The job stays locked until its outcome is recorded, so that no other worker
+/// picks it up meanwhile. It is up for grabs again if this one dies.
+#[tracing::instrument(skip_all)]
+async fn dequeue_job(
+    pool: &PgPool,
+    queues: &[String],
+) -> Result<Option<(PgTx, QueuedJob)>, anyhow::Error> {
+    let mut tx = pool.begin().await?;
+
+    let r = sqlx::query_as!(
+        QueuedJob,
+        r#"
+        select
+            job_id,
+            kind,
+            payload::text as "payload!",
+            trace_context::text as "trace_context!",
+            n_attempts,
+            max_attempts
+        from jobs
+        where queue = any($1)
+        and failed_at is null
+        and run_after <= now()
+        order by priority desc, run_after
+        for update
+        skip locked
+        limit 1
+        "#,
+        queues
+    )
+    .fetch_optional(tx.as_mut())
+    .await?;
+
+    Ok(r.map(|r| (tx, r)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_job(mut tx: PgTx, job_id: Uuid) -> Result<(), anyhow::Error> {
+    sqlx::query!("delete from jobs where job_id = $1", job_id)
+        .execute(tx.as_mut())
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn defer_job(
+    mut tx: PgTx,
+    job_id: Uuid,
+    run_after: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "update jobs set run_after = $2 where job_id = $1",
+        job_id,
+        run_after
+    )
+    .execute(tx.as_mut())
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Schedule a retry at `retry_after`, or set the job aside for good.
+#[tracing::instrument(skip_all)]
+async fn fail_job(
+    mut tx: PgTx,
+    job_id: Uuid,
+    retry_after: Option<DateTime<Utc>>,
+    error: &anyhow::Error,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        update jobs
+        set
+            n_attempts = n_attempts + 1,
+            run_after = coalesce($2, run_after),
+            failed_at = case when $2 is null then now() end,
+            last_error = $3
+        where job_id = $1
+        "#,
+        job_id,
+        retry_after,
+        format!("{error:#}")
+    )
+    .execute(tx.as_mut())
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn worker_loop(
+    registry: &JobRegistry,
+    recurring_jobs: &[RecurringJob],
+    context: &JobContext<'_>,
+    settings: &JobsSettings,
+) -> Result<(), anyhow::Error> {
+    let mut wakeup = Wakeup::listen(context.pool, JOBS_CHANNEL).await;
+    let mut heartbeat = Heartbeat::new(JOBS_WORKER);
+    let mut next_scheduling_at = Instant::now();
+    loop {
+        heartbeat.beat(context.pool).await;
+        // Keep recurring jobs coming even while the queue is never empty.
+        if Instant::now() >= next_scheduling_at {
+            if let Err(e) = enqueue_due_recurring_jobs(context.pool, recurring_jobs).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to queue the recurring jobs that are due"
+                )
+            }
+            next_scheduling_at = Instant::now() + settings.poll_interval();
+        }
+        match try_execute_job(registry, context, settings).await {
+            Ok(ExecutionOutcome::TaskCompleted | ExecutionOutcome::TaskDeferred) => {}
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                wakeup.wait(settings.poll_interval()).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(settings.poll_interval()).await;
+            }
+        }
+    }
+}
+
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email.clone().client();
+    let rate_limiter = configuration.delivery.rate_limiter();
+    let feed_client = configuration.feed_import.client();
+    let recurring_jobs =
+        recurring_jobs(&configuration).context("Invalid recurring job schedule")?;
+    let context = JobContext {
+        pool: &pool,
+        email_client: &email_client,
+        rate_limiter: &rate_limiter,
+        idempotency: &configuration.idempotency,
+        feed_import: &configuration.feed_import,
+        feed_client: &feed_client,
+    };
+
+    worker_loop(
+        &job_registry(),
+        &recurring_jobs,
+        &context,
+        &configuration.jobs,
+    )
+    .await
+}
@@ -1,11 +1,21 @@
+pub mod audit;
 pub mod authentication;
+pub mod autoresponder;
+pub mod client_ip;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod feed_importer;
+pub mod health;
 pub mod idempotency;
-pub mod issue_delivery_worker;
+pub mod issue_delivery;
+pub mod jobs;
+pub mod metrics;
+pub mod outbox;
 pub mod routes;
+pub mod send_rate_limit;
 pub mod session_state;
 pub mod startup;
 pub mod telemetry;
 pub mod utils;
+pub mod wakeup;
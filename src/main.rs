@@ -4,9 +4,10 @@ use anyhow::Result;
 use tokio::task::JoinError;
 use zero2prod::{
     configuration::get_configuration,
-    issue_delivery_worker::run_worker_until_stopped,
+    jobs::run_worker_until_stopped,
+    outbox::run_relay_until_stopped,
     startup::Application,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, init_subscriber, shutdown_tracer_provider},
 };
 
 #[tokio::main]
@@ -18,12 +19,15 @@ async fn main() -> Result<()> {
     let application = Application::build(configuration.clone()).await?;
 
     let application_task = tokio::spawn(application.run_until_stopped());
-    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration.clone()));
+    let outbox_relay_task = tokio::spawn(run_relay_until_stopped(configuration));
 
     tokio::select! {
         o = application_task => report_exit("API", o),
         o = worker_task => report_exit("Background worker", o),
+        o = outbox_relay_task => report_exit("Outbox relay", o),
     }
+    shutdown_tracer_provider();
     Ok(())
 }
 
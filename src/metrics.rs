@@ -0,0 +1,225 @@
+//! Prometheus metrics, scraped from `/metrics`.
+//!
+//! Counters and histograms are updated as things happen. Gauges about the
+//! database pool and the job queues are read afresh on every scrape.
+
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+};
+use actix_web_lab::middleware::Next;
+use once_cell::sync::Lazy;
+use prometheus::{
+    core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::PgPool;
+
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    emails_sent: IntCounterVec,
+    email_send_duration: HistogramVec,
+    logins: IntCounterVec,
+    idempotency_records_pruned: IntCounter,
+}
+
+static METRICS: Lazy<Metrics> = Lazy::new(|| {
+    let metrics = Metrics {
+        registry: Registry::new(),
+        http_requests: IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests served"),
+            &["method", "route", "status"],
+        )
+        .unwrap(),
+        http_request_duration: HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to serve HTTP requests",
+            ),
+            &["method", "route"],
+        )
+        .unwrap(),
+        emails_sent: IntCounterVec::new(
+            Opts::new("emails_sent_total", "Emails handed to the email API"),
+            &["outcome"],
+        )
+        .unwrap(),
+        email_send_duration: HistogramVec::new(
+            HistogramOpts::new(
+                "email_send_duration_seconds",
+                "Time taken by the email API to accept an email",
+            ),
+            &["outcome"],
+        )
+        .unwrap(),
+        logins: IntCounterVec::new(
+            Opts::new("logins_total", "Admin login attempts"),
+            &["method", "outcome"],
+        )
+        .unwrap(),
+        idempotency_records_pruned: IntCounter::new(
+            "idempotency_records_pruned_total",
+            "Expired idempotency records deleted",
+        )
+        .unwrap(),
+    };
+    let collectors: [Box<dyn Collector>; 6] = [
+        Box::new(metrics.http_requests.clone()),
+        Box::new(metrics.http_request_duration.clone()),
+        Box::new(metrics.emails_sent.clone()),
+        Box::new(metrics.email_send_duration.clone()),
+        Box::new(metrics.logins.clone()),
+        Box::new(metrics.idempotency_records_pruned.clone()),
+    ];
+    for collector in collectors {
+        metrics.registry.register(collector).unwrap();
+    }
+    metrics
+});
+
+/// How an admin logged in, or tried to.
+#[derive(Copy, Clone, Debug)]
+pub enum LoginMethod {
+    Password,
+    SecondFactor,
+    SingleSignOn,
+}
+
+impl LoginMethod {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LoginMethod::Password => "password",
+            LoginMethod::SecondFactor => "second_factor",
+            LoginMethod::SingleSignOn => "sso",
+        }
+    }
+}
+
+fn outcome(succeeded: bool) -> &'static str {
+    if succeeded {
+        "success"
+    } else {
+        "failure"
+    }
+}
+
+pub fn record_login(method: LoginMethod, succeeded: bool) {
+    METRICS
+        .logins
+        .with_label_values(&[method.as_str(), outcome(succeeded)])
+        .inc();
+}
+
+pub fn record_email_sent(elapsed: Duration, succeeded: bool) {
+    let outcome = outcome(succeeded);
+    METRICS.emails_sent.with_label_values(&[outcome]).inc();
+    METRICS
+        .email_send_duration
+        .with_label_values(&[outcome])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn record_idempotency_records_pruned(n_pruned: u64) {
+    METRICS.idempotency_records_pruned.inc_by(n_pruned);
+}
+
+/// Count requests and time them, per route rather than per path so that the
+/// number of series stays bounded.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".into());
+
+    let response = next.call(req).await;
+
+    let status = match &response {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    METRICS
+        .http_requests
+        .with_label_values(&[&method, &route, status.as_str()])
+        .inc();
+    METRICS
+        .http_request_duration
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
+
+/// All metrics, in the Prometheus text format.
+#[tracing::instrument(name = "Gather metrics", skip(pool))]
+pub async fn gather(pool: &PgPool) -> Result<String, anyhow::Error> {
+    let mut families = METRICS.registry.gather();
+    families.extend(pool_metrics(pool)?.gather());
+    // The other metrics are still worth having while the database is down.
+    match job_metrics(pool).await {
+        Ok(registry) => families.extend(registry.gather()),
+        Err(e) => tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to gather the job metrics"
+        ),
+    }
+
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&families, &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+fn pool_metrics(pool: &PgPool) -> Result<Registry, prometheus::Error> {
+    let registry = Registry::new();
+    let connections = IntGaugeVec::new(
+        Opts::new("db_pool_connections", "Open database connections"),
+        &["state"],
+    )?;
+    let idle = pool.num_idle() as i64;
+    connections.with_label_values(&["idle"]).set(idle);
+    connections
+        .with_label_values(&["in_use"])
+        .set(pool.size() as i64 - idle);
+    registry.register(Box::new(connections))?;
+
+    let max_connections = IntGauge::new(
+        "db_pool_max_connections",
+        "How many database connections the pool may open",
+    )?;
+    max_connections.set(pool.options().get_max_connections() as i64);
+    registry.register(Box::new(max_connections))?;
+    Ok(registry)
+}
+
+async fn job_metrics(pool: &PgPool) -> Result<Registry, anyhow::Error> {
+    let counts = sqlx::query!(
+        r#"
+        select
+            queue,
+            kind,
+            failed_at is not null as "failed!",
+            count(*) as "n!"
+        from jobs
+        group by 1, 2, 3
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let registry = Registry::new();
+    let jobs = IntGaugeVec::new(
+        Opts::new("background_jobs", "Queued background jobs"),
+        &["queue", "kind", "state"],
+    )?;
+    for r in counts {
+        let state = if r.failed { "failed" } else { "pending" };
+        jobs.with_label_values(&[&r.queue, &r.kind, state]).set(r.n);
+    }
+    registry.register(Box::new(jobs))?;
+    Ok(registry)
+}
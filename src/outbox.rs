@@ -0,0 +1,251 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{OutboxSettings, Settings},
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    health::{Heartbeat, OUTBOX_RELAY},
+    jobs::ExecutionOutcome,
+    send_rate_limit::SendRateLimiter,
+    startup::get_connection_pool,
+    wakeup::{self, Wakeup, OUTBOX_CHANNEL},
+};
+
+type PgTx = Transaction<'static, Postgres>;
+
+/// Something to tell another system once the transaction that decided it has
+/// committed.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OutboxMessage {
+    Email {
+        recipient: String,
+        subject: String,
+        html_content: String,
+        text_content: String,
+    },
+}
+
+/// Queue `message` for delivery, as part of `transaction`.
+#[tracing::instrument(skip_all)]
+pub async fn enqueue(
+    transaction: &mut Transaction<'_, Postgres>,
+    message: &OutboxMessage,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        insert into outbox (message_id, payload, execute_after, created_at)
+        values ($1, $2::text::jsonb, now(), now())
+        "#,
+        Uuid::new_v4(),
+        serde_json::to_string(message)?
+    )
+    .execute(transaction.as_mut())
+    .await?;
+    wakeup::notify(transaction, OUTBOX_CHANNEL).await?;
+    Ok(())
+}
+
+struct QueuedMessage {
+    message_id: Uuid,
+    payload: String,
+    n_attempts: i32,
+}
+
+#[tracing::instrument(skip_all, fields(
+    message_id=tracing::field::Empty,
+    n_attempts=tracing::field::Empty,
+))]
+pub async fn try_deliver_message(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    rate_limiter: &SendRateLimiter,
+    settings: &OutboxSettings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((tx, message)) = dequeue_message(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("message_id", display(message.message_id))
+        .record("n_attempts", message.n_attempts);
+
+    let outcome = match serde_json::from_str(&message.payload) {
+        Ok(payload) => deliver(pool, email_client, rate_limiter, payload).await,
+        Err(e) => Err(anyhow::Error::new(e).context("The message payload is unreadable")),
+    };
+    match outcome {
+        Ok(Delivery::Sent) => delete_message(tx, message.message_id).await?,
+        Ok(Delivery::Deferred(execute_after)) => {
+            defer_message(tx, message.message_id, execute_after).await?;
+            return Ok(ExecutionOutcome::TaskDeferred);
+        }
+        Err(e) => {
+            let n_attempts = message.n_attempts as u32 + 1;
+            if n_attempts >= settings.max_attempts {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to deliver an outbox message, giving up"
+                );
+                delete_message(tx, message.message_id).await?;
+            } else {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to deliver an outbox message, will retry"
+                );
+                let retry_delay = settings.retry_delay(n_attempts);
+                schedule_retry(tx, message.message_id, retry_delay, &e).await?;
+            }
+        }
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+enum Delivery {
+    Sent,
+    /// Not attempted, the send budget is used up until then.
+    Deferred(DateTime<Utc>),
+}
+
+async fn deliver(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    rate_limiter: &SendRateLimiter,
+    message: OutboxMessage,
+) -> Result<Delivery, anyhow::Error> {
+    match message {
+        OutboxMessage::Email {
+            recipient,
+            subject,
+            html_content,
+            text_content,
+        } => {
+            let recipient = SubscriberEmail::parse(recipient).map_err(anyhow::Error::msg)?;
+            if !rate_limiter.try_acquire(pool, &recipient.domain()).await? {
+                return Ok(Delivery::Deferred(SendRateLimiter::next_window()));
+            }
+            email_client
+                .send_email(&recipient, &subject, &html_content, &text_content)
+                .await?;
+        }
+    }
+    Ok(Delivery::Sent)
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_message(pool: &PgPool) -> Result<Option<(PgTx, QueuedMessage)>, anyhow::Error> {
+    let mut tx = pool.begin().await?;
+
+    let r = sqlx::query_as!(
+        QueuedMessage,
+        r#"
+        select message_id, payload::text as "payload!", n_attempts
+        from outbox
+        where execute_after <= now()
+        order by execute_after
+        for update
+        skip locked
+        limit 1
+        "#,
+    )
+    .fetch_optional(tx.as_mut())
+    .await?;
+
+    Ok(r.map(|r| (tx, r)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_message(mut tx: PgTx, message_id: Uuid) -> Result<(), anyhow::Error> {
+    sqlx::query!("delete from outbox where message_id = $1", message_id)
+        .execute(tx.as_mut())
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Put the message back in the queue, without counting an attempt.
+#[tracing::instrument(skip_all)]
+async fn defer_message(
+    mut tx: PgTx,
+    message_id: Uuid,
+    execute_after: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "update outbox set execute_after = $2 where message_id = $1",
+        message_id,
+        execute_after
+    )
+    .execute(tx.as_mut())
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn schedule_retry(
+    mut tx: PgTx,
+    message_id: Uuid,
+    retry_delay: std::time::Duration,
+    error: &anyhow::Error,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        update outbox
+        set
+            n_attempts = n_attempts + 1,
+            execute_after = now() + make_interval(secs => $2),
+            last_error = $3
+        where message_id = $1
+        "#,
+        message_id,
+        retry_delay.as_secs_f64(),
+        format!("{error:#}")
+    )
+    .execute(tx.as_mut())
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn relay_loop(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    rate_limiter: &SendRateLimiter,
+    settings: &OutboxSettings,
+) -> Result<(), anyhow::Error> {
+    let mut wakeup = Wakeup::listen(pool, OUTBOX_CHANNEL).await;
+    let mut heartbeat = Heartbeat::new(OUTBOX_RELAY);
+    loop {
+        heartbeat.beat(pool).await;
+        match try_deliver_message(pool, email_client, rate_limiter, settings).await {
+            Ok(ExecutionOutcome::TaskCompleted | ExecutionOutcome::TaskDeferred) => {}
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                wakeup.wait(settings.poll_interval()).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+            }
+        }
+    }
+}
+
+pub async fn run_relay_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email.client();
+    let rate_limiter = configuration.delivery.rate_limiter();
+
+    relay_loop(&pool, &email_client, &rate_limiter, &configuration.outbox).await
+}
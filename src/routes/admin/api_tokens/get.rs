@@ -0,0 +1,107 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use chrono::Utc;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{list_api_tokens, ApiScope, CsrfToken, Role, UserId},
+    utils::e500,
+};
+
+pub async fn api_tokens_list(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    let mut tokens_html = String::new();
+    for token in list_api_tokens(**user_id, &pool).await.map_err(e500)? {
+        let expires = match token.expires_at {
+            Some(expires_at) if expires_at <= Utc::now() => "expired".to_string(),
+            Some(expires_at) => expires_at.to_rfc3339(),
+            None => "never".to_string(),
+        };
+        writeln!(
+            tokens_html,
+            r#"<tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{expires}</td>
+            <td>{}</td>
+            <td>
+                <form action="/admin/api-tokens/{}/revoke" method="post">
+                    {csrf_field}
+                    <button type="submit">Revoke</button>
+                </form>
+            </td>
+        </tr>"#,
+            encode_minimal(&token.name),
+            token.scopes.join(", "),
+            token.created_at.to_rfc3339(),
+            token
+                .last_used_at
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_else(|| "never".into()),
+            token.token_id,
+        )
+        .unwrap();
+    }
+
+    // Only offer the scopes the user's role can make use of.
+    let mut scopes_html = String::new();
+    for scope in ApiScope::ALL {
+        if *role >= scope.required_role() {
+            writeln!(
+                scopes_html,
+                r#"<label><input type="checkbox" name="scope" value="{scope}"> {scope}</label>"#
+            )
+            .unwrap();
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API tokens</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Name</th><th>Scopes</th><th>Created</th><th>Expires</th><th>Last used</th><th></th></tr>
+        {tokens_html}
+    </table>
+    <form action="/admin/api-tokens" method="post">
+        {csrf_field}
+        <label>Name:
+            <input type="text" placeholder="e.g. CMS" name="name">
+        </label>
+        {scopes_html}
+        <label>Expires after:
+            <select name="expires_in_days">
+                <option value="30">30 days</option>
+                <option value="90">90 days</option>
+                <option value="365">a year</option>
+                <option value="">never</option>
+            </select>
+        </label>
+        <button type="submit">Create token</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
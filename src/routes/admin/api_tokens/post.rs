@@ -0,0 +1,152 @@
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{create_api_token, revoke_api_token, ApiScope, Role, UserId},
+    utils::{e500, see_other},
+};
+
+#[tracing::instrument(
+    name = "Create an API token",
+    skip(form, pool, user_id, role, request),
+    fields(user_id=%*user_id)
+)]
+pub async fn create_user_api_token(
+    form: web::Form<Vec<(String, String)>>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Read as pairs since the `scope` checkbox can be sent several times.
+    let mut name = String::new();
+    let mut expires_in_days = String::new();
+    let mut scopes = Vec::new();
+    for (key, value) in form.into_inner() {
+        match key.as_str() {
+            "name" => name = value,
+            "expires_in_days" => expires_in_days = value,
+            "scope" => match ApiScope::try_from(value) {
+                Ok(scope) => scopes.push(scope),
+                Err(e) => {
+                    FlashMessage::error(e.to_string()).send();
+                    return Ok(see_other("/admin/api-tokens"));
+                }
+            },
+            _ => {}
+        }
+    }
+
+    let name = name.trim();
+    if name.is_empty() {
+        FlashMessage::error("The token needs a name.").send();
+        return Ok(see_other("/admin/api-tokens"));
+    }
+    if scopes.is_empty() {
+        FlashMessage::error("The token needs at least one scope.").send();
+        return Ok(see_other("/admin/api-tokens"));
+    }
+    if let Some(scope) = scopes.iter().find(|s| *role < s.required_role()) {
+        FlashMessage::error(format!("Your role does not allow the {scope} scope.")).send();
+        return Ok(see_other("/admin/api-tokens"));
+    }
+    let expires_at = match expires_in_days.as_str() {
+        "" => None,
+        days => match days.parse::<u16>() {
+            Ok(days) => Some(Utc::now() + Duration::days(days.into())),
+            Err(_) => {
+                FlashMessage::error("The expiry is invalid.").send();
+                return Ok(see_other("/admin/api-tokens"));
+            }
+        },
+    };
+
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let token = create_api_token(**user_id, name, &scopes, expires_at, tx.as_mut())
+        .await
+        .map_err(e500)?;
+    let scope_list = scopes
+        .iter()
+        .map(ApiScope::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    AuditEvent::new(AuditAction::ApiTokenCreated, &request)
+        .actor(**user_id)
+        .target(name)
+        .details(format!("with the scopes {scope_list}"))
+        .record(tx.as_mut())
+        .await
+        .map_err(e500)?;
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to create an API token")
+        .map_err(e500)?;
+
+    // Not a redirect: the token is not stored anywhere we could show it from.
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>New API token</title>
+</head>
+<body>
+    <p>The API token {} has been created.</p>
+    <p>Copy it now, it will not be shown again:</p>
+    <p><code>{token}</code></p>
+    <p><a href="/admin/api-tokens">Done</a></p>
+</body>
+</html>"#,
+            encode_minimal(name),
+        )))
+}
+
+#[tracing::instrument(
+    name = "Revoke an API token",
+    skip(pool, user_id, request),
+    fields(user_id=%*user_id)
+)]
+pub async fn revoke_user_api_token(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let token_id = path.into_inner();
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    if revoke_api_token(**user_id, token_id, tx.as_mut())
+        .await
+        .map_err(e500)?
+    {
+        AuditEvent::new(AuditAction::ApiTokenRevoked, &request)
+            .actor(**user_id)
+            .target(token_id)
+            .record(tx.as_mut())
+            .await
+            .map_err(e500)?;
+        tx.commit()
+            .await
+            .context("Failed to commit SQL transaction to revoke an API token")
+            .map_err(e500)?;
+        FlashMessage::info("The API token has been revoked.").send();
+    } else {
+        FlashMessage::error("The API token does not exist.").send();
+    }
+    Ok(see_other("/admin/api-tokens"))
+}
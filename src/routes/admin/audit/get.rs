@@ -0,0 +1,210 @@
+use actix_web::{
+    http::header::{ContentDisposition, ContentType},
+    web, HttpResponse,
+};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    audit::{search_audit_log, AuditAction, AuditEntry, AuditFilter},
+    utils::e500,
+};
+
+/// The page only shows the most recent entries, the export has all of them.
+const MAX_ENTRIES_ON_PAGE: i64 = 200;
+
+#[derive(serde::Deserialize)]
+pub struct AuditQuery {
+    #[serde(default)]
+    action: String,
+    #[serde(default)]
+    actor: String,
+    /// `YYYY-MM-DD`, from the start of the day.
+    #[serde(default)]
+    since: String,
+    /// `YYYY-MM-DD`, up to the end of the day.
+    #[serde(default)]
+    until: String,
+}
+
+impl AuditQuery {
+    fn to_filter(&self) -> Result<AuditFilter, String> {
+        let since = parse_day(&self.since)?;
+        let until = parse_day(&self.until)?.map(|day| day + Days::new(1));
+        Ok(AuditFilter {
+            action: non_empty(&self.action),
+            actor: non_empty(&self.actor),
+            since,
+            until,
+            limit: None,
+        })
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_owned())
+}
+
+fn parse_day(value: &str) -> Result<Option<DateTime<Utc>>, String> {
+    let Some(value) = non_empty(value) else {
+        return Ok(None);
+    };
+    let day = NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+        .map_err(|_| format!("{value} is not a valid date, use YYYY-MM-DD."))?;
+    Ok(Some(day.and_hms_opt(0, 0, 0).unwrap().and_utc()))
+}
+
+pub async fn audit_log_page(
+    query: web::Query<AuditQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (msg_html, entries) = match query.to_filter() {
+        Ok(filter) => {
+            let filter = AuditFilter {
+                limit: Some(MAX_ENTRIES_ON_PAGE),
+                ..filter
+            };
+            let entries = search_audit_log(&filter, &pool).await.map_err(e500)?;
+            (String::new(), entries)
+        }
+        Err(e) => (format!("<p><i>{}</i></p>", encode_minimal(&e)), Vec::new()),
+    };
+
+    let mut entries_html = String::new();
+    for entry in &entries {
+        writeln!(
+            entries_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            entry.occurred_at.to_rfc3339(),
+            encode_minimal(entry.actor.as_deref().unwrap_or("")),
+            encode_minimal(entry.ip_address.as_deref().unwrap_or("")),
+            entry.action,
+            encode_minimal(entry.target.as_deref().unwrap_or("")),
+            encode_minimal(&entry.details),
+        )
+        .unwrap();
+    }
+
+    let action_options = action_options(&query.action);
+    let export_query = serde_urlencoded::to_string([
+        ("action", &query.action),
+        ("actor", &query.actor),
+        ("since", &query.since),
+        ("until", &query.until),
+    ])
+    .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Audit log</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/audit" method="get">
+        <label>Action:
+            <select name="action">{action_options}</select>
+        </label>
+        <label>User:
+            <input type="text" placeholder="Any user" name="actor" value="{}">
+        </label>
+        <label>From:
+            <input type="date" name="since" value="{}">
+        </label>
+        <label>To:
+            <input type="date" name="until" value="{}">
+        </label>
+        <button type="submit">Filter</button>
+    </form>
+    <p><a href="/admin/audit/export?{}">Export as CSV</a></p>
+    <table>
+        <tr><th>When</th><th>User</th><th>IP address</th><th>Action</th><th>Target</th><th>Details</th></tr>
+        {entries_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            encode_minimal(&query.actor),
+            encode_minimal(&query.since),
+            encode_minimal(&query.until),
+            encode_minimal(&export_query),
+        )))
+}
+
+/// Every group of actions, followed by every single action.
+fn action_options(selected: &str) -> String {
+    let mut groups: Vec<&str> = Vec::new();
+    for action in AuditAction::ALL {
+        let group = action.as_str().split('.').next().unwrap();
+        if !groups.contains(&group) {
+            groups.push(group);
+        }
+    }
+    let mut html = String::from(r#"<option value="">any</option>"#);
+    for value in groups
+        .into_iter()
+        .chain(AuditAction::ALL.iter().map(AuditAction::as_str))
+    {
+        let selected = if value == selected { " selected" } else { "" };
+        write!(
+            html,
+            r#"<option value="{value}"{selected}>{value}</option>"#
+        )
+        .unwrap();
+    }
+    html
+}
+
+pub async fn export_audit_log(
+    query: web::Query<AuditQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = match query.to_filter() {
+        Ok(filter) => filter,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e)),
+    };
+    let entries = search_audit_log(&filter, &pool).await.map_err(e500)?;
+
+    let mut csv = String::from("occurred_at,actor,ip_address,action,target,details\r\n");
+    for entry in &entries {
+        write_csv_row(&mut csv, entry);
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition::attachment("audit-log.csv"))
+        .body(csv))
+}
+
+fn write_csv_row(csv: &mut String, entry: &AuditEntry) {
+    let fields = [
+        entry.occurred_at.to_rfc3339(),
+        entry.actor.clone().unwrap_or_default(),
+        entry.ip_address.clone().unwrap_or_default(),
+        entry.action.clone(),
+        entry.target.clone().unwrap_or_default(),
+        entry.details.clone(),
+    ];
+    let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+    write!(csv, "{}\r\n", fields.join(",")).unwrap();
+}
+
+/// Quote fields as RFC 4180 asks, and keep spreadsheets from evaluating
+/// anything that looks like a formula.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{value}")
+    } else {
+        value.to_owned()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
@@ -0,0 +1,131 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{authentication::CsrfToken, utils::e500};
+
+struct SequenceStep {
+    sequence_id: Uuid,
+    sequence_name: String,
+    delay_days: Option<i32>,
+    title: Option<String>,
+}
+
+pub async fn autoresponders_form(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    let steps = get_sequence_steps(&pool).await.map_err(e500)?;
+
+    let mut sequences_html = String::new();
+    for (i, step) in steps.iter().enumerate() {
+        let is_first_step = i == 0 || steps[i - 1].sequence_id != step.sequence_id;
+        let is_last_step = i + 1 == steps.len() || steps[i + 1].sequence_id != step.sequence_id;
+
+        if is_first_step {
+            writeln!(
+                sequences_html,
+                "<h3>{}</h3>\n<ul>",
+                encode_minimal(&step.sequence_name)
+            )
+            .unwrap();
+        }
+        if let (Some(delay_days), Some(title)) = (step.delay_days, &step.title) {
+            writeln!(
+                sequences_html,
+                "<li>Day {delay_days}: {}</li>",
+                encode_minimal(title)
+            )
+            .unwrap();
+        }
+        if is_last_step {
+            writeln!(
+                sequences_html,
+                r#"</ul>
+<form action="/admin/autoresponders/steps" method="post">
+    {csrf_field}
+    <input hidden type="text" name="sequence_id" value="{}">
+    <label>Send after (days): <input type="number" min="0" name="delay_days" value="0"></label>
+    <br>
+    <label>Title: <input type="text" name="title"></label>
+    <br>
+    <label>Plain text content:<br>
+        <textarea name="text_content" rows="10" cols="50"></textarea>
+    </label>
+    <br>
+    <label>HTML content:<br>
+        <textarea name="html_content" rows="10" cols="50"></textarea>
+    </label>
+    <br>
+    <button type="submit">Add step</button>
+</form>"#,
+                step.sequence_id
+            )
+            .unwrap();
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Autoresponders</title>
+</head>
+<body>
+    {msg_html}
+    <p>Newly confirmed subscribers are enrolled in every sequence below.</p>
+    {sequences_html}
+    <h3>New sequence</h3>
+    <form action="/admin/autoresponders" method="post">
+        {csrf_field}
+        <label>Name:<br>
+            <input
+                type="text"
+                placeholder="Enter the sequence name"
+                name="name"
+            >
+        </label>
+        <br>
+        <button type="submit">Create sequence</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Get autoresponder sequences", skip(pool))]
+async fn get_sequence_steps(pool: &PgPool) -> Result<Vec<SequenceStep>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        SequenceStep,
+        r#"
+        select
+            sq.sequence_id,
+            sq.name as sequence_name,
+            st.delay_days as "delay_days?",
+            st.title as "title?"
+        from autoresponder_sequences sq
+        left join autoresponder_steps st on st.sequence_id = sq.sequence_id
+        order by sq.created_at, sq.sequence_id, st.delay_days, st.created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve autoresponder sequences")?;
+
+    Ok(rows)
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::autoresponders_form;
+pub use post::{create_autoresponder_sequence, create_autoresponder_step};
@@ -0,0 +1,107 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::{anyhow, Context};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::{e400, e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct SequenceFormData {
+    name: String,
+}
+
+#[tracing::instrument(name = "Create an autoresponder sequence", skip(form, pool))]
+pub async fn create_autoresponder_sequence(
+    form: web::Form<SequenceFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.0.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("The sequence name cannot be empty.").send();
+        return Ok(see_other("/admin/autoresponders"));
+    }
+
+    sqlx::query!(
+        r#"
+        insert into autoresponder_sequences (sequence_id, name, created_at)
+        values ($1, $2, now())
+        "#,
+        Uuid::new_v4(),
+        name
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to store autoresponder sequence")
+    .map_err(e500)?;
+
+    FlashMessage::info("The autoresponder sequence has been created.").send();
+    Ok(see_other("/admin/autoresponders"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct StepFormData {
+    sequence_id: Uuid,
+    delay_days: i32,
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(
+    name = "Add an autoresponder step",
+    skip(form, pool),
+    fields(sequence_id=%form.sequence_id)
+)]
+pub async fn create_autoresponder_step(
+    form: web::Form<StepFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let StepFormData {
+        sequence_id,
+        delay_days,
+        title,
+        text_content,
+        html_content,
+    } = form.0;
+
+    if delay_days < 0 {
+        FlashMessage::error("A step cannot be sent before the subscriber confirms.").send();
+        return Ok(see_other("/admin/autoresponders"));
+    }
+
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        insert into autoresponder_steps (
+            step_id,
+            sequence_id,
+            delay_days,
+            title,
+            text_content,
+            html_content,
+            created_at
+        )
+        select $1, sequence_id, $3, $4, $5, $6, now()
+        from autoresponder_sequences
+        where sequence_id = $2
+        "#,
+        Uuid::new_v4(),
+        sequence_id,
+        delay_days,
+        title,
+        text_content,
+        html_content
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to store autoresponder step")
+    .map_err(e500)?
+    .rows_affected();
+
+    if n_inserted_rows == 0 {
+        return Err(e400(anyhow!("Unknown autoresponder sequence")));
+    }
+
+    FlashMessage::info("The autoresponder step has been added.").send();
+    Ok(see_other("/admin/autoresponders"))
+}
@@ -4,12 +4,15 @@ use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::{
+    authentication::{CsrfToken, Role},
     session_state::TypedSession,
     utils::{e500, see_other},
 };
 
 pub async fn admin_dashboard(
     session: TypedSession,
+    role: web::ReqData<Role>,
+    csrf_token: web::ReqData<CsrfToken>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let username = if let Some(user_id) = session.get_user_id().map_err(e500)? {
@@ -17,6 +20,14 @@ pub async fn admin_dashboard(
     } else {
         return Ok(see_other("/login"));
     };
+    let role = role.into_inner();
+    let users_link = if role == Role::Owner {
+        r#"<li><a href="/admin/users">Manage users</a></li>
+            <li><a href="/admin/audit">Audit log</a></li>"#
+    } else {
+        ""
+    };
+    let csrf_field = csrf_token.form_field();
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
         .body(format!(
@@ -29,12 +40,19 @@ pub async fn admin_dashboard(
         <title>Login</title>
     </head>
     <body>
-        <p>Welcome {username}!</p>
+        <p>Welcome {username}! You are signed in as {role}.</p>
         <p>Available actions:</p>
         <ol>
+            <li><a href="/admin/newsletters">Send a newsletter issue</a></li>
+            <li><a href="/admin/issues">Manage newsletter issues</a></li>
             <li><a href="/admin/password">Change password</a></li>
+            <li><a href="/admin/sessions">Active sessions</a></li>
+            <li><a href="/admin/api-tokens">API tokens</a></li>
+            <li><a href="/admin/autoresponders">Autoresponders</a></li>
+            {users_link}
             <li>
                 <form name="logoutForm" action="/admin/logout" method="post">
+                    {csrf_field}
                     <input type="submit" value="Logout" />
                 </form>
             </li>
@@ -0,0 +1,265 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{
+    audit::{search_audit_log, AuditFilter},
+    authentication::CsrfToken,
+    issue_delivery::DeliverIssue,
+    jobs::Job,
+    utils::{e500, see_other},
+};
+
+struct IssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: Option<DateTime<Utc>>,
+    pending_deliveries: i64,
+}
+
+pub async fn issues_list(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    let mut issues_html = String::new();
+    for issue in get_issues(&pool).await.map_err(e500)? {
+        let id = issue.newsletter_issue_id;
+        let status = match issue.published_at {
+            Some(published_at) => format!("published {}", published_at.to_rfc3339()),
+            None => "draft".to_string(),
+        };
+        let halt_form = if issue.pending_deliveries > 0 {
+            format!(
+                r#"<form action="/admin/issues/{id}/halt" method="post">
+                    {csrf_field}
+                <button type="submit">Halt sending ({} pending)</button>
+            </form>"#,
+                issue.pending_deliveries
+            )
+        } else {
+            String::new()
+        };
+        writeln!(
+            issues_html,
+            r#"<tr>
+            <td>{}</td>
+            <td>{status}</td>
+            <td>
+                <a href="/admin/issues/{id}/edit">Edit</a>
+                <a href="/admin/issues/{id}/delete">Delete</a>
+                {halt_form}
+            </td>
+        </tr>"#,
+            encode_minimal(&issue.title),
+        )
+        .unwrap();
+    }
+
+    let mut audit_html = String::new();
+    let filter = AuditFilter {
+        action: Some("issue".into()),
+        limit: Some(20),
+        ..Default::default()
+    };
+    for entry in search_audit_log(&filter, &pool).await.map_err(e500)? {
+        writeln!(
+            audit_html,
+            "<li>{} - {} {} issue {}: {}</li>",
+            entry.occurred_at.to_rfc3339(),
+            encode_minimal(entry.actor.as_deref().unwrap_or("unknown")),
+            entry.action.trim_start_matches("issue."),
+            entry.target.as_deref().unwrap_or_default(),
+            encode_minimal(&entry.details),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter Issues</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Title</th><th>Status</th><th>Actions</th></tr>
+        {issues_html}
+    </table>
+    <p>Recent changes:</p>
+    <ul>
+        {audit_html}
+    </ul>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+pub async fn edit_issue_form(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = path.into_inner();
+    let Some(issue) = get_issue(&pool, issue_id).await.map_err(e500)? else {
+        return Ok(see_other("/admin/issues"));
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    let title = encode_minimal(&issue.title);
+    let text_content = encode_minimal(&issue.text_content);
+    let html_content = encode_minimal(&issue.html_content);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Edit Newsletter Issue</title>
+</head>
+<body>
+    {msg_html}
+    <p>Changes apply to the web archive and to deliveries that are still pending.</p>
+    <form action="/admin/issues/{issue_id}/edit" method="post">
+        {csrf_field}
+        <label>Title:<br>
+            <input type="text" name="title" value="{title}">
+        </label>
+        <br>
+        <label>Plain text content:<br>
+            <textarea name="text_content" rows="20" cols="50">{text_content}</textarea>
+        </label>
+        <br>
+        <label>HTML content:<br>
+            <textarea name="html_content" rows="20" cols="50">{html_content}</textarea>
+        </label>
+        <br>
+        <button type="submit">Save</button>
+    </form>
+    <p><a href="/admin/issues">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+pub async fn delete_issue_form(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = path.into_inner();
+    let Some(issue) = get_issue(&pool, issue_id).await.map_err(e500)? else {
+        return Ok(see_other("/admin/issues"));
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    let title = encode_minimal(&issue.title);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Delete Newsletter Issue</title>
+</head>
+<body>
+    {msg_html}
+    <p>Delete "{title}"? Pending deliveries are cancelled and the issue disappears from the archive.</p>
+    <form action="/admin/issues/{issue_id}/delete" method="post">
+        {csrf_field}
+        <label>
+            <input type="checkbox" name="confirm" value="yes">
+            I understand that this cannot be undone
+        </label>
+        <br>
+        <button type="submit">Delete</button>
+    </form>
+    <p><a href="/admin/issues">Cancel</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+struct IssueContent {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(name = "Get newsletter issue", skip(pool))]
+async fn get_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueContent>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        IssueContent,
+        r#"
+        select title, text_content, html_content
+        from newsletter_issues
+        where newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve newsletter issue")?;
+
+    Ok(issue)
+}
+
+#[tracing::instrument(name = "Get newsletter issues", skip(pool))]
+async fn get_issues(pool: &PgPool) -> Result<Vec<IssueSummary>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        select
+            i.newsletter_issue_id,
+            i.title,
+            i.published_at,
+            (
+                select count(*) from jobs j
+                where j.kind = $1
+                and j.payload->>'newsletter_issue_id' = i.newsletter_issue_id::text
+                and j.failed_at is null
+            ) as "pending_deliveries!"
+        from newsletter_issues i
+        order by i.created_at desc
+        "#,
+        DeliverIssue::KIND
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve newsletter issues")?;
+
+    Ok(issues)
+}
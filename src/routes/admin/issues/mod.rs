@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::{delete_issue_form, edit_issue_form, issues_list};
+pub use post::{delete_issue, edit_issue, halt_issue_delivery};
@@ -0,0 +1,203 @@
+use actix_web::{
+    web::{self, ReqData},
+    HttpRequest, HttpResponse,
+};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::UserId,
+    issue_delivery::cancel_pending_deliveries,
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct EditFormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(
+    name = "Edit a newsletter issue",
+    skip(form, pool, user_id, request),
+    fields(user_id=%*user_id)
+)]
+pub async fn edit_issue(
+    path: web::Path<Uuid>,
+    form: web::Form<EditFormData>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = path.into_inner();
+    let EditFormData {
+        title,
+        text_content,
+        html_content,
+    } = form.0;
+    if title.trim().is_empty() {
+        FlashMessage::error("The title cannot be empty.").send();
+        return Ok(see_other(&format!("/admin/issues/{issue_id}/edit")));
+    }
+
+    let mut tx = begin(&pool).await?;
+    let n_updated = sqlx::query!(
+        r#"
+        update newsletter_issues
+        set
+            title = $2,
+            text_content = $3,
+            html_content = $4,
+            updated_at = now()
+        where newsletter_issue_id = $1
+        "#,
+        issue_id,
+        title,
+        text_content,
+        html_content
+    )
+    .execute(tx.as_mut())
+    .await
+    .context("Failed to update newsletter issue")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        FlashMessage::error("The newsletter issue does not exist.").send();
+        return Ok(see_other("/admin/issues"));
+    }
+
+    AuditEvent::new(AuditAction::IssueEdited, &request)
+        .actor(**user_id)
+        .target(issue_id)
+        .details(format!("title: {title}"))
+        .record(tx.as_mut())
+        .await
+        .map_err(e500)?;
+    commit(tx).await?;
+
+    FlashMessage::info("The newsletter issue has been updated.").send();
+    Ok(see_other("/admin/issues"))
+}
+
+#[tracing::instrument(
+    name = "Halt the delivery of a newsletter issue",
+    skip(pool, user_id, request),
+    fields(user_id=%*user_id)
+)]
+pub async fn halt_issue_delivery(
+    path: web::Path<Uuid>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = path.into_inner();
+
+    let mut tx = begin(&pool).await?;
+    let n_cancelled = cancel_pending_deliveries(&mut tx, issue_id)
+        .await
+        .map_err(e500)?;
+    AuditEvent::new(AuditAction::IssueHalted, &request)
+        .actor(**user_id)
+        .target(issue_id)
+        .details(format!("{n_cancelled} pending deliveries cancelled"))
+        .record(tx.as_mut())
+        .await
+        .map_err(e500)?;
+    commit(tx).await?;
+
+    FlashMessage::info(format!(
+        "Sending has been halted, {n_cancelled} pending deliveries were cancelled."
+    ))
+    .send();
+    Ok(see_other("/admin/issues"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct DeleteFormData {
+    confirm: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Delete a newsletter issue",
+    skip(form, pool, user_id, request),
+    fields(user_id=%*user_id)
+)]
+pub async fn delete_issue(
+    path: web::Path<Uuid>,
+    form: web::Form<DeleteFormData>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = path.into_inner();
+    if form.0.confirm.as_deref() != Some("yes") {
+        FlashMessage::error("Please confirm that you want to delete this issue.").send();
+        return Ok(see_other(&format!("/admin/issues/{issue_id}/delete")));
+    }
+
+    let mut tx = begin(&pool).await?;
+    let n_cancelled = cancel_pending_deliveries(&mut tx, issue_id)
+        .await
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        update feed_entries
+        set newsletter_issue_id = null
+        where newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .execute(tx.as_mut())
+    .await
+    .context("Failed to detach the feed entry of a newsletter issue")
+    .map_err(e500)?;
+    let deleted = sqlx::query!(
+        r#"
+        delete from newsletter_issues
+        where newsletter_issue_id = $1
+        returning title
+        "#,
+        issue_id
+    )
+    .fetch_optional(tx.as_mut())
+    .await
+    .context("Failed to delete newsletter issue")
+    .map_err(e500)?;
+    let Some(deleted) = deleted else {
+        FlashMessage::error("The newsletter issue does not exist.").send();
+        return Ok(see_other("/admin/issues"));
+    };
+
+    AuditEvent::new(AuditAction::IssueDeleted, &request)
+        .actor(**user_id)
+        .target(issue_id)
+        .details(format!(
+            "title: {}, {n_cancelled} pending deliveries cancelled",
+            deleted.title
+        ))
+        .record(tx.as_mut())
+        .await
+        .map_err(e500)?;
+    commit(tx).await?;
+
+    FlashMessage::info("The newsletter issue has been deleted.").send();
+    Ok(see_other("/admin/issues"))
+}
+
+async fn begin(pool: &PgPool) -> Result<Transaction<'static, Postgres>, actix_web::Error> {
+    pool.begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)
+}
+
+async fn commit(tx: Transaction<'static, Postgres>) -> Result<(), actix_web::Error> {
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction")
+        .map_err(e500)
+}
@@ -1,9 +1,21 @@
+mod api_tokens;
+mod audit;
+mod autoresponders;
 mod dashboard;
+mod issues;
 mod logout;
 mod newsletter;
 mod password;
+mod sessions;
+mod users;
 
-pub use dashboard::admin_dashboard;
+pub use api_tokens::*;
+pub use audit::*;
+pub use autoresponders::*;
+pub use dashboard::{admin_dashboard, get_username};
+pub use issues::*;
 pub use logout::log_out;
 pub use newsletter::*;
 pub use password::*;
+pub use sessions::*;
+pub use users::*;
@@ -1,15 +1,47 @@
 use actix_web::http::header::ContentType;
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
 use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{authentication::CsrfToken, utils::e500};
+
+struct Draft {
+    newsletter_issue_id: Uuid,
+    title: String,
+}
 
 pub async fn publish_newsletter_form(
+    pool: web::Data<PgPool>,
     flash_messages: IncomingFlashMessages,
+    csrf_token: web::ReqData<CsrfToken>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let mut msg_html = String::new();
     for m in flash_messages.iter() {
         writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
+    let csrf_field = csrf_token.form_field();
+
+    let mut drafts_html = String::new();
+    for draft in get_drafts(&pool).await.map_err(e500)? {
+        writeln!(
+            drafts_html,
+            r#"<li>
+            <form action="/admin/newsletters/drafts" method="post">
+                {csrf_field}
+                {}
+                <input hidden type="text" name="newsletter_issue_id" value="{}">
+                <button type="submit">Publish</button>
+            </form>
+        </li>"#,
+            encode_minimal(&draft.title),
+            draft.newsletter_issue_id
+        )
+        .unwrap();
+    }
 
     let idempotency_key = uuid::Uuid::new_v4().to_string();
     Ok(HttpResponse::Ok()
@@ -24,6 +56,7 @@ pub async fn publish_newsletter_form(
 <body>
     {msg_html}
     <form action="/admin/newsletters" method="post">
+        {csrf_field}
         <label>Title:<br>
             <input
                 type="text"
@@ -52,9 +85,32 @@ pub async fn publish_newsletter_form(
         <br>
         <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
         <button type="submit">Publish</button>
+        <button type="submit" formaction="/admin/newsletters/drafts/new">Save as draft</button>
     </form>
+    <p>Drafts:</p>
+    <ul>
+        {drafts_html}
+    </ul>
     <p><a href="/admin/dashboard">&lt;- Back</a></p>
 </body>
 </html>"#,
         )))
 }
+
+#[tracing::instrument(name = "Get draft newsletter issues", skip(pool))]
+async fn get_drafts(pool: &PgPool) -> Result<Vec<Draft>, anyhow::Error> {
+    let drafts = sqlx::query_as!(
+        Draft,
+        r#"
+        select newsletter_issue_id, title
+        from newsletter_issues
+        where published_at is null
+        order by created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve draft newsletter issues")?;
+
+    Ok(drafts)
+}
@@ -2,4 +2,7 @@ mod get;
 mod post;
 
 pub use get::publish_newsletter_form;
-pub use post::publish_newsletter;
+pub use post::{
+    insert_newsletter_issue, publish_draft, publish_newsletter, publish_newsletter_issue,
+    save_draft,
+};
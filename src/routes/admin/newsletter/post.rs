@@ -1,11 +1,13 @@
 use crate::{
+    audit::{AuditAction, AuditEvent},
     authentication::UserId,
-    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
-    utils::{e400, e500, see_other},
+    idempotency::{begin_transaction, commit_transaction, send_replayable_flash_message},
+    issue_delivery::enqueue_deliveries,
+    utils::{e500, see_other},
 };
 use actix_web::{
     web::{self, ReqData},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
 use actix_web_flash_messages::FlashMessage;
 use anyhow::Context;
@@ -17,62 +19,161 @@ pub struct FormData {
     title: String,
     text_content: String,
     html_content: String,
-    idempotency_key: String,
 }
 
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(form, pool, user_id),
+    skip(form, pool, user_id, request),
     fields(user_id=%*user_id)
 )]
 pub async fn publish_newsletter(
     form: web::Form<FormData>,
     user_id: ReqData<UserId>,
     pool: web::Data<PgPool>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     let FormData {
         title,
         text_content,
         html_content,
-        idempotency_key,
     } = form.0;
-    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
-    let success_message = FlashMessage::info("The newsletter issue has been published!");
-
-    let mut tx = match try_processing(&pool, &idempotency_key, *user_id)
+    let mut tx = begin_transaction(&request, &pool)
         .await
-        .map_err(e500)?
-    {
-        NextAction::StartProcessing(tx) => tx,
-        NextAction::ReturnSavedResponse(saved_response) => {
-            success_message.send();
-            return Ok(saved_response);
-        }
-    };
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
 
     let issue_id = insert_newsletter_issue(&mut tx, &title, &text_content, &html_content)
         .await
         .context("Failed to store newsletter issue details")
         .map_err(e500)?;
 
-    enqueue_delivery_tasks(&mut tx, issue_id)
+    publish_newsletter_issue(&mut tx, issue_id)
+        .await
+        .context("Failed to publish newsletter issue")
+        .map_err(e500)?;
+    AuditEvent::new(AuditAction::IssuePublished, &request)
+        .actor(*user_id)
+        .target(issue_id)
+        .details(format!("published \"{title}\" without a draft"))
+        .record(tx.as_mut())
+        .await
+        .map_err(e500)?;
+
+    commit_transaction(&request, tx)
+        .await
+        .context("Failed to commit SQL transaction to publish a newsletter issue")
+        .map_err(e500)?;
+
+    // Retries with the same idempotency key get the message again.
+    let mut response = see_other("/admin/newsletters");
+    send_replayable_flash_message(
+        &mut response,
+        FlashMessage::info("The newsletter issue has been published!"),
+    );
+    Ok(response)
+}
+
+#[derive(serde::Deserialize)]
+pub struct DraftFormData {
+    newsletter_issue_id: Uuid,
+}
+
+#[tracing::instrument(
+    name = "Publish a draft newsletter issue",
+    skip(form, pool, user_id, request),
+    fields(newsletter_issue_id=%form.newsletter_issue_id)
+)]
+pub async fn publish_draft(
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+    user_id: ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let published = publish_newsletter_issue(&mut tx, form.0.newsletter_issue_id)
         .await
-        .context("Failed to enqueue delivery tasks")
+        .context("Failed to publish newsletter issue")
         .map_err(e500)?;
+    if published {
+        AuditEvent::new(AuditAction::IssuePublished, &request)
+            .actor(**user_id)
+            .target(form.0.newsletter_issue_id)
+            .details("published a draft")
+            .record(tx.as_mut())
+            .await
+            .map_err(e500)?;
+    }
 
-    let response = see_other("/admin/newsletters");
-    let response = save_response(tx, &idempotency_key, *user_id, response)
+    tx.commit()
         .await
+        .context("Failed to commit SQL transaction to publish a draft")
         .map_err(e500)?;
 
-    success_message.send();
+    if published {
+        FlashMessage::info("The newsletter issue has been published!").send();
+    } else {
+        FlashMessage::error("This newsletter issue has already been published.").send();
+    }
+    Ok(see_other("/admin/newsletters"))
+}
 
+#[derive(serde::Deserialize)]
+pub struct NewDraftFormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(
+    name = "Save a draft newsletter issue",
+    skip(form, pool, user_id, request)
+)]
+pub async fn save_draft(
+    form: web::Form<NewDraftFormData>,
+    pool: web::Data<PgPool>,
+    user_id: ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut tx = begin_transaction(&request, &pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let issue_id =
+        insert_newsletter_issue(&mut tx, &form.title, &form.text_content, &form.html_content)
+            .await
+            .context("Failed to store newsletter issue details")
+            .map_err(e500)?;
+    AuditEvent::new(AuditAction::IssueDrafted, &request)
+        .actor(**user_id)
+        .target(issue_id)
+        .details(format!("drafted \"{}\"", form.title))
+        .record(tx.as_mut())
+        .await
+        .map_err(e500)?;
+
+    commit_transaction(&request, tx)
+        .await
+        .context("Failed to commit SQL transaction to save a draft")
+        .map_err(e500)?;
+
+    let mut response = see_other("/admin/newsletters");
+    send_replayable_flash_message(
+        &mut response,
+        FlashMessage::info("The newsletter issue has been saved as a draft."),
+    );
     Ok(response)
 }
 
+/// Store a new issue as a draft. It is not delivered until it gets published.
 #[tracing::instrument(skip_all)]
-async fn insert_newsletter_issue(
+pub async fn insert_newsletter_issue(
     tx: &mut Transaction<'_, Postgres>,
     title: &str,
     text_content: &str,
@@ -85,9 +186,8 @@ async fn insert_newsletter_issue(
             newsletter_issue_id,
             title,
             text_content,
-            html_content,
-            published_at
-        ) values ($1, $2, $3, $4, now())
+            html_content
+        ) values ($1, $2, $3, $4)
         "#,
         newsletter_issue_id,
         title,
@@ -100,23 +200,30 @@ async fn insert_newsletter_issue(
     Ok(newsletter_issue_id)
 }
 
-#[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
+/// Mark a draft as published and queue its delivery to every confirmed subscriber.
+///
+/// Returns `false`, without enqueuing anything, if the issue was already published.
+#[tracing::instrument(skip(tx))]
+pub async fn publish_newsletter_issue(
     tx: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<bool, anyhow::Error> {
+    let n_published = sqlx::query!(
         r#"
-        insert into issue_delivery_queue (
-            newsletter_issue_id,
-            subscriber_email
-        )
-        select $1, email from subscriptions
-        where status = 'confirmed'
+        update newsletter_issues
+        set published_at = now()
+        where newsletter_issue_id = $1
+        and published_at is null
         "#,
         newsletter_issue_id
     )
     .execute(tx.as_mut())
-    .await?;
-    Ok(())
+    .await?
+    .rows_affected();
+
+    if n_published == 0 {
+        return Ok(false);
+    }
+    enqueue_deliveries(tx, newsletter_issue_id).await?;
+    Ok(true)
 }
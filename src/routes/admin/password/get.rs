@@ -1,7 +1,13 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web::{http::header::ContentType, web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use qrcode::{render::svg, QrCode};
+use sqlx::PgPool;
+use uuid::Uuid;
 
 use crate::{
+    authentication::{get_totp_enrolment, CsrfToken},
+    routes::admin::dashboard::get_username,
     session_state::TypedSession,
     utils::{e500, see_other},
 };
@@ -10,11 +16,17 @@ use std::fmt::Write;
 
 pub async fn change_password_form(
     session: TypedSession,
+    pool: web::Data<PgPool>,
     flash_messages: IncomingFlashMessages,
+    csrf_token: web::ReqData<CsrfToken>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    if session.get_user_id().map_err(e500)?.is_none() {
+    let Some(user_id) = session.get_user_id().map_err(e500)? else {
         return Ok(see_other("/login"));
-    }
+    };
+    let csrf_field = csrf_token.form_field();
+    let two_factor_html = two_factor_section(user_id, &csrf_field, &pool)
+        .await
+        .map_err(e500)?;
 
     let mut msg_html = String::new();
     for m in flash_messages.iter() {
@@ -32,6 +44,7 @@ pub async fn change_password_form(
     <body>
         {msg_html}
         <form action="/admin/password" method="post">
+        {csrf_field}
         <label
             >Current Password
             <input type="password" placeholder="Enter current password" name="current_password"
@@ -43,9 +56,56 @@ pub async fn change_password_form(
         <br>
         <button type="submit">Change password</button>
         </form>
+        {two_factor_html}
         <p><a href="/admin/dashboard">&lt;- Back</a></p>
     </body>
 </html>
 "#),
       ))
 }
+
+async fn two_factor_section(
+    user_id: Uuid,
+    csrf_field: &str,
+    pool: &PgPool,
+) -> Result<String, anyhow::Error> {
+    let html = match get_totp_enrolment(user_id, pool).await? {
+        None => format!(
+            r#"<p>Two-factor authentication is off.</p>
+        <form action="/admin/password/totp" method="post">
+        {csrf_field}
+        <button type="submit">Set up two-factor authentication</button>
+        </form>"#
+        ),
+        Some(enrolment) if !enrolment.confirmed => {
+            let username = get_username(user_id, pool).await?;
+            let uri = enrolment.totp.provisioning_uri(&username);
+            let qr_code = QrCode::new(uri.as_bytes())?
+                .render::<svg::Color>()
+                .min_dimensions(200, 200)
+                .build();
+            // Inline SVG must not carry the XML declaration.
+            let qr_code = qr_code.trim_start_matches(r#"<?xml version="1.0" standalone="yes"?>"#);
+            format!(
+                r#"<p>Scan this code with your authenticator app, then enter the code it shows.</p>
+        {qr_code}
+        <p>Can't scan it? Use this link: <code>{}</code></p>
+        <form action="/admin/password/totp/confirm" method="post">
+        {csrf_field}
+        <label>Code <input type="text" autocomplete="one-time-code" name="code" /></label>
+        <button type="submit">Turn on two-factor authentication</button>
+        </form>"#,
+                encode_minimal(&uri)
+            )
+        }
+        Some(_) => format!(
+            r#"<p>Two-factor authentication is on.</p>
+        <form action="/admin/password/totp/disable" method="post">
+        {csrf_field}
+        <label>Current Password <input type="password" name="current_password" /></label>
+        <button type="submit">Turn off two-factor authentication</button>
+        </form>"#
+        ),
+    };
+    Ok(html)
+}
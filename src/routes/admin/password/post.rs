@@ -1,11 +1,19 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::PgPool;
+use std::fmt::Write;
 
 use crate::{
-    authentication::{validate_credentials, AuthError, Credentials, UserId},
+    audit::{AuditAction, AuditEvent},
+    authentication::{
+        check_new_password, confirm_totp_enrolment, disable_totp, revoke_other_sessions,
+        start_totp_enrolment, validate_credentials, AuthError, Credentials, UserId,
+    },
+    configuration::{PasswordHashingSettings, PasswordPolicySettings},
     routes::admin::dashboard::get_username,
+    session_state::TypedSession,
     utils::{e500, see_other},
 };
 
@@ -20,25 +28,36 @@ pub async fn change_password(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
     user_id: web::ReqData<UserId>,
+    password_policy: web::Data<PasswordPolicySettings>,
+    password_hashing: web::Data<PasswordHashingSettings>,
+    session: TypedSession,
+    request: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
 
-    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
-        FlashMessage::error(
-            "You entered two different new passwords - the field values must match.",
-        )
-        .send();
+    let mut violations = check_new_password(
+        &form.new_password,
+        &form.new_password_check,
+        &username,
+        &password_policy,
+    );
+    if form.new_password.expose_secret() == form.current_password.expose_secret() {
+        violations.push("The new password must be different from the current one.".to_string());
+    }
+    if !violations.is_empty() {
+        for violation in violations {
+            FlashMessage::error(violation).send();
+        }
         return Ok(see_other("/admin/password"));
     }
 
-    let username = get_username(*user_id, &pool).await.map_err(e500)?;
-
     let credentials = Credentials {
         username,
         password: form.0.current_password,
     };
 
-    if let Err(e) = validate_credentials(credentials, &pool).await {
+    if let Err(e) = validate_credentials(credentials, &password_hashing, &pool).await {
         return match e {
             AuthError::InvalidCredentials(_) => {
                 FlashMessage::error("The current password is incorrect.").send();
@@ -48,11 +67,162 @@ pub async fn change_password(
         };
     }
 
-    crate::authentication::change_password(*user_id, form.0.new_password, &pool)
+    let current_session_id = session
+        .get_session_id()
+        .map_err(e500)?
+        .context("The session id is missing from an authenticated session")
+        .map_err(e500)?;
+    let mut tx = pool
+        .begin()
         .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    crate::authentication::change_password(
+        *user_id,
+        form.0.new_password,
+        &password_hashing,
+        &mut tx,
+    )
+    .await
+    .map_err(e500)?;
+    // Whoever else might know the old password gets logged out.
+    let n_revoked = revoke_other_sessions(*user_id, current_session_id, tx.as_mut())
+        .await
+        .map_err(e500)?;
+    AuditEvent::new(AuditAction::PasswordChanged, &request)
+        .actor(*user_id)
+        .details(format!("{n_revoked} other sessions logged out"))
+        .record(tx.as_mut())
+        .await
+        .map_err(e500)?;
+    tx.commit()
+        .await
+        .context("Failed to commit SQL tx to change a password")
         .map_err(e500)?;
-
     FlashMessage::info("Your password has been changed.").send();
+    if n_revoked > 0 {
+        FlashMessage::info(format!("{n_revoked} other sessions have been logged out.")).send();
+    }
+
+    Ok(see_other("/admin/password"))
+}
+
+pub async fn enrol_totp(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    start_totp_enrolment(**user_id, &pool).await.map_err(e500)?;
+
+    Ok(see_other("/admin/password"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmTotpFormData {
+    code: String,
+}
+
+pub async fn confirm_totp(
+    form: web::Form<ConfirmTotpFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let Some(recovery_codes) = confirm_totp_enrolment(**user_id, &form.0.code, &mut tx)
+        .await
+        .map_err(e500)?
+    else {
+        FlashMessage::error("The code is invalid, please try again.").send();
+        return Ok(see_other("/admin/password"));
+    };
+    AuditEvent::new(AuditAction::TwoFactorEnabled, &request)
+        .actor(**user_id)
+        .record(tx.as_mut())
+        .await
+        .map_err(e500)?;
+    tx.commit()
+        .await
+        .context("Failed to commit SQL tx to turn on two-factor authentication")
+        .map_err(e500)?;
+
+    let mut codes_html = String::new();
+    for code in recovery_codes {
+        writeln!(codes_html, "<li><code>{code}</code></li>").unwrap();
+    }
+    // Not a redirect: the recovery codes are not stored anywhere we could show them from.
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html" charset="UTF-8" />
+        <meta name="viewport" content="width=device-width, initial-scale=1.0" />
+        <title>Recovery codes</title>
+    </head>
+    <body>
+        <p>Two-factor authentication is now on.</p>
+        <p>Keep these recovery codes somewhere safe. Each of them can be used once
+        to log in if you lose access to your authenticator app.</p>
+        <ul>
+            {codes_html}
+        </ul>
+        <p><a href="/admin/password">Done</a></p>
+    </body>
+</html>
+"#
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct DisableTotpFormData {
+    current_password: Secret<String>,
+}
+
+pub async fn turn_off_totp(
+    form: web::Form<DisableTotpFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    password_hashing: web::Data<PasswordHashingSettings>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: form.0.current_password,
+    };
+
+    if let Err(e) = validate_credentials(credentials, &password_hashing, &pool).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect.").send();
+                Ok(see_other("/admin/password"))
+            }
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    disable_totp(*user_id, &mut tx).await.map_err(e500)?;
+    AuditEvent::new(AuditAction::TwoFactorDisabled, &request)
+        .actor(*user_id)
+        .record(tx.as_mut())
+        .await
+        .map_err(e500)?;
+    tx.commit()
+        .await
+        .context("Failed to commit SQL tx to turn off two-factor authentication")
+        .map_err(e500)?;
 
+    FlashMessage::info("Two-factor authentication has been turned off.").send();
     Ok(see_other("/admin/password"))
 }
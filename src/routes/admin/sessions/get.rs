@@ -0,0 +1,80 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{list_sessions, CsrfToken, UserId},
+    session_state::TypedSession,
+    utils::e500,
+};
+
+pub async fn sessions_list(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    let mut sessions_html = String::new();
+    for s in list_sessions(**user_id, &pool).await.map_err(e500)? {
+        let action = if Some(s.session_id) == current_session_id {
+            "This session".to_string()
+        } else {
+            format!(
+                r#"<form action="/admin/sessions/{}/revoke" method="post">
+                    {csrf_field}
+                    <button type="submit">Log out</button>
+                </form>"#,
+                s.session_id
+            )
+        };
+        writeln!(
+            sessions_html,
+            r#"<tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{action}</td>
+        </tr>"#,
+            s.created_at.to_rfc3339(),
+            s.last_seen_at.to_rfc3339(),
+            encode_minimal(s.ip_address.as_deref().unwrap_or("unknown")),
+            encode_minimal(s.user_agent.as_deref().unwrap_or("unknown")),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Sessions</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Logged in</th><th>Last seen</th><th>IP address</th><th>Browser</th><th></th></tr>
+        {sessions_html}
+    </table>
+    <form action="/admin/sessions/revoke-others" method="post">
+        {csrf_field}
+        <button type="submit">Log out all other sessions</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
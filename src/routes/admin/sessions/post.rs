@@ -0,0 +1,58 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{revoke_other_sessions, revoke_session, UserId},
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+#[tracing::instrument(name = "Log out a session", skip(pool, session, user_id), fields(user_id=%*user_id))]
+pub async fn log_out_session(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let session_id = path.into_inner();
+    if Some(session_id) == session.get_session_id().map_err(e500)? {
+        FlashMessage::error("Use the logout button to log out of this session.").send();
+        return Ok(see_other("/admin/sessions"));
+    }
+
+    if revoke_session(**user_id, session_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info("The session has been logged out.").send();
+    } else {
+        FlashMessage::error("The session does not exist.").send();
+    }
+    Ok(see_other("/admin/sessions"))
+}
+
+#[tracing::instrument(
+    name = "Log out the other sessions",
+    skip(pool, session, user_id),
+    fields(user_id=%*user_id)
+)]
+pub async fn log_out_other_sessions(
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let current_session_id = session
+        .get_session_id()
+        .map_err(e500)?
+        .context("The session id is missing from an authenticated session")
+        .map_err(e500)?;
+    let n_revoked = revoke_other_sessions(**user_id, current_session_id, pool.as_ref())
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("{n_revoked} other sessions have been logged out.")).send();
+    Ok(see_other("/admin/sessions"))
+}
@@ -0,0 +1,169 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{CsrfToken, Role},
+    utils::e500,
+};
+
+struct User {
+    user_id: Uuid,
+    username: String,
+    email: Option<String>,
+    role: String,
+    disabled_at: Option<DateTime<Utc>>,
+}
+
+struct PendingInvitation {
+    email: String,
+    role: String,
+    expires_at: DateTime<Utc>,
+}
+
+pub async fn users_list(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: web::ReqData<CsrfToken>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let csrf_field = csrf_token.form_field();
+
+    let mut users_html = String::new();
+    for user in get_users(&pool).await.map_err(e500)? {
+        let id = user.user_id;
+        let (status, toggle) = match user.disabled_at {
+            Some(_) => ("disabled", "enable"),
+            None => ("active", "disable"),
+        };
+        writeln!(
+            users_html,
+            r#"<tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>
+                <form action="/admin/users/{id}/role" method="post">
+                    {csrf_field}
+                    <select name="role">{}</select>
+                    <button type="submit">Change role</button>
+                </form>
+            </td>
+            <td>{status}</td>
+            <td>
+                <form action="/admin/users/{id}/{toggle}" method="post">
+                    {csrf_field}
+                    <button type="submit">{toggle}</button>
+                </form>
+            </td>
+        </tr>"#,
+            encode_minimal(&user.username),
+            encode_minimal(user.email.as_deref().unwrap_or("")),
+            role_options(Some(&user.role)),
+        )
+        .unwrap();
+    }
+
+    let mut invitations_html = String::new();
+    for invitation in get_pending_invitations(&pool).await.map_err(e500)? {
+        writeln!(
+            invitations_html,
+            "<li>{} as {}, expires {}</li>",
+            encode_minimal(&invitation.email),
+            invitation.role,
+            invitation.expires_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+
+    let invite_role_options = role_options(None);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Users</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Username</th><th>Email</th><th>Role</th><th>Status</th><th></th></tr>
+        {users_html}
+    </table>
+    <p>Pending invitations:</p>
+    <ul>
+        {invitations_html}
+    </ul>
+    <form action="/admin/users/invitations" method="post">
+        {csrf_field}
+        <label>Email:
+            <input type="email" placeholder="Enter an email" name="email">
+        </label>
+        <label>Role:
+            <select name="role">{invite_role_options}</select>
+        </label>
+        <button type="submit">Invite</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+fn role_options(selected: Option<&str>) -> String {
+    let mut html = String::new();
+    for role in Role::ALL {
+        let role = role.as_str();
+        let selected = if Some(role) == selected {
+            " selected"
+        } else {
+            ""
+        };
+        write!(html, r#"<option value="{role}"{selected}>{role}</option>"#).unwrap();
+    }
+    html
+}
+
+#[tracing::instrument(name = "Get users", skip(pool))]
+async fn get_users(pool: &PgPool) -> Result<Vec<User>, anyhow::Error> {
+    let users = sqlx::query_as!(
+        User,
+        r#"
+        select user_id, username, email, role, disabled_at
+        from users
+        order by username
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve users")?;
+
+    Ok(users)
+}
+
+#[tracing::instrument(name = "Get pending invitations", skip(pool))]
+async fn get_pending_invitations(pool: &PgPool) -> Result<Vec<PendingInvitation>, anyhow::Error> {
+    let invitations = sqlx::query_as!(
+        PendingInvitation,
+        r#"
+        select email, role, expires_at
+        from user_invitations
+        where accepted_at is null and expires_at > now()
+        order by created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve pending invitations")?;
+
+    Ok(invitations)
+}
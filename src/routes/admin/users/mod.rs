@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::users_list;
+pub use post::{change_user_role, disable_user, enable_user, invite_user};
@@ -0,0 +1,274 @@
+use actix_web::{
+    web::{self, ReqData},
+    HttpRequest, HttpResponse,
+};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{generate_token, hash_token, Role, UserId},
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    startup::ApplicationBaseUrl,
+    utils::{e400, e500, see_other},
+};
+
+/// How long a signup link can be used for.
+const INVITATION_VALIDITY: chrono::Duration = chrono::Duration::days(7);
+
+#[derive(serde::Deserialize)]
+pub struct InvitationFormData {
+    email: String,
+    role: String,
+}
+
+#[tracing::instrument(
+    name = "Invite a user",
+    skip(form, pool, email_client, base_url, user_id, request),
+    fields(user_id=%*user_id)
+)]
+pub async fn invite_user(
+    form: web::Form<InvitationFormData>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let role: Role = form.0.role.try_into().map_err(e400)?;
+    let email = match SubscriberEmail::parse(form.0.email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+
+    let invitation_token = generate_token();
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        insert into user_invitations (
+            invitation_token_hash,
+            email,
+            role,
+            invited_by,
+            created_at,
+            expires_at
+        ) values ($1, $2, $3, $4, now(), $5)
+        "#,
+        hash_token(&invitation_token),
+        email.as_ref(),
+        role.as_str(),
+        **user_id,
+        chrono::Utc::now() + INVITATION_VALIDITY
+    )
+    .execute(tx.as_mut())
+    .await
+    .context("Failed to store the invitation")
+    .map_err(e500)?;
+    AuditEvent::new(AuditAction::UserInvited, &request)
+        .actor(**user_id)
+        .target(&email)
+        .details(format!("as {role}"))
+        .record(tx.as_mut())
+        .await
+        .map_err(e500)?;
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to invite a user")
+        .map_err(e500)?;
+
+    let signup_link = format!(
+        "{}/signup?invitation_token={}",
+        base_url.0, invitation_token
+    );
+    email_client
+        .send_email(
+            &email,
+            "You have been invited to manage the newsletter",
+            &format!(
+                "You have been invited to join the newsletter team as {role}.<br />\
+                Click <a href=\"{signup_link}\">here</a> to create your account."
+            ),
+            &format!(
+                "You have been invited to join the newsletter team as {role}.\n\
+                Visit {signup_link} to create your account."
+            ),
+        )
+        .await
+        .context("Failed to send the invitation email")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("An invitation has been sent to {email}.")).send();
+    Ok(see_other("/admin/users"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleFormData {
+    role: String,
+}
+
+#[tracing::instrument(
+    name = "Change the role of a user",
+    skip(form, pool, user_id, request),
+    fields(user_id=%*user_id)
+)]
+pub async fn change_user_role(
+    path: web::Path<Uuid>,
+    form: web::Form<RoleFormData>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_user_id = path.into_inner();
+    let role: Role = form.0.role.try_into().map_err(e400)?;
+    if target_user_id == **user_id {
+        FlashMessage::error("You cannot change your own role.").send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let username = sqlx::query_scalar!(
+        r#"
+        update users
+        set role = $2
+        where user_id = $1
+        returning username
+        "#,
+        target_user_id,
+        role.as_str()
+    )
+    .fetch_optional(tx.as_mut())
+    .await
+    .context("Failed to change the role of a user")
+    .map_err(e500)?;
+    if let Some(username) = username {
+        AuditEvent::new(AuditAction::UserRoleChanged, &request)
+            .actor(**user_id)
+            .target(username)
+            .details(format!("to {role}"))
+            .record(tx.as_mut())
+            .await
+            .map_err(e500)?;
+    }
+
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to change the role of a user")
+        .map_err(e500)?;
+
+    FlashMessage::info("The role has been changed.").send();
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(
+    name = "Disable a user",
+    skip(pool, user_id, request),
+    fields(user_id=%*user_id)
+)]
+pub async fn disable_user(
+    path: web::Path<Uuid>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_user_id = path.into_inner();
+    if target_user_id == **user_id {
+        FlashMessage::error("You cannot disable your own account.").send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let username = sqlx::query_scalar!(
+        r#"
+        update users
+        set disabled_at = now()
+        where user_id = $1 and disabled_at is null
+        returning username
+        "#,
+        target_user_id
+    )
+    .fetch_optional(tx.as_mut())
+    .await
+    .context("Failed to disable a user")
+    .map_err(e500)?;
+    if let Some(username) = username {
+        AuditEvent::new(AuditAction::UserDisabled, &request)
+            .actor(**user_id)
+            .target(username)
+            .record(tx.as_mut())
+            .await
+            .map_err(e500)?;
+    }
+
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to disable a user")
+        .map_err(e500)?;
+
+    FlashMessage::info("The account has been disabled.").send();
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(
+    name = "Enable a user",
+    skip(pool, user_id, request),
+    fields(user_id=%*user_id)
+)]
+pub async fn enable_user(
+    path: web::Path<Uuid>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let username = sqlx::query_scalar!(
+        r#"
+        update users
+        set disabled_at = null
+        where user_id = $1 and disabled_at is not null
+        returning username
+        "#,
+        path.into_inner()
+    )
+    .fetch_optional(tx.as_mut())
+    .await
+    .context("Failed to enable a user")
+    .map_err(e500)?;
+    if let Some(username) = username {
+        AuditEvent::new(AuditAction::UserEnabled, &request)
+            .actor(**user_id)
+            .target(username)
+            .record(tx.as_mut())
+            .await
+            .map_err(e500)?;
+    }
+
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to enable a user")
+        .map_err(e500)?;
+
+    FlashMessage::info("The account has been enabled.").send();
+    Ok(see_other("/admin/users"))
+}
@@ -0,0 +1,171 @@
+use actix_web::{
+    web::{self, ReqData},
+    HttpRequest, HttpResponse,
+};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::ApiError;
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::UserId,
+    idempotency::{begin_transaction, commit_transaction, IdempotencyKey},
+    routes::{insert_newsletter_issue, publish_newsletter_issue},
+};
+
+#[derive(serde::Deserialize)]
+pub struct NewIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+impl NewIssue {
+    fn validate(&self) -> Result<(), ApiError> {
+        if self.title.trim().is_empty() {
+            return Err(ApiError::ValidationError(
+                "The title cannot be empty.".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize)]
+struct IssueResponse {
+    newsletter_issue_id: Uuid,
+    published: bool,
+}
+
+#[tracing::instrument(
+    name = "Create a draft through the API",
+    skip(request, body, pool, user_id)
+)]
+pub async fn create_draft(
+    request: HttpRequest,
+    body: web::Json<NewIssue>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    body.validate()?;
+    let mut tx = begin_transaction(&request, &pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let newsletter_issue_id =
+        insert_newsletter_issue(&mut tx, &body.title, &body.text_content, &body.html_content)
+            .await
+            .context("Failed to store newsletter issue details")?;
+    AuditEvent::new(AuditAction::IssueDrafted, &request)
+        .actor(**user_id)
+        .target(newsletter_issue_id)
+        .details(format!("drafted \"{}\" through the API", body.title))
+        .record(tx.as_mut())
+        .await?;
+    commit_transaction(&request, tx)
+        .await
+        .context("Failed to commit SQL transaction to save a draft")?;
+
+    Ok(HttpResponse::Created().json(IssueResponse {
+        newsletter_issue_id,
+        published: false,
+    }))
+}
+
+#[tracing::instrument(name = "Publish a draft through the API", skip(request, pool, user_id))]
+pub async fn publish_draft(
+    request: HttpRequest,
+    path: web::Path<Uuid>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let newsletter_issue_id = path.into_inner();
+    let mut tx = begin_transaction(&request, &pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let published = publish_newsletter_issue(&mut tx, newsletter_issue_id)
+        .await
+        .context("Failed to publish newsletter issue")?;
+    if !published {
+        let exists = sqlx::query!(
+            "select 1 as exists from newsletter_issues where newsletter_issue_id = $1",
+            newsletter_issue_id
+        )
+        .fetch_optional(tx.as_mut())
+        .await
+        .context("Failed to look up a newsletter issue")?
+        .is_some();
+        return Err(if exists {
+            ApiError::Conflict("This newsletter issue has already been published.".into())
+        } else {
+            ApiError::NotFound("The newsletter issue does not exist.".into())
+        });
+    }
+    AuditEvent::new(AuditAction::IssuePublished, &request)
+        .actor(**user_id)
+        .target(newsletter_issue_id)
+        .details("published a draft through the API")
+        .record(tx.as_mut())
+        .await?;
+    commit_transaction(&request, tx)
+        .await
+        .context("Failed to commit SQL transaction to publish a draft")?;
+
+    Ok(HttpResponse::Ok().json(IssueResponse {
+        newsletter_issue_id,
+        published: true,
+    }))
+}
+
+/// Create and publish an issue in one go. Retries with the same `Idempotency-Key`
+/// header get the original response back instead of publishing it again, the
+/// header is required since there is no taking a publication back.
+#[tracing::instrument(
+    name = "Publish a newsletter issue through the API",
+    skip(request, body, pool, user_id, idempotency_key),
+    fields(user_id=%*user_id)
+)]
+pub async fn publish_issue(
+    request: HttpRequest,
+    body: web::Json<NewIssue>,
+    user_id: ReqData<UserId>,
+    idempotency_key: Option<ReqData<IdempotencyKey>>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    if idempotency_key.is_none() {
+        return Err(ApiError::ValidationError(
+            "The Idempotency-Key header is required.".into(),
+        ));
+    }
+    body.validate()?;
+
+    let mut tx = begin_transaction(&request, &pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let newsletter_issue_id =
+        insert_newsletter_issue(&mut tx, &body.title, &body.text_content, &body.html_content)
+            .await
+            .context("Failed to store newsletter issue details")?;
+    publish_newsletter_issue(&mut tx, newsletter_issue_id)
+        .await
+        .context("Failed to publish newsletter issue")?;
+    AuditEvent::new(AuditAction::IssuePublished, &request)
+        .actor(**user_id)
+        .target(newsletter_issue_id)
+        .details(format!(
+            "published \"{}\" without a draft through the API",
+            body.title
+        ))
+        .record(tx.as_mut())
+        .await?;
+
+    commit_transaction(&request, tx)
+        .await
+        .context("Failed to commit SQL transaction to publish a newsletter issue")?;
+
+    Ok(HttpResponse::Created().json(IssueResponse {
+        newsletter_issue_id,
+        published: true,
+    }))
+}
@@ -0,0 +1,29 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use super::ApiError;
+
+#[derive(serde::Serialize)]
+struct SubscriberCounts {
+    confirmed: i64,
+    pending_confirmation: i64,
+}
+
+#[tracing::instrument(name = "Count subscribers", skip(pool))]
+pub async fn subscriber_counts(pool: web::Data<PgPool>) -> Result<HttpResponse, ApiError> {
+    let counts = sqlx::query_as!(
+        SubscriberCounts,
+        r#"
+        select
+            count(*) filter (where status = 'confirmed') as "confirmed!",
+            count(*) filter (where status = 'pending_confirmation') as "pending_confirmation!"
+        from subscriptions
+        "#
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to count subscribers")?;
+
+    Ok(HttpResponse::Ok().json(counts))
+}
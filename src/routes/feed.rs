@@ -0,0 +1,188 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{startup::ApplicationBaseUrl, utils::e500};
+
+const FEED_TITLE: &str = "Newsletter";
+const FEED_LENGTH: i64 = 50;
+
+struct PublishedIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+pub async fn atom_feed(
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_published_issues(&pool).await.map_err(e500)?;
+    let base_url = &base_url.0;
+    let updated = last_updated_at(&issues).to_rfc3339();
+
+    let mut entries = String::new();
+    for issue in &issues {
+        writeln!(
+            entries,
+            r#"    <entry>
+        <id>urn:uuid:{}</id>
+        <title>{}</title>
+        <link rel="alternate" href="{base_url}/issues/{}" />
+        <published>{}</published>
+        <updated>{}</updated>
+        <content type="html">{}</content>
+    </entry>"#,
+            issue.newsletter_issue_id,
+            encode_minimal(&issue.title),
+            issue.newsletter_issue_id,
+            issue.published_at.to_rfc3339(),
+            issue.updated_at.to_rfc3339(),
+            encode_minimal(&issue.html_content),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+    <id>{base_url}/feed.atom</id>
+    <title>{FEED_TITLE}</title>
+    <updated>{updated}</updated>
+    <author>
+        <name>{FEED_TITLE}</name>
+    </author>
+    <link rel="self" href="{base_url}/feed.atom" />
+    <link rel="alternate" href="{base_url}/" />
+{entries}</feed>
+"#
+        )))
+}
+
+pub async fn rss_feed(
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_published_issues(&pool).await.map_err(e500)?;
+    let base_url = &base_url.0;
+    let updated = last_updated_at(&issues).to_rfc2822();
+
+    let mut items = String::new();
+    for issue in &issues {
+        writeln!(
+            items,
+            r#"        <item>
+            <guid isPermaLink="false">urn:uuid:{}</guid>
+            <title>{}</title>
+            <link>{base_url}/issues/{}</link>
+            <pubDate>{}</pubDate>
+            <description>{}</description>
+        </item>"#,
+            issue.newsletter_issue_id,
+            encode_minimal(&issue.title),
+            issue.newsletter_issue_id,
+            issue.published_at.to_rfc2822(),
+            encode_minimal(&issue.html_content),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/rss+xml; charset=utf-8")
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+    <channel>
+        <title>{FEED_TITLE}</title>
+        <link>{base_url}/</link>
+        <description>Published issues of our newsletter</description>
+        <lastBuildDate>{updated}</lastBuildDate>
+        <atom:link rel="self" type="application/rss+xml" href="{base_url}/feed.rss" />
+{items}    </channel>
+</rss>
+"#
+        )))
+}
+
+/// The web version of a published issue, which feed entries link to.
+pub async fn published_issue(
+    path: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = sqlx::query!(
+        r#"
+        select title, html_content
+        from newsletter_issues
+        where newsletter_issue_id = $1 and published_at is not null
+        "#,
+        path.into_inner()
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to retrieve a published newsletter issue")
+    .map_err(e500)?;
+    let Some(issue) = issue else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+    <link rel="alternate" type="application/atom+xml" href="/feed.atom">
+</head>
+<body>
+    <h1>{title}</h1>
+    {}
+</body>
+</html>"#,
+            issue.html_content,
+            title = encode_minimal(&issue.title),
+        )))
+}
+
+/// Feeds must always carry an update timestamp, even before the first issue is out.
+fn last_updated_at(issues: &[PublishedIssue]) -> DateTime<Utc> {
+    issues
+        .iter()
+        .map(|i| i.updated_at)
+        .max()
+        .unwrap_or_else(|| std::time::UNIX_EPOCH.into())
+}
+
+#[tracing::instrument(name = "Get published issues", skip(pool))]
+async fn get_published_issues(pool: &PgPool) -> Result<Vec<PublishedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        PublishedIssue,
+        r#"
+        select
+            newsletter_issue_id,
+            title,
+            html_content,
+            published_at as "published_at!",
+            greatest(updated_at, published_at) as "updated_at!"
+        from newsletter_issues
+        where published_at is not null
+        order by published_at desc
+        limit $1
+        "#,
+        FEED_LENGTH
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve published newsletter issues")?;
+
+    Ok(issues)
+}
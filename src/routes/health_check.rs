@@ -1,5 +1,23 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
 
+use crate::health::{HealthChecks, Status};
+
+/// Liveness: the process is up and serving requests, whatever the state of
+/// what it depends on.
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+/// Readiness: everything the application depends on is healthy. Unhealthy
+/// dependencies get a 503, with the details of every check in the body.
+pub async fn readiness_check(
+    pool: web::Data<PgPool>,
+    health_checks: web::Data<HealthChecks>,
+) -> HttpResponse {
+    let readiness = health_checks.run(&pool).await;
+    match readiness.status {
+        Status::Healthy => HttpResponse::Ok().json(readiness),
+        Status::Unhealthy => HttpResponse::ServiceUnavailable().json(readiness),
+    }
+}
@@ -1,12 +1,28 @@
-use actix_web::{http::header::ContentType, HttpResponse};
-use actix_web_flash_messages::IncomingFlashMessages;
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
 use std::fmt::Write;
 
-pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+use crate::{
+    authentication::{get_password_reset_username, OidcClient},
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+pub async fn login_form(
+    flash_messages: IncomingFlashMessages,
+    oidc_client: web::Data<Option<OidcClient>>,
+) -> HttpResponse {
     let mut flash_html = String::new();
     for m in flash_messages.iter() {
         writeln!(flash_html, "<p><i>{}</i></p>", m.content()).unwrap()
     }
+    let sso_link = if oidc_client.is_some() {
+        r#"<p><a href="/login/sso">Log in with single sign-on</a></p>"#
+    } else {
+        ""
+    };
 
     HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -28,8 +44,133 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
         <label>Password <input type="password" name="password" /></label>
         <button type="submit">Login</button>
         </form>
+        {sso_link}
+        <p><a href="/login/forgot">Forgot your password?</a></p>
+    </body>
+</html>
+"#
+        ))
+}
+
+pub async fn login_second_factor_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_pending_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let mut flash_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(flash_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html" charset="UTF-8" />
+        <meta name="viewport" content="width=device-width, initial-scale=1.0" />
+        <title>Two-factor authentication</title>
+    </head>
+    <body>
+        {flash_html}
+        <form action="/login/totp" method="post">
+        <label
+            >Code from your authenticator app, or a recovery code
+            <input type="text" autocomplete="one-time-code" name="code"
+        /></label>
+        <button type="submit">Verify</button>
+        </form>
+    </body>
+</html>
+"#
+        )))
+}
+
+pub async fn forgot_password_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut flash_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(flash_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html" charset="UTF-8" />
+        <meta name="viewport" content="width=device-width, initial-scale=1.0" />
+        <title>Forgot password</title>
+    </head>
+    <body>
+        {flash_html}
+        <p>Enter your username and we will email you a link to choose a new password.</p>
+        <form action="/login/forgot" method="post">
+        <label
+            >Username
+            <input type="text" placeholder="Enter Username" name="username"
+        /></label>
+        <button type="submit">Send reset link</button>
+        </form>
+        <p><a href="/login">&lt;- Back</a></p>
     </body>
 </html>
 "#
         ))
 }
+
+#[derive(serde::Deserialize)]
+pub struct ResetParameters {
+    reset_token: String,
+}
+
+pub async fn reset_password_form(
+    parameters: web::Query<ResetParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let reset_token = &parameters.reset_token;
+    if get_password_reset_username(reset_token, &pool)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        FlashMessage::error("This reset link is invalid or has expired.").send();
+        return Ok(see_other("/login/forgot"));
+    }
+
+    let mut flash_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(flash_html, "<p><i>{}</i></p>", m.content()).unwrap()
+    }
+
+    let reset_token = encode_minimal(reset_token);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html" charset="UTF-8" />
+        <meta name="viewport" content="width=device-width, initial-scale=1.0" />
+        <title>Reset password</title>
+    </head>
+    <body>
+        {flash_html}
+        <form action="/login/reset" method="post">
+        <input hidden type="text" name="reset_token" value="{reset_token}">
+        <label>New password <input type="password" name="new_password" /></label>
+        <br>
+        <label>Confirm new password <input type="password" placeholder="Type the new password again" name="new_password_check" /></label>
+        <br>
+        <button type="submit">Reset password</button>
+        </form>
+    </body>
+</html>
+"#
+        )))
+}
@@ -1,5 +1,7 @@
 mod get;
 mod post;
+mod sso;
 
-pub use get::login_form;
-pub use post::login;
+pub use get::{forgot_password_form, login_form, login_second_factor_form, reset_password_form};
+pub use post::{forgot_password, login, login_second_factor, submit_password_reset};
+pub use sso::{complete_sso_login, start_sso_login};
@@ -1,13 +1,28 @@
-use actix_web::{error::InternalError, web, HttpResponse};
+use std::time::Duration;
+
+use actix_web::{error::InternalError, web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
 use secrecy::Secret;
 use sqlx::PgPool;
+use tracing::Instrument;
 
 use crate::{
-    authentication::{validate_credentials, AuthError, Credentials},
-    routes::error_chain_fmt,
+    audit::{AuditAction, AuditEvent},
+    authentication::{
+        check_new_password, get_password_reset_username, is_totp_enabled,
+        issue_password_reset_token, reset_password, start_session, validate_credentials,
+        verify_second_factor, AuthError, Credentials, LoginGate, LoginThrottle,
+    },
+    client_ip::client_ip,
+    configuration::{PasswordHashingSettings, PasswordPolicySettings},
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    metrics::{self, LoginMethod},
+    routes::{admin::get_username, error_chain_fmt},
     session_state::TypedSession,
-    utils::see_other,
+    startup::ApplicationBaseUrl,
+    utils::{e500, see_other},
 };
 
 #[derive(serde::Deserialize)]
@@ -16,8 +31,18 @@ pub struct FormData {
     password: Secret<String>,
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
-    skip(form, pool, session),
+    skip(
+        form,
+        pool,
+        session,
+        request,
+        throttle,
+        password_hashing,
+        email_client,
+        base_url
+    ),
     fields(
         username=tracing::field::Empty,
         user_id=tracing::field::Empty
@@ -27,27 +52,89 @@ pub async fn login(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
     session: TypedSession,
+    request: HttpRequest,
+    throttle: web::Data<LoginThrottle>,
+    password_hashing: web::Data<PasswordHashingSettings>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
         username: form.0.username,
         password: form.0.password,
     };
+    let username = credentials.username.clone();
+    let client_ip = client_ip(&request).map_or_else(|| "unknown".into(), |ip| ip.to_string());
+
+    tracing::Span::current().record("username", tracing::field::display(&username));
 
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    let attempt = match throttle
+        .attempt(&username, &client_ip)
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+    {
+        LoginGate::Locked { retry_after } => {
+            AuditEvent::new(AuditAction::LoginFailed, &request)
+                .target(&username)
+                .details("locked out after too many failed attempts")
+                .record(pool.as_ref())
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            metrics::record_login(LoginMethod::Password, false);
+            return Err(login_redirect(LoginError::TooManyAttempts {
+                minutes: retry_after.as_secs().div_ceil(60),
+            }));
+        }
+        LoginGate::Open { delay, attempt } => {
+            tokio::time::sleep(delay).await;
+            attempt
+        }
+    };
 
-    match validate_credentials(credentials, &pool).await {
+    match validate_credentials(credentials, &password_hashing, &pool).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            throttle
+                .record_success(&username, &client_ip)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            let needs_second_factor = is_totp_enabled(user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
             session.renew();
-            session
-                .insert_user_id(user_id)
-                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            if needs_second_factor {
+                session
+                    .insert_pending_user_id(user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(see_other("/login/totp"));
+            }
+            start_session(&session, user_id, &request, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            AuditEvent::new(AuditAction::LoginSucceeded, &request)
+                .actor(user_id)
+                .details("password")
+                .record(pool.as_ref())
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            metrics::record_login(LoginMethod::Password, true);
 
             Ok(see_other("/admin/dashboard"))
         }
         Err(e) => {
             let e = match e {
-                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::InvalidCredentials(_) => {
+                    AuditEvent::new(AuditAction::LoginFailed, &request)
+                        .target(&username)
+                        .details("invalid credentials")
+                        .record(pool.as_ref())
+                        .await
+                        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+                    metrics::record_login(LoginMethod::Password, false);
+                    if attempt == throttle.max_failures_per_username() {
+                        notify_account_owner_of_lockout(username, pool, email_client, base_url);
+                    }
+                    LoginError::AuthError(e.into())
+                }
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
             };
             Err(login_redirect(e))
@@ -55,6 +142,288 @@ pub async fn login(
     }
 }
 
+/// Tell the owner of `username`, if it exists and has an email address, that
+/// it has just been locked.
+fn notify_account_owner_of_lockout(
+    username: String,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) {
+    actix_web::rt::spawn(
+        async move {
+            if let Err(e) =
+                send_lockout_notification(&username, &pool, &email_client, &base_url.0).await
+            {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a lockout notification"
+                );
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+async fn send_lockout_notification(
+    username: &str,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<(), anyhow::Error> {
+    let email = sqlx::query!(
+        r#"
+        select email as "email!"
+        from users
+        where username = $1 and email is not null
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the email address of a user")?;
+    let Some(email) = email else {
+        return Ok(());
+    };
+    let email = SubscriberEmail::parse(email.email).map_err(anyhow::Error::msg)?;
+    let reset_link = format!("{base_url}/login/forgot");
+    email_client
+        .send_email(
+            &email,
+            "Your account has been locked",
+            &format!(
+                "There have been several failed attempts to log in to your account, \
+                so it has been locked for a while.<br />\
+                If it was not you, consider <a href=\"{reset_link}\">resetting your password</a>."
+            ),
+            &format!(
+                "There have been several failed attempts to log in to your account, \
+                so it has been locked for a while.\n\
+                If it was not you, consider resetting your password at {reset_link}."
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct SecondFactorFormData {
+    code: String,
+}
+
+#[tracing::instrument(
+    skip(form, pool, session, request, throttle),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn login_second_factor(
+    form: web::Form<SecondFactorFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    request: HttpRequest,
+    throttle: web::Data<LoginThrottle>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(user_id) = session.get_pending_user_id().map_err(e500)? else {
+        return Ok(see_other("/login"));
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let client_ip = client_ip(&request).map_or_else(|| "unknown".into(), |ip| ip.to_string());
+
+    let attempt = match throttle
+        .attempt_second_factor(user_id, &client_ip)
+        .await
+        .map_err(e500)?
+    {
+        LoginGate::Locked { retry_after } => {
+            AuditEvent::new(AuditAction::LoginFailed, &request)
+                .target(get_username(user_id, &pool).await.map_err(e500)?)
+                .details("locked out after too many failed attempts")
+                .record(pool.as_ref())
+                .await
+                .map_err(e500)?;
+            metrics::record_login(LoginMethod::SecondFactor, false);
+            return Ok(second_factor_lockout(&session, retry_after));
+        }
+        LoginGate::Open { delay, attempt } => {
+            tokio::time::sleep(delay).await;
+            attempt
+        }
+    };
+
+    if !verify_second_factor(user_id, &form.0.code, &pool)
+        .await
+        .map_err(e500)?
+    {
+        AuditEvent::new(AuditAction::LoginFailed, &request)
+            .target(get_username(user_id, &pool).await.map_err(e500)?)
+            .details("invalid second factor code")
+            .record(pool.as_ref())
+            .await
+            .map_err(e500)?;
+        metrics::record_login(LoginMethod::SecondFactor, false);
+        if attempt >= throttle.max_failures_per_username() {
+            return Ok(second_factor_lockout(&session, throttle.lockout()));
+        }
+        FlashMessage::error("The authentication code is invalid.").send();
+        return Ok(see_other("/login/totp"));
+    }
+
+    throttle
+        .record_second_factor_success(user_id, &client_ip)
+        .await
+        .map_err(e500)?;
+    session.clear();
+    session.renew();
+    start_session(&session, user_id, &request, &pool)
+        .await
+        .map_err(e500)?;
+    AuditEvent::new(AuditAction::LoginSucceeded, &request)
+        .actor(user_id)
+        .details("password and second factor")
+        .record(pool.as_ref())
+        .await
+        .map_err(e500)?;
+    metrics::record_login(LoginMethod::SecondFactor, true);
+    Ok(see_other("/admin/dashboard"))
+}
+
+/// Send the user back to the start, the password alone will not let them in
+/// until the lockout is over.
+fn second_factor_lockout(session: &TypedSession, retry_after: Duration) -> HttpResponse {
+    session.log_out();
+    FlashMessage::error(format!(
+        "Too many invalid codes, please try again in {} minutes.",
+        retry_after.as_secs().div_ceil(60)
+    ))
+    .send();
+    see_other("/login")
+}
+
+#[derive(serde::Deserialize)]
+pub struct ForgotPasswordFormData {
+    username: String,
+}
+
+#[tracing::instrument(skip(form, pool, email_client, base_url), fields(username=%form.username))]
+pub async fn forgot_password(
+    form: web::Form<ForgotPasswordFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    // The link is sent in the background so that the response looks and takes
+    // the same whether the username exists or not.
+    actix_web::rt::spawn(
+        async move {
+            if let Err(e) =
+                send_password_reset_link(&form.0.username, &pool, &email_client, &base_url.0).await
+            {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a password reset link"
+                );
+            }
+        }
+        .in_current_span(),
+    );
+
+    FlashMessage::info(
+        "If the account exists, a link to reset its password has been sent to its email address.",
+    )
+    .send();
+    see_other("/login")
+}
+
+async fn send_password_reset_link(
+    username: &str,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<(), anyhow::Error> {
+    let Some((email, reset_token)) = issue_password_reset_token(username, pool).await? else {
+        return Ok(());
+    };
+    let reset_link = format!("{base_url}/login/reset?reset_token={reset_token}");
+    email_client
+        .send_email(
+            &email,
+            "Reset your password",
+            &format!(
+                "Click <a href=\"{reset_link}\">here</a> to choose a new password.<br />\
+                The link expires in an hour. If you did not ask for it, you can ignore this email."
+            ),
+            &format!(
+                "Visit {reset_link} to choose a new password.\n\
+                The link expires in an hour. If you did not ask for it, you can ignore this email."
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetPasswordFormData {
+    reset_token: String,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[tracing::instrument(skip(form, pool, password_policy, password_hashing, request))]
+pub async fn submit_password_reset(
+    form: web::Form<ResetPasswordFormData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    password_policy: web::Data<PasswordPolicySettings>,
+    password_hashing: web::Data<PasswordHashingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ResetPasswordFormData {
+        reset_token,
+        new_password,
+        new_password_check,
+    } = form.0;
+
+    let Some(username) = get_password_reset_username(&reset_token, &pool)
+        .await
+        .map_err(e500)?
+    else {
+        FlashMessage::error("This reset link is invalid or has expired.").send();
+        return Ok(see_other("/login/forgot"));
+    };
+    let violations = check_new_password(
+        &new_password,
+        &new_password_check,
+        &username,
+        &password_policy,
+    );
+    if !violations.is_empty() {
+        for violation in violations {
+            FlashMessage::error(violation).send();
+        }
+        return Ok(see_other(&format!(
+            "/login/reset?reset_token={reset_token}"
+        )));
+    }
+
+    if !reset_password(&reset_token, new_password, &password_hashing, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("This reset link is invalid or has expired.").send();
+        return Ok(see_other("/login/forgot"));
+    }
+
+    AuditEvent::new(AuditAction::PasswordReset, &request)
+        .target(&username)
+        .details("with an emailed reset link")
+        .record(pool.as_ref())
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("Your password has been reset, you can now log in.").send();
+    Ok(see_other("/login"))
+}
+
 fn login_redirect(e: LoginError) -> InternalError<LoginError> {
     FlashMessage::error(e.to_string()).send();
     InternalError::from_response(e, see_other("/login"))
@@ -64,6 +433,8 @@ fn login_redirect(e: LoginError) -> InternalError<LoginError> {
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("Too many failed login attempts, please try again in {minutes} minutes.")]
+    TooManyAttempts { minutes: u64 },
     #[error("something went wrong")]
     UnexpectedError(#[from] anyhow::Error),
 }
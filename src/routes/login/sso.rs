@@ -0,0 +1,118 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{start_session, OidcClient, SsoError},
+    metrics::{self, LoginMethod},
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+pub async fn start_sso_login(
+    oidc_client: web::Data<Option<OidcClient>>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(oidc_client) = oidc_client.as_ref() else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let (authorization_url, login) = match oidc_client.start_login().await {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to start a single sign-on login"
+            );
+            FlashMessage::error("The identity provider is unavailable, please try again later.")
+                .send();
+            return Ok(see_other("/login"));
+        }
+    };
+    session.insert_sso_login(&login).map_err(e500)?;
+    Ok(see_other(authorization_url.as_str()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct CallbackParameters {
+    code: Option<String>,
+    state: Option<String>,
+}
+
+/// Where the identity provider sends the browser back to. Users logging in this
+/// way are not asked for their local second factor, the provider is in charge.
+#[tracing::instrument(
+    skip_all,
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn complete_sso_login(
+    parameters: web::Query<CallbackParameters>,
+    oidc_client: web::Data<Option<OidcClient>>,
+    session: TypedSession,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(oidc_client) = oidc_client.as_ref() else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let login = session.take_sso_login().map_err(e500)?;
+    // No code when the user cancelled or the provider refused to log them in.
+    let (Some(login), Some(code), Some(state)) = (login, &parameters.code, &parameters.state)
+    else {
+        return Ok(sso_failed());
+    };
+    if *state != login.state {
+        tracing::warn!("The single sign-on callback is for another login");
+        return Ok(sso_failed());
+    }
+
+    match oidc_client.complete_login(code, &login, &pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            session.clear();
+            session.renew();
+            start_session(&session, user_id, &request, &pool)
+                .await
+                .map_err(e500)?;
+            AuditEvent::new(AuditAction::LoginSucceeded, &request)
+                .actor(user_id)
+                .details("single sign-on")
+                .record(pool.as_ref())
+                .await
+                .map_err(e500)?;
+            metrics::record_login(LoginMethod::SingleSignOn, true);
+            Ok(see_other("/admin/dashboard"))
+        }
+        Err(SsoError::UnknownUser(identity)) => {
+            AuditEvent::new(AuditAction::LoginFailed, &request)
+                .target(identity)
+                .details("single sign-on identity without an active account")
+                .record(pool.as_ref())
+                .await
+                .map_err(e500)?;
+            metrics::record_login(LoginMethod::SingleSignOn, false);
+            FlashMessage::error("No active account matches your identity provider login.").send();
+            Ok(see_other("/login"))
+        }
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Single sign-on failed"
+            );
+            AuditEvent::new(AuditAction::LoginFailed, &request)
+                .details(format!("single sign-on failed: {e}"))
+                .record(pool.as_ref())
+                .await
+                .map_err(e500)?;
+            metrics::record_login(LoginMethod::SingleSignOn, false);
+            Ok(sso_failed())
+        }
+    }
+}
+
+fn sso_failed() -> HttpResponse {
+    FlashMessage::error("Single sign-on failed, please try again.").send();
+    see_other("/login")
+}
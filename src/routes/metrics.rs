@@ -0,0 +1,11 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::{metrics, utils::e500};
+
+pub async fn export_metrics(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let body = metrics::gather(&pool).await.map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(body))
+}
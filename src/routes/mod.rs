@@ -1,14 +1,21 @@
 mod admin;
+pub mod api;
+mod feed;
 mod health_check;
 mod home;
 mod login;
+mod metrics;
+mod signup;
 mod subscriptions;
 mod subscriptions_confirm;
 
 pub use admin::*;
+pub use feed::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
+pub use metrics::*;
+pub use signup::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
 
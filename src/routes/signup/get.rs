@@ -0,0 +1,90 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{authentication::hash_token, utils::e500};
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    invitation_token: String,
+}
+
+pub async fn signup_form(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let invitation_token = &parameters.invitation_token;
+    let body = match get_invited_email(&pool, invitation_token)
+        .await
+        .map_err(e500)?
+    {
+        Some(email) => format!(
+            r#"<p>Create the account for {}.</p>
+    <form action="/signup" method="post">
+        <input hidden type="text" name="invitation_token" value="{}">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <br>
+        <label>Password
+            <input type="password" placeholder="Enter Password" name="password">
+        </label>
+        <br>
+        <label>Confirm password
+            <input type="password" placeholder="Type the password again" name="password_check">
+        </label>
+        <br>
+        <button type="submit">Create account</button>
+    </form>"#,
+            encode_minimal(&email),
+            encode_minimal(invitation_token),
+        ),
+        None => "<p>This invitation is invalid or has expired.</p>".to_string(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Sign up</title>
+</head>
+<body>
+    {msg_html}
+    {body}
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Get invited email", skip(pool, invitation_token))]
+async fn get_invited_email(
+    pool: &PgPool,
+    invitation_token: &str,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        select email from user_invitations
+        where invitation_token_hash = $1
+        and accepted_at is null
+        and expires_at > now()
+        "#,
+        hash_token(invitation_token)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the invitation")?;
+
+    Ok(row.map(|r| r.email))
+}
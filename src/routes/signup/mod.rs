@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::signup_form;
+pub use post::sign_up;
@@ -0,0 +1,108 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::{
+    authentication::{check_new_password, create_user, hash_token, Role},
+    configuration::{PasswordHashingSettings, PasswordPolicySettings},
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    invitation_token: String,
+    username: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Sign up",
+    skip(form, pool, password_policy, password_hashing),
+    fields(username=%form.username)
+)]
+pub async fn sign_up(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    password_policy: web::Data<PasswordPolicySettings>,
+    password_hashing: web::Data<PasswordHashingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData {
+        invitation_token,
+        username,
+        password,
+        password_check,
+    } = form.0;
+    let signup_page = format!("/signup?invitation_token={}", invitation_token);
+
+    let username = username.trim();
+    if username.is_empty() {
+        FlashMessage::error("The username cannot be empty.").send();
+        return Ok(see_other(&signup_page));
+    }
+    let violations = check_new_password(&password, &password_check, username, &password_policy);
+    if !violations.is_empty() {
+        for violation in violations {
+            FlashMessage::error(violation).send();
+        }
+        return Ok(see_other(&signup_page));
+    }
+
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+
+    let Some((email, role)) = accept_invitation(&mut tx, &invitation_token)
+        .await
+        .map_err(e500)?
+    else {
+        FlashMessage::error("This invitation is invalid or has expired.").send();
+        return Ok(see_other("/login"));
+    };
+
+    // Dropping the transaction un-claims the invitation, so it can be retried.
+    if create_user(&mut tx, username, password, &email, role, &password_hashing)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        FlashMessage::error("This username is already taken.").send();
+        return Ok(see_other(&signup_page));
+    }
+
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to create a user")
+        .map_err(e500)?;
+
+    FlashMessage::info("Your account has been created, you can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+/// Mark the invitation as used, returning who it was for and with which role.
+#[tracing::instrument(skip_all)]
+async fn accept_invitation(
+    tx: &mut Transaction<'_, Postgres>,
+    invitation_token: &str,
+) -> Result<Option<(String, Role)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        update user_invitations
+        set accepted_at = now()
+        where invitation_token_hash = $1
+        and accepted_at is null
+        and expires_at > now()
+        returning email, role
+        "#,
+        hash_token(invitation_token)
+    )
+    .fetch_optional(tx.as_mut())
+    .await
+    .context("Failed to accept the invitation")?;
+
+    row.map(|r| Ok((r.email, r.role.try_into()?))).transpose()
+}
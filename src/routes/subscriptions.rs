@@ -1,4 +1,4 @@
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::{Context, Result};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use reqwest::StatusCode;
@@ -8,7 +8,8 @@ use uuid::Uuid;
 
 use crate::{
     domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    idempotency::{begin_transaction, commit_transaction},
+    outbox::{self, OutboxMessage},
     routes::error_chain_fmt,
     startup::ApplicationBaseUrl,
 };
@@ -53,7 +54,7 @@ impl TryFrom<FormData> for NewSubscriber {
 }
 
 #[tracing::instrument(
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, base_url, request),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -62,8 +63,8 @@ impl TryFrom<FormData> for NewSubscriber {
 pub async fn subscribe(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, SubscribeError> {
     sqlx::query!("delete from subscriptions")
         .execute(pool.as_ref())
@@ -72,8 +73,7 @@ pub async fn subscribe(
 
     let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
 
-    let mut transaction = pool
-        .begin()
+    let mut transaction = begin_transaction(&request, &pool)
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
@@ -86,17 +86,16 @@ pub async fn subscribe(
         .await
         .context("Failed to store the confirmation token for a new subscriber")?;
 
-    send_confirmation_email(
-        &email_client,
+    queue_confirmation_email(
+        &mut transaction,
         new_subscriber,
         &base_url.0,
         &subscription_token,
     )
     .await
-    .context("Failed to send a confirmation email")?;
+    .context("Failed to queue a confirmation email")?;
 
-    transaction
-        .commit()
+    commit_transaction(&request, transaction)
         .await
         .context("Failed to commit SQL transaction to store a new subscriber")?;
 
@@ -145,30 +144,36 @@ pub async fn store_token(
     Ok(())
 }
 
-#[tracing::instrument(skip(email_client, new_subscriber, base_url, subscription_token))]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
+/// The email is only sent once the subscriber has been committed, by the outbox
+/// relay.
+#[tracing::instrument(skip(transaction, new_subscriber, base_url, subscription_token))]
+pub async fn queue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<()> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
 
-    let html_body = &format!(
+    let html_body = format!(
         "Welcome to our newsletter!<br />\
                 Click <a href=\"{}\">here</a> to confirm you subscription",
         confirmation_link
     );
-    let text_body = &format!(
+    let text_body = format!(
         "Welcome to our newsletter!\nClick {} to confirm you subscription",
         confirmation_link
     );
-    email_client
-        .send_email(&new_subscriber.email, "Welcome!", html_body, text_body)
-        .await
+    let message = OutboxMessage::Email {
+        recipient: new_subscriber.email.as_ref().to_owned(),
+        subject: "Welcome!".into(),
+        html_content: html_body,
+        text_content: text_body,
+    };
+    outbox::enqueue(transaction, &message).await
 }
 
 fn generate_subscription_token() -> String {
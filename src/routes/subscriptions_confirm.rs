@@ -3,6 +3,8 @@ use anyhow::Result;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::autoresponder::enroll_subscriber;
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
@@ -27,16 +29,28 @@ pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>
 
 #[tracing::instrument(skip(subscriber_id, pool))]
 pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<()> {
-    sqlx::query!(
-        r#"update subscriptions set status = 'confirmed' where id = $1"#,
+    let mut transaction = pool.begin().await?;
+    let n_confirmed = sqlx::query!(
+        r#"
+        update subscriptions set status = 'confirmed'
+        where id = $1 and status <> 'confirmed'
+        "#,
         subscriber_id
     )
-    .execute(pool)
+    .execute(&mut *transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
-    })?;
+    })?
+    .rows_affected();
+
+    // Only newly confirmed subscribers start the welcome sequences, clicking the
+    // confirmation link twice must not enroll them again.
+    if n_confirmed > 0 {
+        enroll_subscriber(&mut transaction, subscriber_id).await?;
+    }
+    transaction.commit().await?;
     Ok(())
 }
 
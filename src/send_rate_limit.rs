@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::jobs::{Job, JobContext, JobOutcome};
+
+const GLOBAL_BUCKET: &str = "global";
+
+/// Per-minute send budgets, counted in Postgres so that they hold across every
+/// worker instance.
+#[derive(Clone, Default)]
+pub struct SendRateLimiter {
+    max_per_minute: Option<u32>,
+    max_per_minute_by_domain: HashMap<String, u32>,
+}
+
+impl SendRateLimiter {
+    pub fn new(
+        max_per_minute: Option<u32>,
+        max_per_minute_by_domain: impl IntoIterator<Item = (String, u32)>,
+    ) -> Self {
+        Self {
+            max_per_minute,
+            max_per_minute_by_domain: max_per_minute_by_domain
+                .into_iter()
+                .map(|(domain, limit)| (domain.to_lowercase(), limit))
+                .collect(),
+        }
+    }
+
+    /// Reserve a slot in the current minute for an email to `domain`.
+    ///
+    /// Returns `false`, without consuming any budget, if either the global or the
+    /// domain budget is exhausted.
+    #[tracing::instrument(skip(self, pool))]
+    pub async fn try_acquire(&self, pool: &PgPool, domain: &str) -> Result<bool, sqlx::Error> {
+        let mut buckets = Vec::with_capacity(2);
+        if let Some(limit) = self.max_per_minute {
+            buckets.push((GLOBAL_BUCKET.to_string(), limit));
+        }
+        if let Some(limit) = self.max_per_minute_by_domain.get(domain) {
+            buckets.push((format!("domain:{domain}"), *limit));
+        }
+        if buckets.is_empty() {
+            return Ok(true);
+        }
+
+        let mut tx = pool.begin().await?;
+        for (bucket, limit) in buckets {
+            let reserved = sqlx::query!(
+                r#"
+                insert into send_rate_windows (bucket, window_start, sent_count)
+                select $1, date_trunc('minute', now()), 1
+                where $2::int8 > 0
+                on conflict (bucket, window_start) do update
+                set sent_count = send_rate_windows.sent_count + 1
+                where send_rate_windows.sent_count < $2::int8
+                returning sent_count
+                "#,
+                bucket,
+                i64::from(limit)
+            )
+            .fetch_optional(tx.as_mut())
+            .await?
+            .is_some();
+
+            if !reserved {
+                tx.rollback().await?;
+                return Ok(false);
+            }
+        }
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// When the budgets are topped up again.
+    pub fn next_window() -> DateTime<Utc> {
+        let window = chrono::Duration::minutes(1);
+        Utc::now()
+            .duration_trunc(window)
+            .expect("A minute is a valid rounding duration")
+            + window
+    }
+
+    /// Drop the counters of windows that are long gone.
+    #[tracing::instrument(skip_all)]
+    pub async fn prune(pool: &PgPool) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            delete from send_rate_windows
+            where window_start < now() - interval '1 hour'
+            "#
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Run [`SendRateLimiter::prune`] on a schedule.
+#[derive(Serialize, Deserialize)]
+pub struct PruneSendRateWindows;
+
+impl Job for PruneSendRateWindows {
+    const KIND: &'static str = "prune_send_rate_windows";
+
+    fn unique_key(&self) -> Option<String> {
+        Some(Self::KIND.into())
+    }
+
+    async fn run(self, context: &JobContext<'_>) -> Result<JobOutcome, anyhow::Error> {
+        SendRateLimiter::prune(context.pool).await?;
+        Ok(JobOutcome::Completed)
+    }
+}
@@ -2,25 +2,87 @@ use std::future::Ready;
 
 use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
 use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
+use crate::authentication::SsoLogin;
+
 pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const AUTHENTICATED_AT_KEY: &'static str = "authenticated_at";
+    const SESSION_ID_KEY: &'static str = "session_id";
+    const PENDING_USER_ID_KEY: &'static str = "pending_user_id";
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
+    const SSO_LOGIN_KEY: &'static str = "sso_login";
 
     pub fn renew(&self) {
         self.0.renew()
     }
 
     pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
-        self.0.insert(Self::USER_ID_KEY, user_id)
+        self.0.insert(Self::USER_ID_KEY, user_id)?;
+        self.0
+            .insert(Self::AUTHENTICATED_AT_KEY, Utc::now().timestamp_micros())
     }
 
     pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    /// When the session was authenticated. Sessions opened before this was recorded
+    /// count as infinitely old.
+    pub fn get_authenticated_at(&self) -> Result<DateTime<Utc>, SessionGetError> {
+        let micros = self.0.get(Self::AUTHENTICATED_AT_KEY)?.unwrap_or(0);
+        Ok(DateTime::from_timestamp_micros(micros).unwrap_or_default())
+    }
+
+    /// The id of the session in `user_sessions`, see `start_session`.
+    pub fn insert_session_id(&self, session_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
+    /// The token admin forms must submit back, see `reject_forged_requests`.
+    pub fn insert_csrf_token(&self, token: &str) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::CSRF_TOKEN_KEY, token)
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, SessionGetError> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
+    /// Remember a single sign-on login until the identity provider sends the user back.
+    pub fn insert_sso_login(&self, login: &SsoLogin) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::SSO_LOGIN_KEY, login)
+    }
+
+    /// The pending single sign-on login, which can only be completed once.
+    pub fn take_sso_login(&self) -> Result<Option<SsoLogin>, SessionGetError> {
+        let login = self.0.get(Self::SSO_LOGIN_KEY)?;
+        self.0.remove(Self::SSO_LOGIN_KEY);
+        Ok(login)
+    }
+
+    /// Remember who passed the password check while they still owe us a second factor.
+    /// The session is not authenticated until `insert_user_id` is called.
+    pub fn insert_pending_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::PENDING_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::PENDING_USER_ID_KEY)
+    }
+
+    /// Drop everything stored so far, e.g. the state of a half-finished login.
+    pub fn clear(&self) {
+        self.0.clear()
+    }
+
     pub fn log_out(&self) {
         self.0.purge()
     }
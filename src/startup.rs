@@ -1,8 +1,8 @@
 use std::net::TcpListener;
 
-use actix_session::{storage::RedisSessionStore, SessionMiddleware};
+use actix_session::{config::BrowserSession, storage::RedisSessionStore, SessionMiddleware};
 use actix_web::{
-    cookie::Key,
+    cookie::{time, Key},
     dev::Server,
     web::{self, Data},
     App, HttpServer,
@@ -15,9 +15,16 @@ use sqlx::{postgres::PgPoolOptions, PgPool};
 use tracing_actix_web::TracingLogger;
 
 use crate::{
-    authentication::reject_anonymous_users,
-    configuration::{DatabaseSettings, Settings},
+    authentication::{
+        reject_anonymous_users, reject_forged_requests, reject_invalid_api_tokens,
+        reject_unauthorized_users, LoginThrottle, SESSION_TTL,
+    },
+    client_ip::TrustedProxies,
+    configuration::{ApplicationSettings, DatabaseSettings, Settings},
     email_client::EmailClient,
+    health::HealthChecks,
+    idempotency::idempotent,
+    metrics::track_requests,
     routes::*,
 };
 
@@ -27,46 +34,187 @@ async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
-    hmac_secret: Secret<String>,
-    redis_uri: Secret<String>,
+    login_throttle: LoginThrottle,
+    configuration: Settings,
 ) -> Result<Server> {
     let db_pool = Data::new(db_pool);
     let email_client = Data::new(email_client);
+    let login_throttle = Data::new(login_throttle);
+    let password_policy = Data::new(configuration.password_policy);
+    let password_hashing = Data::new(configuration.password_hashing);
+    let idempotency = Data::new(configuration.idempotency);
 
+    let ApplicationSettings {
+        base_url,
+        hmac_secret,
+        trusted_proxies,
+        ..
+    } = configuration.application;
+    let trusted_proxies = Data::new(TrustedProxies(trusted_proxies));
+    let oidc_client = Data::new(configuration.oidc.client(&base_url));
+    let redis_uri = configuration.redis_uri;
+    let health_checks = Data::new(HealthChecks::new(&redis_uri, configuration.health)?);
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    let server =
+        HttpServer::new(move || {
+            App::new()
+                .wrap(from_fn(track_requests))
+                .wrap(TracingLogger::default())
+                .wrap(message_framework.clone())
+                .wrap(
+                    SessionMiddleware::builder(redis_store.clone(), secret_key.clone())
+                        .session_lifecycle(
+                            BrowserSession::default()
+                                .state_ttl(time::Duration::seconds(SESSION_TTL.num_seconds())),
+                        )
+                        .build(),
+                )
+                .route("/", web::get().to(home))
+                .route("/login", web::get().to(login_form))
+                .route("/login", web::post().to(login))
+                .route("/login/totp", web::get().to(login_second_factor_form))
+                .route("/login/totp", web::post().to(login_second_factor))
+                .route("/login/sso", web::get().to(start_sso_login))
+                .route("/login/sso/callback", web::get().to(complete_sso_login))
+                .route("/login/forgot", web::get().to(forgot_password_form))
+                .route("/login/forgot", web::post().to(forgot_password))
+                .route("/login/reset", web::get().to(reset_password_form))
+                .route("/login/reset", web::post().to(submit_password_reset))
+                .route("/signup", web::get().to(signup_form))
+                .route("/signup", web::post().to(sign_up))
+                .route("/newsletters", web::post().to(publish_newsletter))
+                .route("/health_check", web::get().to(health_check))
+                .route("/health/live", web::get().to(health_check))
+                .route("/health/ready", web::get().to(readiness_check))
+                .route(
+                    "/metrics",
+                    web::get()
+                        .to(export_metrics)
+                        .wrap(from_fn(reject_invalid_api_tokens)),
+                )
+                .route("/feed.atom", web::get().to(atom_feed))
+                .route("/feed.rss", web::get().to(rss_feed))
+                .route("/issues/{issue_id}", web::get().to(published_issue))
+                .route(
+                    "/subscriptions",
+                    web::post().to(subscribe).wrap(from_fn(idempotent)),
+                )
+                .route("/subscriptions/confirm", web::get().to(confirm))
+                .service(
+                    web::scope("/admin")
+                        .wrap(from_fn(reject_forged_requests))
+                        .wrap(from_fn(reject_unauthorized_users))
+                        .wrap(from_fn(reject_anonymous_users))
+                        .route("/logout", web::post().to(log_out))
+                        .route("/dashboard", web::get().to(admin_dashboard))
+                        .route("/api-tokens", web::get().to(api_tokens_list))
+                        .route("/api-tokens", web::post().to(create_user_api_token))
+                        .route(
+                            "/api-tokens/{token_id}/revoke",
+                            web::post().to(revoke_user_api_token),
+                        )
+                        .route("/sessions", web::get().to(sessions_list))
+                        .route(
+                            "/sessions/revoke-others",
+                            web::post().to(log_out_other_sessions),
+                        )
+                        .route(
+                            "/sessions/{session_id}/revoke",
+                            web::post().to(log_out_session),
+                        )
+                        .route("/password", web::get().to(change_password_form))
+                        .route("/password", web::post().to(change_password))
+                        .route("/password/totp", web::post().to(enrol_totp))
+                        .route("/password/totp/confirm", web::post().to(confirm_totp))
+                        .route("/password/totp/disable", web::post().to(turn_off_totp))
+                        .route("/newsletters", web::get().to(publish_newsletter_form))
+                        .route(
+                            "/newsletters",
+                            web::post().to(publish_newsletter).wrap(from_fn(idempotent)),
+                        )
+                        .route("/newsletters/drafts", web::post().to(publish_draft))
+                        .route(
+                            "/newsletters/drafts/new",
+                            web::post().to(save_draft).wrap(from_fn(idempotent)),
+                        )
+                        .route("/issues", web::get().to(issues_list))
+                        .route("/issues/{issue_id}/edit", web::get().to(edit_issue_form))
+                        .route("/issues/{issue_id}/edit", web::post().to(edit_issue))
+                        .route(
+                            "/issues/{issue_id}/halt",
+                            web::post().to(halt_issue_delivery),
+                        )
+                        .route(
+                            "/issues/{issue_id}/delete",
+                            web::get().to(delete_issue_form),
+                        )
+                        .route("/issues/{issue_id}/delete", web::post().to(delete_issue))
+                        .route("/users", web::get().to(users_list))
+                        .route("/users/invitations", web::post().to(invite_user))
+                        .route("/users/{user_id}/role", web::post().to(change_user_role))
+                        .route("/users/{user_id}/disable", web::post().to(disable_user))
+                        .route("/users/{user_id}/enable", web::post().to(enable_user))
+                        .route("/audit", web::get().to(audit_log_page))
+                        .route("/audit/export", web::get().to(export_audit_log))
+                        .route("/autoresponders", web::get().to(autoresponders_form))
+                        .route(
+                            "/autoresponders",
+                            web::post().to(create_autoresponder_sequence),
+                        )
+                        .route(
+                            "/autoresponders/steps",
+                            web::post().to(create_autoresponder_step),
+                        ),
+                )
+                .service(
+                    web::scope("/api/v1")
+                        .wrap(from_fn(reject_invalid_api_tokens))
+                        .app_data(web::JsonConfig::default().error_handler(|e, _| {
+                            api::ApiError::ValidationError(e.to_string()).into()
+                        }))
+                        .route(
+                            "/drafts",
+                            web::post().to(api::create_draft).wrap(from_fn(idempotent)),
+                        )
+                        .route(
+                            "/drafts/{newsletter_issue_id}/publish",
+                            web::post().to(api::publish_draft).wrap(from_fn(idempotent)),
+                        )
+                        .route(
+                            "/issues",
+                            web::post().to(api::publish_issue).wrap(from_fn(idempotent)),
+                        )
+                        .route("/subscribers/count", web::get().to(api::subscriber_counts)),
+                )
+                .app_data(db_pool.clone())
+                .app_data(email_client.clone())
+                .app_data(login_throttle.clone())
+                .app_data(password_policy.clone())
+                .app_data(password_hashing.clone())
+                .app_data(idempotency.clone())
+                .app_data(oidc_client.clone())
+                .app_data(health_checks.clone())
+                .app_data(trusted_proxies.clone())
+                .app_data(Data::new(ApplicationBaseUrl(base_url.clone())))
+                .app_data(Data::new(HmacSecret(hmac_secret.clone())))
+        })
+        .listen(listener)?
+        .run();
+
+    Ok(server)
+}
+
+/// Serve the metrics to whoever can reach `listener`, which must not be exposed.
+fn run_metrics_server(listener: TcpListener, db_pool: PgPool) -> Result<Server> {
+    let db_pool = Data::new(db_pool);
     let server = HttpServer::new(move || {
         App::new()
             .wrap(TracingLogger::default())
-            .wrap(message_framework.clone())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
-            ))
-            .route("/", web::get().to(home))
-            .route("/login", web::get().to(login_form))
-            .route("/login", web::post().to(login))
-            .route("/newsletters", web::post().to(publish_newsletter))
-            .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
-            .route("/subscriptions/confirm", web::get().to(confirm))
-            .service(
-                web::scope("/admin")
-                    .wrap(from_fn(reject_anonymous_users))
-                    .route("/logout", web::post().to(log_out))
-                    .route("/dashboard", web::get().to(admin_dashboard))
-                    .route("/password", web::get().to(change_password_form))
-                    .route("/password", web::post().to(change_password))
-                    .route("/newsletters", web::get().to(publish_newsletter_form))
-                    .route("/newsletters", web::post().to(publish_newsletter)),
-            )
+            .route("/metrics", web::get().to(export_metrics))
             .app_data(db_pool.clone())
-            .app_data(email_client.clone())
-            .app_data(Data::new(ApplicationBaseUrl(base_url.clone())))
-            .app_data(Data::new(HmacSecret(hmac_secret.clone())))
     })
     .listen(listener)?
     .run();
@@ -83,35 +231,69 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
 pub struct Application {
     port: u16,
     server: Server,
+    metrics_port: Option<u16>,
+    metrics_server: Option<Server>,
 }
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self> {
         let connection_pool = get_connection_pool(&configuration.database);
-        let email_client = configuration.email.client();
+        let email_client = configuration.email.clone().client();
+        let login_throttle = LoginThrottle::new(
+            &configuration.redis_uri,
+            configuration.login_throttle.clone(),
+        )
+        .await?;
 
         let listener = TcpListener::bind(configuration.application.address())?;
         let port = listener.local_addr().unwrap().port();
 
+        let (metrics_port, metrics_server) = match configuration.metrics.port {
+            Some(metrics_port) => {
+                let address = format!("{}:{}", configuration.metrics.host, metrics_port);
+                let listener = TcpListener::bind(address)?;
+                let metrics_port = listener.local_addr().unwrap().port();
+                let server = run_metrics_server(listener, connection_pool.clone())?;
+                (Some(metrics_port), Some(server))
+            }
+            None => (None, None),
+        };
+
         let server = run(
             listener,
             connection_pool,
             email_client,
-            configuration.application.base_url,
-            configuration.application.hmac_secret,
-            configuration.redis_uri,
+            login_throttle,
+            configuration,
         )
         .await?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            metrics_port,
+            metrics_server,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// `None` unless the metrics are served on a port of their own.
+    pub fn metrics_port(&self) -> Option<u16> {
+        self.metrics_port
+    }
+
     pub async fn run_until_stopped(self) -> Result<()> {
-        let _ = self.server.await;
+        match self.metrics_server {
+            Some(metrics_server) => {
+                let _ = tokio::join!(self.server, metrics_server);
+            }
+            None => {
+                let _ = self.server.await;
+            }
+        }
         Ok(())
     }
 }
@@ -1,10 +1,21 @@
+use std::collections::HashMap;
+
+use once_cell::sync::OnceCell;
+use opentelemetry::{global, trace::TracerProvider};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
 use tokio::task::JoinHandle;
-use tracing::{subscriber::set_global_default, Subscriber};
+use tracing::{subscriber::set_global_default, Span, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
 
+static TRACER_PROVIDER: OnceCell<SdkTracerProvider> = OnceCell::new();
+
 /// Compose multiple layers into a `tracing`'s subscriber.
+///
+/// Spans are also exported over OTLP when `OTEL_EXPORTER_OTLP_ENDPOINT` is
+/// set, see the `opentelemetry-otlp` crate for the other `OTEL_*` variables.
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
@@ -16,12 +27,38 @@ where
     // We are falling back to printing all spans at info-level or above if the RUST_LOG
     // environment variable has not been set.
     let env_filter = EnvFilter::try_from_default_env().unwrap_or(EnvFilter::new(env_filter));
+    let tracer = tracer_provider(&name).tracer(name.clone());
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
 
     Registry::default()
         .with(env_filter)
         .with(JsonStorageLayer)
         .with(formatting_layer)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Spans carry a W3C trace context either way, so that traces started by our
+/// callers go on through us even when we don't export our part of them.
+fn tracer_provider(service_name: &str) -> SdkTracerProvider {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let mut builder = SdkTracerProvider::builder().with_resource(
+        Resource::builder()
+            .with_service_name(service_name.to_owned())
+            .build(),
+    );
+    if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_some() {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .build()
+            .expect("Failed to build the OTLP exporter");
+        builder = builder.with_batch_exporter(exporter);
+    }
+    let provider = builder.build();
+
+    global::set_tracer_provider(provider.clone());
+    let _ = TRACER_PROVIDER.set(provider.clone());
+    provider
 }
 
 /// Register a subscriber as global default to process span data.
@@ -33,6 +70,36 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
 
+/// Export the spans that are still buffered. Call before exiting.
+pub fn shutdown_tracer_provider() {
+    if let Some(provider) = TRACER_PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to export the last spans"
+            );
+        }
+    }
+}
+
+/// The trace context of the current span (e.g. `traceparent`), to hand over
+/// to whatever carries on with the work.
+pub fn current_trace_context() -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Span::current().context(), &mut carrier)
+    });
+    carrier
+}
+
+/// Make `span` part of the trace `trace_context` was taken from. Must be called
+/// before the span is entered.
+pub fn continue_trace(span: &Span, trace_context: &HashMap<String, String>) {
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(trace_context));
+    let _ = span.set_parent(parent);
+}
+
 pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
 where
     F: FnOnce() -> R + Send + 'static,
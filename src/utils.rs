@@ -1,4 +1,4 @@
-use actix_web::HttpResponse;
+use actix_web::{dev::ServiceRequest, web, HttpResponse};
 use reqwest::header::LOCATION;
 
 pub fn e500<T>(e: T) -> actix_web::Error
@@ -20,3 +20,13 @@ pub fn see_other(location: &str) -> HttpResponse {
         .insert_header((LOCATION, location))
         .finish()
 }
+
+/// Read the whole body of a request in a middleware, leaving it in place for
+/// the handler to read as well.
+pub async fn buffer_body(req: &mut ServiceRequest) -> Result<web::Bytes, actix_web::Error> {
+    let body = req.extract::<web::Bytes>().await?;
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body.clone());
+    req.set_payload(payload.into());
+    Ok(body)
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use sqlx::{postgres::PgListener, PgPool, Postgres, Transaction};
+
+/// Notified when background jobs are queued.
+pub const JOBS_CHANNEL: &str = "jobs";
+/// Notified when outbox messages are queued.
+pub const OUTBOX_CHANNEL: &str = "outbox";
+
+/// Wake the workers listening on `channel` once `transaction` commits.
+/// Notifications are dropped along with the transaction if it rolls back.
+#[tracing::instrument(skip(transaction))]
+pub async fn notify(
+    transaction: &mut Transaction<'_, Postgres>,
+    channel: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("select pg_notify($1, '')", channel)
+        .execute(transaction.as_mut())
+        .await?;
+    Ok(())
+}
+
+/// Lets an idle worker sleep until there is work for it on a channel.
+///
+/// Workers keep polling every so often, to pick up work that became due with
+/// time and to get by while the listener connection is down.
+pub struct Wakeup {
+    pool: PgPool,
+    channel: &'static str,
+    listener: Option<PgListener>,
+}
+
+impl Wakeup {
+    /// Start listening straight away, so that nothing queued from now on is missed.
+    pub async fn listen(pool: &PgPool, channel: &'static str) -> Self {
+        let mut wakeup = Self {
+            pool: pool.clone(),
+            channel,
+            listener: None,
+        };
+        wakeup.connect().await;
+        wakeup
+    }
+
+    async fn connect(&mut self) {
+        let listener = async {
+            let mut listener = PgListener::connect_with(&self.pool).await?;
+            listener.listen(self.channel).await?;
+            Ok::<_, sqlx::Error>(listener)
+        };
+        match listener.await {
+            Ok(listener) => self.listener = Some(listener),
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    channel = self.channel,
+                    "Failed to listen for notifications, polling instead"
+                );
+            }
+        }
+    }
+
+    /// Wait for a notification, or for `poll_interval` to pass.
+    pub async fn wait(&mut self, poll_interval: Duration) {
+        if self.listener.is_none() {
+            self.connect().await;
+        }
+        let Some(listener) = self.listener.as_mut() else {
+            tokio::time::sleep(poll_interval).await;
+            return;
+        };
+        match tokio::time::timeout(poll_interval, listener.try_recv()).await {
+            Err(_) | Ok(Ok(Some(_))) => {}
+            // The listener reconnects on its own on the next wait, there is no
+            // telling what was queued in the meantime.
+            Ok(Ok(None)) => {
+                tracing::warn!(
+                    channel = self.channel,
+                    "Lost the connection listening for notifications"
+                );
+            }
+            Ok(Err(e)) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    channel = self.channel,
+                    "Failed to listen for notifications, polling instead"
+                );
+                self.listener = None;
+                tokio::time::sleep(poll_interval).await;
+            }
+        }
+    }
+}
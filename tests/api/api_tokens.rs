@@ -0,0 +1,282 @@
+use reqwest::Method;
+use uuid::Uuid;
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp, TestUser};
+
+fn issue_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+    })
+}
+
+async fn n_published_issues(app: &TestApp) -> i64 {
+    sqlx::query!(r#"select count(*) as "n!" from newsletter_issues where published_at is not null"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n
+}
+
+#[tokio::test]
+async fn requests_without_a_valid_token_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/api/v1/subscribers/count", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+
+    let response = app
+        .api_request(Method::GET, "subscribers/count", "not-a-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["error"],
+        "The API token is invalid, expired or revoked."
+    );
+}
+
+#[tokio::test]
+async fn tokens_are_stored_hashed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["drafts:write"]).await;
+
+    let stored = sqlx::query!("select token_hash, scopes from api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+    assert_eq!(stored.scopes, vec!["drafts:write"]);
+}
+
+#[tokio::test]
+async fn drafts_can_be_created_and_published() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app
+        .create_api_token(&["drafts:write", "issues:publish"])
+        .await;
+
+    let response = app
+        .api_request(Method::POST, "drafts", &token)
+        .json(&issue_body())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["published"], false);
+    let issue_id = body["newsletter_issue_id"].as_str().unwrap().to_owned();
+    assert_eq!(n_published_issues(&app).await, 0);
+
+    let response = app
+        .api_request(Method::POST, &format!("drafts/{issue_id}/publish"), &token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_published_issues(&app).await, 1);
+
+    let response = app
+        .api_request(Method::POST, &format!("drafts/{issue_id}/publish"), &token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 409);
+
+    let response = app
+        .api_request(
+            Method::POST,
+            &format!("drafts/{}/publish", Uuid::new_v4()),
+            &token,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn invalid_json_bodies_are_rejected_as_json() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["drafts:write"]).await;
+
+    let response = app
+        .api_request(Method::POST, "drafts", &token)
+        .json(&serde_json::json!({ "title": "Missing the content" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().contains("text_content"));
+}
+
+#[tokio::test]
+async fn tokens_can_only_be_used_within_their_scopes() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["subscribers:read"]).await;
+
+    let response = app
+        .api_request(Method::POST, "drafts", &token)
+        .json(&issue_body())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "The API token lacks the drafts:write scope.");
+}
+
+#[tokio::test]
+async fn publishing_is_idempotent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["issues:publish"]).await;
+
+    let response = app
+        .api_request(Method::POST, "issues", &token)
+        .json(&issue_body())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+
+    let idempotency_key = Uuid::new_v4().to_string();
+    let mut bodies = Vec::new();
+    for _ in 0..2 {
+        let response = app
+            .api_request(Method::POST, "issues", &token)
+            .header("Idempotency-Key", &idempotency_key)
+            .json(&issue_body())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 201);
+        bodies.push(response.json::<serde_json::Value>().await.unwrap());
+    }
+    assert_eq!(bodies[0], bodies[1]);
+    assert_eq!(n_published_issues(&app).await, 1);
+    let n_queued = sqlx::query!(r#"select count(*) as "n!" from jobs"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_queued, 1);
+}
+
+#[tokio::test]
+async fn subscriber_counts_can_be_queried() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        "insert into subscriptions (id, email, name, status) \
+        values ($1, 'pending@example.com', 'Pending', 'pending_confirmation')",
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["subscribers:read"]).await;
+
+    let response = app
+        .api_request(Method::GET, "subscribers/count", &token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({ "confirmed": 1, "pending_confirmation": 1 })
+    );
+}
+
+#[tokio::test]
+async fn revoked_and_expired_tokens_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let revoked_token = app.create_api_token(&["subscribers:read"]).await;
+    let token_id = sqlx::query!("select token_id from api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_id;
+    app.post_admin_form(&format!("api-tokens/{token_id}/revoke"), &())
+        .await;
+
+    let expired_token = app.create_api_token(&["subscribers:read"]).await;
+    sqlx::query!(
+        "update api_tokens set expires_at = now() - interval '1 minute' where token_id != $1",
+        token_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    for token in [revoked_token, expired_token] {
+        let response = app
+            .api_request(Method::GET, "subscribers/count", &token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+    }
+}
+
+#[tokio::test]
+async fn tokens_cannot_exceed_the_role_of_their_owner() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    let response = app
+        .post_admin_form(
+            "api-tokens",
+            &[("name", "CMS"), ("scope", "issues:publish")],
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 303);
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/api-tokens", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p><i>Your role does not allow the issues:publish scope.</i></p>"));
+
+    // Demoting the owner limits the tokens they already have.
+    let token = app.create_api_token(&["drafts:write"]).await;
+    sqlx::query!(
+        "update users set role = 'viewer' where user_id = $1",
+        editor.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let response = app
+        .api_request(Method::POST, "drafts", &token)
+        .json(&issue_body())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+}
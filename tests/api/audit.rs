@@ -0,0 +1,229 @@
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+
+struct RecordedEvent {
+    action: String,
+    actor_user_id: Option<Uuid>,
+    ip_address: Option<String>,
+    target: Option<String>,
+}
+
+async fn recorded_events(app: &TestApp) -> Vec<RecordedEvent> {
+    sqlx::query_as!(
+        RecordedEvent,
+        "select action, actor_user_id, ip_address, target from audit_log order by occurred_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+impl TestApp {
+    async fn get_audit_log(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/audit?{query}", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+}
+
+#[tokio::test]
+async fn logins_are_recorded_with_the_ip_address() {
+    let app = spawn_app().await;
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password"
+    }))
+    .await;
+    app.test_user.login(&app).await;
+
+    let events = recorded_events(&app).await;
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].action, "login.failed");
+    assert_eq!(events[0].actor_user_id, None);
+    assert_eq!(
+        events[0].target.as_deref(),
+        Some(app.test_user.username.as_str())
+    );
+    assert_eq!(events[0].ip_address.as_deref(), Some("127.0.0.1"));
+    assert_eq!(events[1].action, "login.succeeded");
+    assert_eq!(events[1].actor_user_id, Some(app.test_user.user_id));
+    assert_eq!(events[1].ip_address.as_deref(), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn password_changes_are_recorded() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let event = recorded_events(&app).await.pop().unwrap();
+    assert_eq!(event.action, "password.changed");
+    assert_eq!(event.actor_user_id, Some(app.test_user.user_id));
+}
+
+#[tokio::test]
+async fn publishing_an_issue_is_recorded() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+
+    let issue_id = sqlx::query!("select newsletter_issue_id from newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let event = recorded_events(&app).await.pop().unwrap();
+    assert_eq!(event.action, "issue.published");
+    assert_eq!(event.actor_user_id, Some(app.test_user.user_id));
+    assert_eq!(event.target, Some(issue_id.to_string()));
+}
+
+#[tokio::test]
+async fn only_owners_can_see_the_audit_log() {
+    let app = spawn_app().await;
+    let publisher = TestUser::generate_with_role("publisher");
+    publisher.store(&app.db_pool).await;
+    publisher.login(&app).await;
+
+    assert_eq!(app.get_audit_log("").await.status().as_u16(), 403);
+    let response = app
+        .api_client
+        .get(format!("{}/admin/audit/export", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_filtered() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+
+    let html_page = app
+        .get_audit_log(&format!("action=login&actor={}", editor.username))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(&editor.username));
+    assert!(html_page.contains("<td>login.succeeded</td>"));
+    assert!(!html_page.contains(&format!("<td>{}</td>", app.test_user.username)));
+
+    let html_page = app
+        .get_audit_log("action=password")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(!html_page.contains("<td>login.succeeded</td>"));
+
+    let html_page = app
+        .get_audit_log("since=yesterday")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p><i>yesterday is not a valid date, use YYYY-MM-DD.</i></p>"));
+}
+
+#[tokio::test]
+async fn the_audit_log_can_be_exported_as_csv() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/audit/export?action=login", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "occurred_at,actor,ip_address,action,target,details"
+    );
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains(&format!(
+        ",{},127.0.0.1,login.succeeded,",
+        app.test_user.username
+    )));
+}
+
+#[tokio::test]
+async fn exported_fields_cannot_start_a_formula() {
+    let app = spawn_app().await;
+    app.post_login(&serde_json::json!({
+        "username": "\t=HYPERLINK(\"https://example.com\")",
+        "password": "wrong-password"
+    }))
+    .await;
+    app.test_user.login(&app).await;
+
+    let csv = app
+        .api_client
+        .get(format!(
+            "{}/admin/audit/export?action=login.failed",
+            &app.address
+        ))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(csv.contains(",\"'\t=HYPERLINK(\"\"https://example.com\"\")\","));
+}
+
+#[tokio::test]
+async fn the_audit_log_is_append_only() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let update = sqlx::query!("update audit_log set details = 'nothing happened'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query!("delete from audit_log")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(update.is_err());
+    assert!(delete.is_err());
+    assert_eq!(recorded_events(&app).await.len(), 1);
+}
@@ -0,0 +1,217 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::send_rate_limit::SendRateLimiter;
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+    TestApp,
+};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_autoresponders_page() {
+    let app = spawn_app().await;
+
+    let response = app.get_autoresponders().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_create_a_sequence() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_autoresponder_sequence(&serde_json::json!({ "name": "Welcome" }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn sequence_name_must_not_be_empty() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_autoresponder_sequence(&serde_json::json!({ "name": " " }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/autoresponders");
+
+    let html_page = app.get_autoresponders_html().await;
+    assert!(html_page.contains("<p><i>The sequence name cannot be empty.</i></p>"));
+}
+
+#[tokio::test]
+async fn created_sequences_and_steps_are_listed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let sequence_id = create_sequence(&app, "Welcome sequence").await;
+    add_step(&app, sequence_id, 3, "Best of").await;
+
+    let html_page = app.get_autoresponders_html().await;
+    assert!(html_page.contains("<p><i>The autoresponder step has been added.</i></p>"));
+    assert!(html_page.contains("Welcome sequence"));
+    assert!(html_page.contains("Day 3: Best of"));
+}
+
+#[tokio::test]
+async fn unconfirmed_subscribers_are_not_enrolled() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let sequence_id = create_sequence(&app, "Welcome sequence").await;
+    add_step(&app, sequence_id, 0, "Welcome").await;
+
+    create_unconfirmed_subscriber(&app).await;
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_autoresponders().await;
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_receive_each_step_once_when_it_is_due() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let sequence_id = create_sequence(&app, "Welcome sequence").await;
+    add_step(&app, sequence_id, 0, "Welcome").await;
+    add_step(&app, sequence_id, 3, "Best of").await;
+
+    create_confirmed_subscriber(&app).await;
+
+    // Day 0: only the welcome email is due, and running the worker again must not
+    // send it a second time.
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Day 0 step")
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.dispatch_all_pending_autoresponders().await;
+        app.dispatch_all_pending_autoresponders().await;
+    }
+
+    // Day 3
+    sqlx::query!(
+        "update autoresponder_enrollments set enrolled_at = enrolled_at - interval '3 days'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Day 3 step")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_autoresponders().await;
+}
+
+#[tokio::test]
+async fn steps_that_cannot_be_delivered_are_retried() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let sequence_id = create_sequence(&app, "Welcome sequence").await;
+    add_step(&app, sequence_id, 0, "Welcome").await;
+    create_confirmed_subscriber(&app).await;
+
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.dispatch_all_pending_autoresponders().await;
+    }
+    let n_delivered = sqlx::query!(r#"select count(*) as "n!" from autoresponder_deliveries"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_delivered, 0);
+
+    // Once the backoff is over.
+    sqlx::query!("update jobs set run_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_autoresponders().await;
+    app.dispatch_all_pending_autoresponders().await;
+}
+
+#[tokio::test]
+async fn steps_beyond_the_send_budget_are_deferred() {
+    let mut app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let sequence_id = create_sequence(&app, "Welcome sequence").await;
+    add_step(&app, sequence_id, 0, "Welcome").await;
+    create_confirmed_subscriber(&app).await;
+    app.rate_limiter = SendRateLimiter::new(Some(1), []);
+    // Use up this minute's budget, if the confirmation email has not already.
+    app.rate_limiter
+        .try_acquire(&app.db_pool, "example.com")
+        .await
+        .unwrap();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_autoresponders().await;
+
+    let job = sqlx::query!(
+        r#"select n_attempts, run_after > now() as "deferred!" from jobs where kind = 'deliver_autoresponder_step'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(job.deferred);
+    assert_eq!(job.n_attempts, 0);
+}
+
+async fn create_sequence(app: &TestApp, name: &str) -> Uuid {
+    let response = app
+        .post_autoresponder_sequence(&serde_json::json!({ "name": name }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/autoresponders");
+
+    sqlx::query!(
+        "select sequence_id from autoresponder_sequences where name = $1",
+        name
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .sequence_id
+}
+
+async fn add_step(app: &TestApp, sequence_id: Uuid, delay_days: i32, title: &str) {
+    let response = app
+        .post_autoresponder_step(&serde_json::json!({
+            "sequence_id": sequence_id,
+            "delay_days": delay_days,
+            "title": title,
+            "text_content": "Autoresponder body as plain text",
+            "html_content": "<p>Autoresponder body as HTML</p>",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/autoresponders");
+}
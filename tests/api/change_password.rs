@@ -122,3 +122,75 @@ async fn changing_password_works() {
         .await;
     assert_is_redirect_to(&response, "/admin/dashboard");
 }
+
+#[tokio::test]
+async fn each_password_policy_violation_is_reported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let new_password = app.test_user.username.clone();
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>The new password must not contain your username.</i></p>"));
+    assert!(!html_page.contains("Your password has been changed."));
+
+    for new_password in ["short", "aaaaaaaaaaaaaaaa", "Password1234"] {
+        app.post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": new_password,
+            "new_password_check": new_password
+        }))
+        .await;
+        let html_page = app.get_change_password_html().await;
+        match new_password {
+            "short" => {
+                assert!(html_page.contains(
+                    "<p><i>The new password must be at least 12 characters long.</i></p>"
+                ));
+                assert!(html_page.contains("<p><i>The new password is too predictable"));
+            }
+            "aaaaaaaaaaaaaaaa" => {
+                assert!(html_page.contains("<p><i>The new password is too predictable"));
+                assert!(!html_page.contains("characters long"));
+            }
+            _ => assert!(html_page.contains(
+                "<p><i>The new password is too common, please choose another one.</i></p>"
+            )),
+        }
+    }
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn the_current_password_cannot_be_reused() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &app.test_user.password,
+            "new_password_check": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page
+        .contains("<p><i>The new password must be different from the current one.</i></p>"));
+}
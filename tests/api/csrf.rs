@@ -0,0 +1,86 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn post_logout_with_token(app: &TestApp, csrf_token: Option<&str>) -> reqwest::Response {
+    let form: Vec<_> = csrf_token.map(|t| ("csrf_token", t)).into_iter().collect();
+    app.api_client
+        .post(format!("{}/admin/logout", &app.address))
+        .form(&form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn admin_forms_embed_the_csrf_token_of_the_session() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csrf_token = app
+        .csrf_token()
+        .await
+        .expect("No CSRF token in the dashboard");
+
+    let html_page = app.get_change_password_html().await;
+    let field = format!(r#"<input type="hidden" name="csrf_token" value="{csrf_token}" />"#);
+    // The password form and the two-factor setup form.
+    assert_eq!(html_page.matches(&field).count(), 2);
+}
+
+#[tokio::test]
+async fn forms_without_a_csrf_token_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = post_logout_with_token(&app, None).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This form has expired or did not come from this site"));
+    // Still logged in.
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn forms_with_a_wrong_csrf_token_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = post_logout_with_token(&app, Some("not-the-token")).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn forms_rendered_for_a_previous_session_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let stale_token = app.csrf_token().await.unwrap();
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+
+    let response = post_logout_with_token(&app, Some(&stale_token)).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_ne!(app.csrf_token().await.unwrap(), stale_token);
+}
+
+#[tokio::test]
+async fn forms_with_the_csrf_token_of_the_session_are_accepted() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csrf_token = app.csrf_token().await.unwrap();
+
+    let response = post_logout_with_token(&app, Some(&csrf_token)).await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn anonymous_users_are_sent_to_the_login_page_before_any_csrf_check() {
+    let app = spawn_app().await;
+
+    let response = post_logout_with_token(&app, None).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
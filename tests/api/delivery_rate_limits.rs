@@ -0,0 +1,153 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::send_rate_limit::SendRateLimiter;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn insert_confirmed_subscriber(app: &TestApp, email: &str) {
+    sqlx::query!(
+        "insert into subscriptions (id, email, name, status) \
+        values ($1, $2, 'Subscriber', 'confirmed')",
+        Uuid::new_v4(),
+        email
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn publish_issue(app: &TestApp) {
+    app.test_user.login(app).await;
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+async fn n_deferred_tasks(app: &TestApp) -> i64 {
+    sqlx::query!(r#"select count(*) as "count!" from jobs where run_after > now()"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn emails_beyond_the_domain_budget_are_deferred() {
+    let mut app = spawn_app().await;
+    app.rate_limiter = SendRateLimiter::new(None, [("GMail.com".to_string(), 1)]);
+    insert_confirmed_subscriber(&app, "first@gmail.com").await;
+    insert_confirmed_subscriber(&app, "second@gmail.com").await;
+    insert_confirmed_subscriber(&app, "third@example.com").await;
+    publish_issue(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(n_deferred_tasks(&app).await, 1);
+}
+
+#[tokio::test]
+async fn emails_beyond_the_global_budget_are_deferred() {
+    let mut app = spawn_app().await;
+    app.rate_limiter = SendRateLimiter::new(Some(1), []);
+    insert_confirmed_subscriber(&app, "first@gmail.com").await;
+    insert_confirmed_subscriber(&app, "second@example.com").await;
+    publish_issue(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(n_deferred_tasks(&app).await, 1);
+}
+
+#[tokio::test]
+async fn budgets_are_shared_between_workers() {
+    let mut app = spawn_app().await;
+    app.rate_limiter = SendRateLimiter::new(Some(2), []);
+    insert_confirmed_subscriber(&app, "first@gmail.com").await;
+    publish_issue(&app).await;
+    // Another worker instance has already used part of this minute's budget.
+    assert!(app
+        .rate_limiter
+        .try_acquire(&app.db_pool, "example.com")
+        .await
+        .unwrap());
+    assert!(app
+        .rate_limiter
+        .try_acquire(&app.db_pool, "example.com")
+        .await
+        .unwrap());
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(n_deferred_tasks(&app).await, 1);
+}
+
+#[tokio::test]
+async fn confirmation_emails_beyond_the_budget_wait_in_the_outbox() {
+    let mut app = spawn_app().await;
+    app.rate_limiter = SendRateLimiter::new(Some(1), []);
+    assert!(app
+        .rate_limiter
+        .try_acquire(&app.db_pool, "example.com")
+        .await
+        .unwrap());
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_outbox().await;
+
+    let message =
+        sqlx::query!(r#"select n_attempts, execute_after > now() as "deferred!" from outbox"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert!(message.deferred);
+    assert_eq!(message.n_attempts, 0);
+}
+
+#[tokio::test]
+async fn budgets_beyond_what_a_counter_can_hold_do_not_block_sending() {
+    let app = spawn_app().await;
+    let rate_limiter = SendRateLimiter::new(Some(u32::MAX), [("gmail.com".to_string(), u32::MAX)]);
+
+    assert!(rate_limiter
+        .try_acquire(&app.db_pool, "gmail.com")
+        .await
+        .unwrap());
+}
@@ -0,0 +1,103 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+#[tokio::test]
+async fn feeds_are_available_before_any_issue_is_published() {
+    let app = spawn_app().await;
+
+    for feed in ["feed.atom", "feed.rss"] {
+        let response = app.get_feed(feed).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn atom_feed_contains_published_issues() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    publish_issue(&app).await;
+
+    let response = app.get_feed("feed.atom").await;
+
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/atom+xml; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    assert!(body.contains("<title>Feed &amp; friends</title>"));
+    assert!(body.contains("<author>\n        <name>Newsletter</name>\n    </author>"));
+    let issue_id = published_issue_id(&app).await;
+    assert!(body.contains(&format!("<id>urn:uuid:{issue_id}</id>")));
+    assert!(body.contains(&format!(
+        r#"<link rel="alternate" href="http://127.0.0.1/issues/{issue_id}" />"#
+    )));
+    assert!(body
+        .contains(r#"<content type="html">&lt;p&gt;Newsletter body as HTML&lt;/p&gt;</content>"#));
+}
+
+#[tokio::test]
+async fn rss_feed_contains_published_issues() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    publish_issue(&app).await;
+
+    let response = app.get_feed("feed.rss").await;
+
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/rss+xml; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    assert!(body.contains("<title>Feed &amp; friends</title>"));
+    assert!(body.contains(r#"<guid isPermaLink="false">urn:uuid:"#));
+    assert!(body.contains("<description>&lt;p&gt;Newsletter body as HTML&lt;/p&gt;</description>"));
+}
+
+#[tokio::test]
+async fn published_issues_have_a_web_page() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    publish_issue(&app).await;
+    let issue_id = published_issue_id(&app).await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/issues/{issue_id}", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<h1>Feed &amp; friends</h1>"));
+    assert!(html_page.contains("<p>Newsletter body as HTML</p>"));
+
+    sqlx::query!("update newsletter_issues set published_at = null")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app
+        .api_client
+        .get(format!("{}/issues/{issue_id}", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+async fn published_issue_id(app: &TestApp) -> uuid::Uuid {
+    sqlx::query_scalar!("select newsletter_issue_id from newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn publish_issue(app: &TestApp) {
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Feed & friends",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
@@ -0,0 +1,235 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::{
+    configuration::get_configuration,
+    feed_importer::{import_new_entries, ImportFeed},
+    jobs::NewJob,
+};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+fn rss_feed(items: &[(&str, &str)]) -> String {
+    let items: String = items
+        .iter()
+        .map(|(guid, title)| {
+            format!(
+                r#"<item>
+                    <guid>{guid}</guid>
+                    <title>{title}</title>
+                    <link>https://blog.example.com/{guid}</link>
+                    <description>&lt;p&gt;Summary of {title}&lt;/p&gt;</description>
+                </item>"#
+            )
+        })
+        .collect();
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0"><channel><title>Blog</title>{items}</channel></rss>"#
+    )
+}
+
+async fn serve_feed(feed_server: &MockServer, items: &[(&str, &str)]) {
+    serve_feed_body(feed_server, rss_feed(items)).await;
+}
+
+async fn serve_feed_body(feed_server: &MockServer, body: String) {
+    feed_server.reset().await;
+    Mock::given(path("/feed.rss"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(body))
+        .mount(feed_server)
+        .await;
+}
+
+async fn import(app: &TestApp, feed_server: &MockServer, auto_publish: bool) -> usize {
+    let mut settings = get_configuration().unwrap().feed_import;
+    settings.auto_publish = auto_publish;
+    import_new_entries(
+        &app.db_pool,
+        &reqwest::Client::new(),
+        &format!("{}/feed.rss", feed_server.uri()),
+        &settings,
+    )
+    .await
+    .unwrap()
+}
+
+/// What the worker does when the recurring import job comes up.
+async fn run_import_job(app: &TestApp) {
+    let mut tx = app.db_pool.begin().await.unwrap();
+    NewJob::new(ImportFeed).enqueue(&mut tx).await.unwrap();
+    tx.commit().await.unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn entries_present_on_the_first_poll_are_not_imported() {
+    let app = spawn_app().await;
+    let feed_server = MockServer::start().await;
+    serve_feed(&feed_server, &[("first-post", "First post")]).await;
+
+    let n_imported = import(&app, &feed_server, false).await;
+
+    assert_eq!(n_imported, 0);
+    let n_issues = sqlx::query!(r#"select count(*) as "count!" from newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn new_entries_are_imported_as_drafts() {
+    let app = spawn_app().await;
+    let feed_server = MockServer::start().await;
+    serve_feed(&feed_server, &[("first-post", "First post")]).await;
+    import(&app, &feed_server, false).await;
+
+    serve_feed(
+        &feed_server,
+        &[("first-post", "First post"), ("second-post", "Second post")],
+    )
+    .await;
+    let n_imported = import(&app, &feed_server, false).await;
+
+    assert_eq!(n_imported, 1);
+    let issue = sqlx::query!(
+        "select title, text_content, html_content, published_at from newsletter_issues"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.title, "Second post");
+    assert_eq!(
+        issue.text_content,
+        "Second post\n\nSummary of Second post\n\nRead more: https://blog.example.com/second-post"
+    );
+    assert!(issue.html_content.contains("<p>Summary of Second post</p>"));
+    assert!(issue
+        .html_content
+        .contains(r#"<a href="https://blog.example.com/second-post">"#));
+    assert!(issue.published_at.is_none());
+
+    app.test_user.login(&app).await;
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("Second post"));
+}
+
+#[tokio::test]
+async fn entries_are_imported_only_once() {
+    let app = spawn_app().await;
+    let feed_server = MockServer::start().await;
+    serve_feed(&feed_server, &[]).await;
+    import(&app, &feed_server, false).await;
+    serve_feed(&feed_server, &[("first-post", "First post")]).await;
+    import(&app, &feed_server, false).await;
+
+    let n_imported = import(&app, &feed_server, false).await;
+
+    assert_eq!(n_imported, 0);
+}
+
+#[tokio::test]
+async fn imported_entries_are_delivered_when_auto_publish_is_enabled() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let feed_server = MockServer::start().await;
+    serve_feed(&feed_server, &[("first-post", "First post")]).await;
+    import(&app, &feed_server, true).await;
+
+    serve_feed(
+        &feed_server,
+        &[("first-post", "First post"), ("second-post", "Second post")],
+    )
+    .await;
+    import(&app, &feed_server, true).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn the_feed_is_polled_by_a_recurring_job() {
+    let mut app = spawn_app().await;
+    let feed_server = MockServer::start().await;
+    app.feed_import_settings.feed_url = Some(format!("{}/feed.rss", feed_server.uri()));
+
+    serve_feed(&feed_server, &[("first-post", "First post")]).await;
+    run_import_job(&app).await;
+    serve_feed(
+        &feed_server,
+        &[("first-post", "First post"), ("second-post", "Second post")],
+    )
+    .await;
+    run_import_job(&app).await;
+
+    let titles: Vec<String> = sqlx::query_scalar!("select title from newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(titles, ["Second post"]);
+}
+
+#[tokio::test]
+async fn scripts_in_imported_entries_do_not_reach_readers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let feed_server = MockServer::start().await;
+    serve_feed(&feed_server, &[("first-post", "First post")]).await;
+    import(&app, &feed_server, true).await;
+    serve_feed_body(
+        &feed_server,
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0"><channel><title>Blog</title><item>
+    <guid>evil-post</guid>
+    <title>Evil post</title>
+    <link>javascript:alert(1)</link>
+    <description>&lt;p onclick="alert(1)"&gt;Hello&lt;/p&gt;&lt;script&gt;alert(1)&lt;/script&gt;&lt;img src="x" onerror="alert(1)"&gt;</description>
+</item></channel></rss>"#
+            .into(),
+    )
+    .await;
+    import(&app, &feed_server, true).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let issue_id = sqlx::query_scalar!("select newsletter_issue_id from newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let html_page = app
+        .api_client
+        .get(format!("{}/issues/{issue_id}", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    for html in [email["HtmlBody"].as_str().unwrap(), &html_page] {
+        assert!(html.contains("<p>Hello</p>"));
+        for payload in ["<script", "onclick", "onerror", "javascript:"] {
+            assert!(!html.contains(payload), "{payload} was not removed");
+        }
+    }
+}
@@ -1,16 +1,111 @@
-use crate::helpers::spawn_app;
+use zero2prod::health::{record_heartbeat, JOBS_WORKER, OUTBOX_RELAY};
+
+use crate::helpers::{spawn_app, TestApp};
+
+impl TestApp {
+    async fn get_readiness(&self) -> (u16, serde_json::Value) {
+        let response = self
+            .api_client
+            .get(format!("{}/health/ready", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        let status = response.status().as_u16();
+        (status, response.json().await.unwrap())
+    }
+
+    async fn record_worker_heartbeats(&self) {
+        for worker in [JOBS_WORKER, OUTBOX_RELAY] {
+            record_heartbeat(&self.db_pool, worker).await.unwrap();
+        }
+    }
+}
 
 #[tokio::test]
 async fn health_check_works() {
     let app = spawn_app().await;
     let client = reqwest::Client::new();
 
-    let response = client
-        .get(format!("{}/health_check", &app.address))
-        .send()
-        .await
-        .expect("Failed to execute request");
+    for path in ["health_check", "health/live"] {
+        let response = client
+            .get(format!("{}/{}", &app.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request");
+
+        assert!(response.status().is_success());
+        assert_eq!(Some(0), response.content_length());
+    }
+}
+
+#[tokio::test]
+async fn ready_when_every_dependency_is_healthy() {
+    let app = spawn_app().await;
+    app.record_worker_heartbeats().await;
+
+    let (status, body) = app.get_readiness().await;
+
+    assert_eq!(status, 200);
+    assert_eq!(body["status"], "healthy");
+    for check in ["database", "migrations", "redis", JOBS_WORKER, OUTBOX_RELAY] {
+        assert_eq!(body["checks"][check]["status"], "healthy", "{check}");
+        assert!(body["checks"][check]["latency_ms"].is_number(), "{check}");
+    }
+}
+
+#[tokio::test]
+async fn not_ready_until_the_workers_have_been_seen_alive() {
+    let app = spawn_app().await;
+
+    let (status, body) = app.get_readiness().await;
+
+    assert_eq!(status, 503);
+    assert_eq!(body["status"], "unhealthy");
+    assert_eq!(body["checks"]["database"]["status"], "healthy");
+    assert_eq!(body["checks"][JOBS_WORKER]["status"], "unhealthy");
+    assert!(body["checks"][JOBS_WORKER]["error"]
+        .as_str()
+        .unwrap()
+        .contains("never been seen alive"));
+}
+
+#[tokio::test]
+async fn not_ready_when_a_worker_heartbeat_is_stale() {
+    let app = spawn_app().await;
+    app.record_worker_heartbeats().await;
+    sqlx::query!(
+        "update worker_heartbeats set beat_at = now() - interval '1 hour' where worker = $1",
+        OUTBOX_RELAY
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let (status, body) = app.get_readiness().await;
+
+    assert_eq!(status, 503);
+    assert_eq!(body["checks"][JOBS_WORKER]["status"], "healthy");
+    assert_eq!(body["checks"][OUTBOX_RELAY]["status"], "unhealthy");
+}
+
+#[tokio::test]
+async fn not_ready_while_migrations_are_pending() {
+    let app = spawn_app().await;
+    app.record_worker_heartbeats().await;
+    // Not checked at compile time, the table only exists once `sqlx` ran the migrations.
+    sqlx::query(
+        "delete from _sqlx_migrations where version = (select max(version) from _sqlx_migrations)",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let (status, body) = app.get_readiness().await;
 
-    assert!(response.status().is_success());
-    assert_eq!(Some(0), response.content_length());
+    assert_eq!(status, 503);
+    assert_eq!(body["checks"]["migrations"]["status"], "unhealthy");
+    assert_eq!(
+        body["checks"]["migrations"]["error"],
+        "Migrations pending: 1"
+    );
 }
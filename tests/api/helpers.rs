@@ -1,13 +1,26 @@
+use std::net::Ipv4Addr;
+
 use anyhow::Result;
 use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::Name;
+use fake::Fake;
 use once_cell::sync::Lazy;
+use secrecy::Secret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    autoresponder::EnqueueDueAutoresponderSteps,
+    configuration::{
+        get_configuration, DatabaseSettings, FeedImportSettings, IdempotencySettings, JobsSettings,
+        OutboxSettings,
+    },
     email_client::EmailClient,
-    issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+    jobs::{job_registry, try_execute_job, ExecutionOutcome, JobContext, NewJob},
+    outbox::try_deliver_message,
+    send_rate_limit::SendRateLimiter,
     startup::{get_connection_pool, Application},
     telemetry::{get_subscriber, init_subscriber},
 };
@@ -40,17 +53,26 @@ pub struct ConfirmationLinks {
 pub struct TestApp {
     pub port: u16,
     pub address: String,
+    /// Serves the metrics without an API token.
+    pub metrics_address: String,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    /// Stands in for the OpenID Connect provider.
+    pub oidc_server: MockServer,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
     pub email_client: EmailClient,
+    pub rate_limiter: SendRateLimiter,
+    pub jobs_settings: JobsSettings,
+    pub outbox_settings: OutboxSettings,
+    pub idempotency_settings: IdempotencySettings,
+    pub feed_import_settings: FeedImportSettings,
 }
 
 impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", self.address))
+            .post(format!("{}/subscriptions", self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -73,15 +95,15 @@ impl TestApp {
             confirmation_link.set_port(Some(self.port)).unwrap();
             confirmation_link
         };
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks { html, plain_text }
     }
 
     pub async fn post_login<Body: serde::Serialize>(&self, body: &Body) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .form(body)
             .send()
             .await
@@ -90,7 +112,7 @@ impl TestApp {
 
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
@@ -99,17 +121,65 @@ impl TestApp {
             .unwrap()
     }
 
-    pub async fn post_logout(&self) -> reqwest::Response {
+    pub async fn post_forgot_password(&self, username: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/forgot", &self.address))
+            .form(&serde_json::json!({ "username": username }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_reset_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
         self.api_client
-            .post(&format!("{}/admin/logout", self.address))
+            .post(format!("{}/login/reset", &self.address))
+            .form(body)
             .send()
             .await
             .expect("Failed to execute request")
     }
 
+    /// The CSRF token embedded in the admin forms, if the user is logged in.
+    pub async fn csrf_token(&self) -> Option<String> {
+        self.get_admin_dashboard_html()
+            .await
+            .split(r#"name="csrf_token" value=""#)
+            .nth(1)
+            .and_then(|s| s.split('"').next())
+            .map(str::to_owned)
+    }
+
+    /// Submit an admin form, with the CSRF token a browser would have sent along.
+    pub async fn post_admin_form<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        let mut form = serde_urlencoded::to_string(body).unwrap();
+        if let Some(csrf_token) = self.csrf_token().await {
+            if !form.is_empty() {
+                form.push('&');
+            }
+            form.push_str(&format!("csrf_token={csrf_token}"));
+        }
+        self.api_client
+            .post(format!("{}/admin/{}", &self.address, path))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(form)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.post_admin_form("logout", &()).await
+    }
+
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
@@ -121,7 +191,7 @@ impl TestApp {
 
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
@@ -135,17 +205,29 @@ impl TestApp {
         &self,
         body: &Body,
     ) -> reqwest::Response {
+        self.post_admin_form("password", body).await
+    }
+
+    pub async fn post_two_factor_setup<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_admin_form(&format!("password/{path}"), body)
+            .await
+    }
+
+    pub async fn post_login_second_factor(&self, code: &str) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
-            .form(&body)
+            .post(format!("{}/login/totp", &self.address))
+            .form(&serde_json::json!({ "code": code }))
             .send()
             .await
-            .expect("Failed to execute request")
+            .expect("Failed to execute request.")
     }
 
     pub async fn get_publish_newsletter(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/newsletters", &self.address))
+            .get(format!("{}/admin/newsletters", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -156,21 +238,179 @@ impl TestApp {
     }
 
     pub async fn post_publish_newsletter<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_admin_form("newsletters", body).await
+    }
+
+    pub async fn post_publish_draft<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_admin_form("newsletters/drafts", body).await
+    }
+
+    pub async fn get_feed(&self, feed: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/{}", &self.address, feed))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issues(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/issues", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issues_html(&self) -> String {
+        self.get_issues().await.text().await.unwrap()
+    }
+
+    pub async fn post_issue_action<Body>(
+        &self,
+        newsletter_issue_id: uuid::Uuid,
+        action: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_admin_form(&format!("issues/{newsletter_issue_id}/{action}"), body)
+            .await
+    }
+
+    pub async fn get_users(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_users_html(&self) -> String {
+        self.get_users().await.text().await.unwrap()
+    }
+
+    /// Create an API token for the logged in user through the admin pages.
+    pub async fn create_api_token(&self, scopes: &[&str]) -> String {
+        let mut form = vec![("name", "CMS"), ("expires_in_days", "30")];
+        form.extend(scopes.iter().map(|s| ("scope", *s)));
+        let html_page = self
+            .post_admin_form("api-tokens", &form)
+            .await
+            .text()
+            .await
+            .unwrap();
+        html_page
+            .split("<code>")
+            .nth(1)
+            .and_then(|s| s.split("</code>").next())
+            .expect("No API token in the page")
+            .to_owned()
+    }
+
+    pub fn api_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        token: &str,
+    ) -> reqwest::RequestBuilder {
+        self.api_client
+            .request(method, format!("{}/api/v1/{}", &self.address, path))
+            .bearer_auth(token)
+    }
+
+    pub async fn get_sessions_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_sessions(&self, path: &str) -> reqwest::Response {
+        self.post_admin_form(&format!("sessions/{path}"), &()).await
+    }
+
+    pub async fn post_users<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_admin_form(&format!("users/{path}"), body).await
+    }
+
+    pub async fn post_signup<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/newsletters", &self.address))
+            .post(format!("{}/signup", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_autoresponders(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/autoresponders", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_autoresponders_html(&self) -> String {
+        self.get_autoresponders().await.text().await.unwrap()
+    }
+
+    pub async fn post_autoresponder_sequence<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_admin_form("autoresponders", body).await
+    }
+
+    pub async fn post_autoresponder_step<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.post_admin_form("autoresponders/steps", body).await
+    }
+
+    /// Stands in for the recurring job, sending the autoresponder steps that are due.
+    pub async fn dispatch_all_pending_autoresponders(&self) {
+        let mut tx = self.db_pool.begin().await.unwrap();
+        NewJob::new(EnqueueDueAutoresponderSteps)
+            .enqueue(&mut tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        self.dispatch_all_pending_emails().await;
+    }
+
+    /// Stands in for the background worker, running the jobs that are due.
     pub async fn dispatch_all_pending_emails(&self) {
+        let registry = job_registry();
+        let feed_client = self.feed_import_settings.client();
+        let context = JobContext {
+            pool: &self.db_pool,
+            email_client: &self.email_client,
+            rate_limiter: &self.rate_limiter,
+            idempotency: &self.idempotency_settings,
+            feed_import: &self.feed_import_settings,
+            feed_client: &feed_client,
+        };
         loop {
             if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
+                try_execute_job(&registry, &context, &self.jobs_settings)
                     .await
                     .unwrap()
             {
@@ -178,6 +418,23 @@ impl TestApp {
             }
         }
     }
+
+    /// Stands in for the outbox relay, delivering the messages that are due.
+    pub async fn dispatch_outbox(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_deliver_message(
+                &self.db_pool,
+                &self.email_client,
+                &self.rate_limiter,
+                &self.outbox_settings,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
@@ -186,6 +443,7 @@ pub async fn spawn_app() -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
+    let oidc_server = MockServer::start().await;
 
     let configuration = {
         let mut c = get_configuration().expect("Failed to get test configuration");
@@ -195,6 +453,16 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
         // Use the mock server as email API.
         c.email.base_url = email_server.uri();
+        // Keep failed login counters apart, every test logs in from the same IP.
+        c.login_throttle.key_prefix = format!("login_throttle:{}", Uuid::new_v4());
+        c.login_throttle.base_delay_millis = 10;
+        // Tests stand in for a proxy to log in from different IPs.
+        c.application.trusted_proxies = vec![Ipv4Addr::LOCALHOST.into()];
+        c.oidc.issuer_url = Some(oidc_server.uri());
+        c.oidc.client_secret = Some(Secret::new("oidc-client-secret".into()));
+        // Keep tests of concurrent requests with the same idempotency key short.
+        c.idempotency.wait_timeout_millis = 1000;
+        c.metrics.port = Some(0);
         c
     };
 
@@ -207,8 +475,14 @@ pub async fn spawn_app() -> TestApp {
         .expect("Failed to build test application");
     let port = application.port();
     let address = format!("http://127.0.0.1:{}", application.port());
+    let metrics_address = format!(
+        "http://127.0.0.1:{}",
+        application
+            .metrics_port()
+            .expect("The metrics port is not set")
+    );
 
-    let _ = tokio::spawn(application.run_until_stopped());
+    drop(tokio::spawn(application.run_until_stopped()));
 
     let client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
@@ -219,11 +493,18 @@ pub async fn spawn_app() -> TestApp {
     let test_app = TestApp {
         port,
         address,
+        metrics_address,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
+        oidc_server,
         test_user: TestUser::generate(),
         api_client: client,
         email_client: configuration.email.client(),
+        rate_limiter: configuration.delivery.rate_limiter(),
+        jobs_settings: configuration.jobs.clone(),
+        outbox_settings: configuration.outbox.clone(),
+        idempotency_settings: configuration.idempotency.clone(),
+        feed_import_settings: configuration.feed_import.clone(),
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
@@ -249,18 +530,24 @@ pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub role: &'static str,
 }
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::generate_with_role("owner")
+    }
+
+    pub fn generate_with_role(role: &'static str) -> Self {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            role,
         }
     }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::new(
             Algorithm::Argon2id,
@@ -272,11 +559,12 @@ impl TestUser {
         .to_string();
 
         sqlx::query!(
-            "insert into users (user_id, username, password_hash)
-            values ($1, $2, $3)",
+            "insert into users (user_id, username, password_hash, role)
+            values ($1, $2, $3, $4)",
             self.user_id,
             self.username,
             password_hash,
+            self.role,
         )
         .execute(pool)
         .await
@@ -292,6 +580,50 @@ impl TestUser {
     }
 }
 
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_links = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": name,
+        "email": email
+    }))
+    .unwrap();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_outbox().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
@@ -0,0 +1,461 @@
+use std::{num::NonZeroU32, time::Duration};
+
+use reqwest::Method;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use zero2prod::idempotency::prune_expired_records;
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+const SUBSCRIPTION: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn subscribe_with_key(
+    app: &TestApp,
+    body: &str,
+    idempotency_key: &str,
+    user_agent: &str,
+) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Idempotency-Key", idempotency_key)
+        .header("User-Agent", user_agent)
+        .body(body.to_owned())
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn anonymous_retries_with_the_same_key_are_performed_once() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    for _ in 0..2 {
+        let response = subscribe_with_key(&app, SUBSCRIPTION, &idempotency_key, "browser").await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    app.dispatch_outbox().await;
+}
+
+#[tokio::test]
+async fn anonymous_keys_are_not_shared_between_clients() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    for user_agent in ["one browser", "another browser"] {
+        let response = subscribe_with_key(&app, SUBSCRIPTION, &idempotency_key, user_agent).await;
+        assert_eq!(response.status().as_u16(), 200);
+        // Subscribing starts by clearing the subscriptions, which their tokens prevent.
+        sqlx::query!("delete from subscription_tokens")
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+    app.dispatch_outbox().await;
+}
+
+#[tokio::test]
+async fn failures_on_our_side_are_not_replayed() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    sqlx::query!("alter table outbox rename column payload to broken")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = subscribe_with_key(&app, SUBSCRIPTION, &idempotency_key, "browser").await;
+    assert_eq!(response.status().as_u16(), 500);
+    sqlx::query!("alter table outbox rename column broken to payload")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = subscribe_with_key(&app, SUBSCRIPTION, &idempotency_key, "browser").await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_outbox().await;
+}
+
+#[tokio::test]
+async fn changes_are_rolled_back_when_the_response_cannot_be_saved() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["issues:publish"]).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    sqlx::query!(
+        r#"
+        create function refuse_update() returns trigger as $$
+        begin
+            raise exception 'Refusing to save the response';
+        end
+        $$ language plpgsql
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "create trigger refuse_update before update on idempotency \
+        for each row execute function refuse_update()"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let publish = || {
+        app.api_request(Method::POST, "issues", &token)
+            .header("Idempotency-Key", &idempotency_key)
+            .json(&serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+            }))
+            .send()
+    };
+
+    let response = publish().await.unwrap();
+    assert_eq!(response.status().as_u16(), 500);
+    let n_issues = sqlx::query!(r#"select count(*) as "n!" from newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 0);
+
+    sqlx::query!("drop trigger refuse_update on idempotency")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = publish().await.unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    let n_queued = sqlx::query!(r#"select count(*) as "n!" from jobs"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_queued, 1);
+}
+
+#[tokio::test]
+async fn retries_of_a_draft_save_get_the_flash_message_again() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let body = serde_json::json!({
+        "title": "Draft title",
+        "text_content": "Draft body",
+        "html_content": "<p>Draft body</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+
+    for _ in 0..2 {
+        let response = app.post_admin_form("newsletters/drafts/new", &body).await;
+        assert_is_redirect_to(&response, "/admin/newsletters");
+        let html_page = app.get_publish_newsletter_html().await;
+        assert!(html_page.contains("The newsletter issue has been saved as a draft."));
+    }
+    let n_issues = sqlx::query!(r#"select count(*) as "n!" from newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 1);
+}
+
+#[tokio::test]
+async fn reusing_a_key_for_another_request_is_rejected() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    subscribe_with_key(&app, SUBSCRIPTION, &idempotency_key, "browser").await;
+
+    let response = subscribe_with_key(
+        &app,
+        "name=ursula&email=ursula%40example.com",
+        &idempotency_key,
+        "browser",
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 422);
+    app.dispatch_outbox().await;
+}
+
+#[tokio::test]
+async fn reusing_a_form_key_for_another_issue_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let issue = |title: &str| {
+        serde_json::json!({
+            "title": title,
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": idempotency_key,
+        })
+    };
+    let response = app.post_publish_newsletter(&issue("First title")).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let response = app.post_publish_newsletter(&issue("Second title")).await;
+
+    assert_eq!(response.status().as_u16(), 422);
+    let n_issues = sqlx::query!(r#"select count(*) as "n!" from newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 1);
+}
+
+#[tokio::test]
+async fn api_retries_of_a_draft_creation_are_performed_once() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["drafts:write"]).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let draft = |title: &str| {
+        serde_json::json!({
+            "title": title,
+            "text_content": "Draft body",
+            "html_content": "<p>Draft body</p>",
+        })
+    };
+
+    let mut bodies = Vec::new();
+    for _ in 0..2 {
+        let response = app
+            .api_request(Method::POST, "drafts", &token)
+            .header("Idempotency-Key", &idempotency_key)
+            .json(&draft("Draft title"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 201);
+        bodies.push(response.json::<serde_json::Value>().await.unwrap());
+    }
+    assert_eq!(bodies[0], bodies[1]);
+
+    let response = app
+        .api_request(Method::POST, "drafts", &token)
+        .header("Idempotency-Key", &idempotency_key)
+        .json(&draft("Another title"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 422);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["error"],
+        "The idempotency key has already been used for a different request."
+    );
+}
+
+#[tokio::test]
+async fn parallel_publishes_with_the_same_key_publish_once() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["issues:publish"]).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let mut requests = JoinSet::new();
+    for _ in 0..5 {
+        let request = app
+            .api_request(Method::POST, "issues", &token)
+            .header("Idempotency-Key", &idempotency_key)
+            .json(&serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+            }));
+        requests.spawn(async move { request.send().await.unwrap() });
+    }
+    let mut bodies = Vec::new();
+    while let Some(response) = requests.join_next().await {
+        let response = response.unwrap();
+        assert_eq!(response.status().as_u16(), 201);
+        bodies.push(response.json::<serde_json::Value>().await.unwrap());
+    }
+
+    assert!(bodies.iter().all(|body| *body == bodies[0]));
+    let n_issues = sqlx::query!(r#"select count(*) as "n!" from newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 1);
+    let n_queued = sqlx::query!(r#"select count(*) as "n!" from jobs"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_queued, 1);
+}
+
+#[tokio::test]
+async fn retries_are_told_to_come_back_while_the_first_request_is_too_slow() {
+    let app = spawn_app().await;
+    // Hold up the first request for much longer than retries are allowed to wait.
+    let mut lock = app.db_pool.begin().await.unwrap();
+    sqlx::query!("lock table outbox")
+        .execute(lock.as_mut())
+        .await
+        .unwrap();
+    let idempotency_key = Uuid::new_v4().to_string();
+    let first_request = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Idempotency-Key", &idempotency_key)
+        .header("User-Agent", "browser")
+        .body(SUBSCRIPTION);
+    let first_request = tokio::spawn(async move { first_request.send().await.unwrap() });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let response = subscribe_with_key(&app, SUBSCRIPTION, &idempotency_key, "browser").await;
+    assert_eq!(response.status().as_u16(), 409);
+    assert_eq!(response.headers()["Retry-After"], "1");
+
+    lock.commit().await.unwrap();
+    assert_eq!(first_request.await.unwrap().status().as_u16(), 200);
+    let response = subscribe_with_key(&app, SUBSCRIPTION, &idempotency_key, "browser").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn waiting_retries_do_not_hold_on_to_a_connection() {
+    let app = spawn_app().await;
+    let mut lock = app.db_pool.begin().await.unwrap();
+    sqlx::query!("lock table outbox")
+        .execute(lock.as_mut())
+        .await
+        .unwrap();
+    let idempotency_key = Uuid::new_v4().to_string();
+    let mut requests = JoinSet::new();
+    for _ in 0..2 {
+        let request = app
+            .api_client
+            .post(format!("{}/subscriptions", app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Idempotency-Key", &idempotency_key)
+            .header("User-Agent", "browser")
+            .body(SUBSCRIPTION);
+        requests.spawn(async move { request.send().await.unwrap() });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    // Only the first request is stuck, behind the lock on the outbox.
+    let n_blocked = sqlx::query!(
+        r#"
+        select count(*) as "n!" from pg_stat_activity
+        where datname = current_database()
+        and wait_event_type = 'Lock'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .n;
+    assert_eq!(n_blocked, 1);
+
+    lock.commit().await.unwrap();
+    while let Some(response) = requests.join_next().await {
+        assert_eq!(response.unwrap().status().as_u16(), 200);
+    }
+}
+
+async fn expire_idempotency_keys(app: &TestApp) {
+    sqlx::query!("update idempotency set created_at = now() - interval '2 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn expired_keys_are_treated_as_fresh() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let response = subscribe_with_key(&app, SUBSCRIPTION, &idempotency_key, "browser").await;
+    assert_eq!(response.status().as_u16(), 200);
+    expire_idempotency_keys(&app).await;
+    sqlx::query!("delete from subscription_tokens")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Even for another request.
+    let response = subscribe_with_key(
+        &app,
+        "name=ursula&email=ursula%40example.com",
+        &idempotency_key,
+        "browser",
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_outbox().await;
+}
+
+#[tokio::test]
+async fn pruning_deletes_expired_records_only() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    for user_agent in ["one", "two", "three", "four", "five"] {
+        subscribe_with_key(&app, SUBSCRIPTION, "a-key", user_agent).await;
+        sqlx::query!("delete from subscription_tokens")
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+    expire_idempotency_keys(&app).await;
+    subscribe_with_key(&app, SUBSCRIPTION, "a-key", "six").await;
+    let mut settings = app.idempotency_settings.clone();
+    settings.prune_batch_size = NonZeroU32::new(2).unwrap();
+
+    let n_pruned = prune_expired_records(&app.db_pool, &settings)
+        .await
+        .unwrap();
+
+    assert_eq!(n_pruned, 5);
+    let n_left = sqlx::query!(r#"select count(*) as "n!" from idempotency"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_left, 1);
+}
@@ -0,0 +1,191 @@
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn publish_issue(app: &TestApp) -> Uuid {
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    sqlx::query!("select newsletter_issue_id from newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+async fn audit_actions(app: &TestApp, newsletter_issue_id: Uuid) -> Vec<String> {
+    sqlx::query!(
+        "select action from audit_log where target = $1 order by occurred_at",
+        newsletter_issue_id.to_string()
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.action)
+    .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_issues() {
+    let app = spawn_app().await;
+
+    let response = app.get_issues().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_issue_action(
+            Uuid::new_v4(),
+            "delete",
+            &serde_json::json!({"confirm": "yes"}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn editing_an_issue_updates_its_content_and_is_audited() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_issue(&app).await;
+
+    let body = serde_json::json!({
+        "title": "Fixed title",
+        "text_content": "Fixed body",
+        "html_content": "<p>Fixed body</p>",
+    });
+    let response = app.post_issue_action(issue_id, "edit", &body).await;
+    assert_is_redirect_to(&response, "/admin/issues");
+
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<p><i>The newsletter issue has been updated.</i></p>"));
+    assert!(html_page.contains("Fixed title"));
+
+    let saved = sqlx::query!(
+        "select title, text_content, updated_at from newsletter_issues \
+        where newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.title, "Fixed title");
+    assert_eq!(saved.text_content, "Fixed body");
+    assert!(saved.updated_at.is_some());
+    assert_eq!(
+        audit_actions(&app, issue_id).await,
+        vec!["issue.published", "issue.edited"]
+    );
+}
+
+#[tokio::test]
+async fn pending_deliveries_use_the_edited_content() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_issue(&app).await;
+
+    let body = serde_json::json!({
+        "title": "Fixed title",
+        "text_content": "Fixed body",
+        "html_content": "<p>Fixed body</p>",
+    });
+    app.post_issue_action(issue_id, "edit", &body).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert_eq!(body["Subject"], "Fixed title");
+}
+
+#[tokio::test]
+async fn halting_an_issue_cancels_its_pending_deliveries() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_issue(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_issue_action(issue_id, "halt", &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/issues");
+
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("1 pending deliveries were cancelled"));
+
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(
+        audit_actions(&app, issue_id).await,
+        vec!["issue.published", "issue.halted"]
+    );
+}
+
+#[tokio::test]
+async fn deleting_an_issue_requires_confirmation() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_issue(&app).await;
+
+    let response = app
+        .post_issue_action(issue_id, "delete", &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/issues/{issue_id}/delete"));
+
+    let n_issues = sqlx::query!(r#"select count(*) as "n!" from newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 1);
+    assert_eq!(audit_actions(&app, issue_id).await, vec!["issue.published"]);
+}
+
+#[tokio::test]
+async fn deleting_an_issue_removes_it_and_its_pending_deliveries() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = publish_issue(&app).await;
+
+    let response = app
+        .post_issue_action(issue_id, "delete", &serde_json::json!({"confirm": "yes"}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/issues");
+
+    let html_page = app.get_issues_html().await;
+    assert!(html_page.contains("<p><i>The newsletter issue has been deleted.</i></p>"));
+    assert!(!html_page.contains("/edit\">Edit</a>"));
+
+    let n_pending = sqlx::query!(r#"select count(*) as "n!" from jobs"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_pending, 0);
+    assert_eq!(
+        audit_actions(&app, issue_id).await,
+        vec!["issue.published", "issue.deleted"]
+    );
+    let feed = app.get_feed("feed.atom").await.text().await.unwrap();
+    assert!(!feed.contains(&issue_id.to_string()));
+}
@@ -0,0 +1,172 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::{
+    configuration::get_configuration,
+    issue_delivery::DeliverIssue,
+    jobs::{enqueue_due_recurring_jobs, recurring_jobs, NewJob},
+};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn publish_issue(app: &TestApp) -> Uuid {
+    app.test_user.login(app).await;
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    sqlx::query!("select newsletter_issue_id from newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+async fn n_queued_jobs(app: &TestApp, kind: &str) -> i64 {
+    sqlx::query!(r#"select count(*) as "n!" from jobs where kind = $1"#, kind)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n
+}
+
+#[tokio::test]
+async fn failed_jobs_are_retried_then_set_aside() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    publish_issue(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let job =
+        sqlx::query!("select n_attempts, run_after > now() as retry_later, last_error from jobs")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(job.n_attempts, 1);
+    assert_eq!(job.retry_later, Some(true));
+    assert!(job.last_error.unwrap().contains("500"));
+
+    sqlx::query!("update jobs set n_attempts = max_attempts - 1, run_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!("update jobs set run_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let job = sqlx::query!("select failed_at from jobs")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(job.failed_at.is_some());
+}
+
+#[tokio::test]
+async fn unique_jobs_are_only_queued_once() {
+    let app = spawn_app().await;
+    let delivery = |newsletter_issue_id| {
+        NewJob::new(DeliverIssue {
+            newsletter_issue_id,
+            subscriber_email: "ursula@example.com".into(),
+        })
+    };
+    let (issue_id, other_issue_id) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut tx = app.db_pool.begin().await.unwrap();
+
+    let mut queued = Vec::new();
+    for id in [issue_id, issue_id, other_issue_id] {
+        queued.push(delivery(id).enqueue(&mut tx).await.unwrap());
+    }
+    tx.commit().await.unwrap();
+
+    assert_eq!(queued, [true, false, true]);
+    assert_eq!(n_queued_jobs(&app, "deliver_issue").await, 2);
+}
+
+#[tokio::test]
+async fn jobs_with_a_higher_priority_run_first() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = publish_issue(&app).await;
+    let mut tx = app.db_pool.begin().await.unwrap();
+    for (subscriber_email, priority) in [("low@example.com", 0), ("high@example.com", 10)] {
+        NewJob::new(DeliverIssue {
+            newsletter_issue_id,
+            subscriber_email: subscriber_email.into(),
+        })
+        .priority(priority)
+        .enqueue(&mut tx)
+        .await
+        .unwrap();
+    }
+    tx.commit().await.unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let recipients: Vec<String> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+            body["To"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    assert_eq!(recipients, ["high@example.com", "low@example.com"]);
+}
+
+#[tokio::test]
+async fn recurring_jobs_are_queued_once_per_run() {
+    let app = spawn_app().await;
+    let recurring_jobs = recurring_jobs(&get_configuration().unwrap()).unwrap();
+
+    // The first run is the next one on the schedule.
+    enqueue_due_recurring_jobs(&app.db_pool, &recurring_jobs)
+        .await
+        .unwrap();
+    assert_eq!(n_queued_jobs(&app, "prune_send_rate_windows").await, 0);
+
+    sqlx::query!("update recurring_jobs set next_run_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        enqueue_due_recurring_jobs(&app.db_pool, &recurring_jobs)
+            .await
+            .unwrap();
+    }
+    assert_eq!(n_queued_jobs(&app, "prune_send_rate_windows").await, 1);
+    assert_eq!(n_queued_jobs(&app, "prune_idempotency_records").await, 1);
+    assert_eq!(
+        n_queued_jobs(&app, "enqueue_due_autoresponder_steps").await,
+        1
+    );
+    // No feed is configured.
+    assert_eq!(n_queued_jobs(&app, "import_feed").await, 0);
+
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(n_queued_jobs(&app, "prune_send_rate_windows").await, 0);
+    assert_eq!(n_queued_jobs(&app, "prune_idempotency_records").await, 0);
+}
@@ -1,4 +1,6 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
 
 #[tokio::test]
 async fn an_error_flash_message_is_set_on_failure() {
@@ -35,3 +37,61 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
+
+#[tokio::test]
+async fn weaker_password_hashes_are_upgraded_on_login() {
+    let app = spawn_app().await;
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let weak_password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(4096, 1, 1, None).unwrap(),
+    )
+    .hash_password(app.test_user.password.as_bytes(), &salt)
+    .unwrap()
+    .to_string();
+    sqlx::query!(
+        "update users set password_hash = $2 where user_id = $1",
+        app.test_user.user_id,
+        weak_password_hash
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    app.test_user.login(&app).await;
+
+    let password_hash = stored_password_hash(&app).await;
+    assert_ne!(password_hash, weak_password_hash);
+    assert!(password_hash.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn up_to_date_password_hashes_are_left_alone() {
+    let app = spawn_app().await;
+    let password_hash = stored_password_hash(&app).await;
+
+    app.test_user.login(&app).await;
+
+    assert_eq!(stored_password_hash(&app).await, password_hash);
+}
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query!(
+        "select password_hash from users where user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash
+}
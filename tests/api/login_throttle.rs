@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use tokio::task::JoinSet;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+const LOCKED_OUT: &str =
+    "<p><i>Too many failed login attempts, please try again in 15 minutes.</i></p>";
+
+async fn post_login_from(app: &TestApp, password: &str, ip: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/login", &app.address))
+        .header("X-Forwarded-For", ip)
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": password,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn a_username_is_locked_after_repeated_failures() {
+    let app = spawn_app().await;
+
+    for _ in 0..5 {
+        let response = post_login_from(&app, "wrong password", "10.0.0.1").await;
+        assert_is_redirect_to(&response, "/login");
+        assert!(app
+            .get_login_html()
+            .await
+            .contains("<p><i>Authentication failed</i></p>"));
+    }
+
+    // Even the right password is turned away, from any IP.
+    let response = post_login_from(&app, &app.test_user.password, "10.0.0.2").await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains(LOCKED_OUT));
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn concurrent_guesses_do_not_get_past_the_limit() {
+    let app = spawn_app().await;
+
+    let mut requests = JoinSet::new();
+    for _ in 0..20 {
+        let request = app
+            .api_client
+            .post(format!("{}/login", &app.address))
+            .header("X-Forwarded-For", "10.0.0.1")
+            .form(&serde_json::json!({
+                "username": &app.test_user.username,
+                "password": "wrong password",
+            }));
+        requests.spawn(async move { request.send().await.unwrap() });
+    }
+    while requests.join_next().await.is_some() {}
+
+    let n_checked = sqlx::query!(
+        r#"
+        select count(*) as "n!" from audit_log
+        where action = 'login.failed' and details = 'invalid credentials'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .n;
+    assert_eq!(n_checked, 5);
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_username_failures() {
+    let app = spawn_app().await;
+
+    for _ in 0..4 {
+        post_login_from(&app, "wrong password", "10.0.0.1").await;
+    }
+    let response = post_login_from(&app, &app.test_user.password, "10.0.0.1").await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    for _ in 0..4 {
+        post_login_from(&app, "wrong password", "10.0.0.1").await;
+    }
+    let response = post_login_from(&app, &app.test_user.password, "10.0.0.1").await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn an_ip_is_locked_after_failures_across_usernames() {
+    let app = spawn_app().await;
+
+    for i in 0..50 {
+        app.api_client
+            .post(format!("{}/login", &app.address))
+            .header("X-Forwarded-For", "10.0.0.3")
+            .form(&serde_json::json!({
+                "username": format!("user-{i}"),
+                "password": "wrong password",
+            }))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let response = post_login_from(&app, &app.test_user.password, "10.0.0.3").await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains(LOCKED_OUT));
+
+    let response = post_login_from(&app, &app.test_user.password, "10.0.0.4").await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn the_account_owner_is_notified_of_a_lockout() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        "update users set email = 'admin@example.com' where user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..6 {
+        post_login_from(&app, "wrong password", "10.0.0.1").await;
+    }
+
+    // The notification is sent in the background.
+    for _ in 0..50 {
+        if let Some(email_request) = app.email_server.received_requests().await.unwrap().pop() {
+            let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+            assert_eq!(body["To"], "admin@example.com");
+            assert_eq!(body["Subject"], "Your account has been locked");
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("No lockout notification was sent");
+}
@@ -1,8 +1,26 @@
 mod admin_dashboard;
+mod api_tokens;
+mod audit;
+mod autoresponders;
 mod change_password;
+mod csrf;
+mod delivery_rate_limits;
+mod feed;
+mod feed_import;
 mod health_check;
 mod helpers;
+mod idempotency;
+mod issues;
+mod jobs;
 mod login;
+mod login_throttle;
+mod metrics;
 mod newsletter;
+mod password_reset;
+mod sessions;
+mod sso;
 mod subscriptions;
 mod subscriptions_confirm;
+mod trace_context;
+mod two_factor;
+mod users;
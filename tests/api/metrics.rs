@@ -0,0 +1,191 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::idempotency::prune_expired_records;
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+impl TestApp {
+    async fn get_metrics(&self) -> String {
+        reqwest::get(format!("{}/metrics", self.metrics_address))
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    }
+}
+
+/// The value of a series, e.g. `logins_total{method="password",outcome="failure"}`.
+/// Metrics are shared by every test app, so counters are only compared with
+/// their earlier values.
+fn sample(metrics: &str, series: &str) -> Option<f64> {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .map(|value| value.parse().unwrap())
+}
+
+#[tokio::test]
+async fn metrics_need_an_api_token_with_the_metrics_scope() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let url = format!("{}/metrics", app.address);
+
+    let response = app.api_client.get(&url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let token = app.create_api_token(&["subscribers:read"]).await;
+    let response = app
+        .api_client
+        .get(&url)
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    let token = app.create_api_token(&["metrics:read"]).await;
+    let response = app
+        .api_client
+        .get(&url)
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("db_pool_connections"));
+}
+
+#[tokio::test]
+async fn requests_are_counted_per_route() {
+    let app = spawn_app().await;
+    let series =
+        r#"http_requests_total{method="GET",route="/admin/issues/{issue_id}/edit",status="303"}"#;
+    let before = sample(&app.get_metrics().await, series).unwrap_or(0.0);
+
+    for _ in 0..2 {
+        let response = app
+            .api_client
+            .get(format!(
+                "{}/admin/issues/{}/edit",
+                app.address,
+                Uuid::new_v4()
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    let metrics = app.get_metrics().await;
+    assert!(sample(&metrics, series).unwrap() >= before + 2.0);
+    assert!(metrics.contains(
+        r#"http_request_duration_seconds_count{method="GET",route="/admin/issues/{issue_id}/edit"}"#
+    ));
+}
+
+#[tokio::test]
+async fn logins_are_counted_by_outcome() {
+    let app = spawn_app().await;
+    let failures = r#"logins_total{method="password",outcome="failure"}"#;
+    let successes = r#"logins_total{method="password",outcome="success"}"#;
+    let metrics = app.get_metrics().await;
+    let (failures_before, successes_before) = (
+        sample(&metrics, failures).unwrap_or(0.0),
+        sample(&metrics, successes).unwrap_or(0.0),
+    );
+
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "not-the-password"
+    }))
+    .await;
+    app.test_user.login(&app).await;
+
+    let metrics = app.get_metrics().await;
+    assert!(sample(&metrics, failures).unwrap() >= failures_before + 1.0);
+    assert!(sample(&metrics, successes).unwrap() >= successes_before + 1.0);
+}
+
+#[tokio::test]
+async fn email_sends_are_counted_and_timed() {
+    let app = spawn_app().await;
+    let failures = r#"emails_sent_total{outcome="failure"}"#;
+    let before = sample(&app.get_metrics().await, failures).unwrap_or(0.0);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_outbox().await;
+
+    let metrics = app.get_metrics().await;
+    assert!(sample(&metrics, failures).unwrap() >= before + 1.0);
+    assert!(metrics.contains(r#"email_send_duration_seconds_count{outcome="failure"}"#));
+}
+
+#[tokio::test]
+async fn queued_jobs_are_reported_per_queue_and_kind() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+
+    let metrics = app.get_metrics().await;
+    let series = r#"background_jobs{kind="deliver_issue",queue="deliveries",state="pending"}"#;
+    assert_eq!(sample(&metrics, series), Some(1.0));
+}
+
+#[tokio::test]
+async fn metrics_are_still_served_when_the_job_queues_cannot_be_read() {
+    let app = spawn_app().await;
+    sqlx::query!("alter table jobs rename to jobs_elsewhere")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let metrics = app.get_metrics().await;
+
+    assert!(metrics.contains("db_pool_connections"));
+    assert!(!metrics.contains("background_jobs"));
+}
+
+#[tokio::test]
+async fn pruned_idempotency_records_are_counted() {
+    let app = spawn_app().await;
+    let series = "idempotency_records_pruned_total";
+    let before = sample(&app.get_metrics().await, series).unwrap_or(0.0);
+    sqlx::query!(
+        r#"
+        insert into idempotency (scope, idempotency_key, created_at)
+        select 'client:test', 'key-' || n, now() - interval '2 days'
+        from generate_series(1, 3) as n
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    prune_expired_records(&app.db_pool, &app.idempotency_settings)
+        .await
+        .unwrap();
+
+    assert!(sample(&app.get_metrics().await, series).unwrap() >= before + 3.0);
+}
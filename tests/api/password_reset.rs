@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+const RESET_LINK_SENT: &str = "<p><i>If the account exists, a link to reset its password has \
+    been sent to its email address.</i></p>";
+
+async fn set_test_user_email(app: &TestApp) {
+    sqlx::query!(
+        "update users set email = 'admin@example.com' where user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// The link is sent in the background, wait for it to reach the email API.
+async fn reset_token_from_email(app: &TestApp) -> String {
+    for _ in 0..50 {
+        if let Some(email_request) = app.email_server.received_requests().await.unwrap().pop() {
+            let links = app.get_confirmation_links(&email_request);
+            assert_eq!(links.html.path(), "/login/reset");
+            return links
+                .html
+                .query_pairs()
+                .find(|(k, _)| k == "reset_token")
+                .unwrap()
+                .1
+                .into_owned();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("No password reset email was sent");
+}
+
+async fn request_reset_token(app: &TestApp) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_forgot_password(&app.test_user.username).await;
+    assert_is_redirect_to(&response, "/login");
+    reset_token_from_email(app).await
+}
+
+#[tokio::test]
+async fn the_response_does_not_reveal_whether_the_username_exists() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_forgot_password("nobody").await;
+    assert_is_redirect_to(&response, "/login");
+    let unknown_user_page = app.get_login_html().await;
+
+    let response = app.post_forgot_password(&app.test_user.username).await;
+    assert_is_redirect_to(&response, "/login");
+    let known_user_page = app.get_login_html().await;
+
+    assert!(unknown_user_page.contains(RESET_LINK_SENT));
+    assert_eq!(unknown_user_page, known_user_page);
+    reset_token_from_email(&app).await;
+}
+
+#[tokio::test]
+async fn a_reset_link_sets_a_new_password() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let reset_token = request_reset_token(&app).await;
+
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/login/reset?reset_token={}",
+            &app.address, reset_token
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "reset_token": &reset_token,
+            "new_password": "a brand new password",
+            "new_password_check": "a brand new password",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your password has been reset, you can now log in.</i></p>"));
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "a brand new password",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn reset_links_can_only_be_used_once() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let reset_token = request_reset_token(&app).await;
+
+    let body = serde_json::json!({
+        "reset_token": &reset_token,
+        "new_password": "a brand new password",
+        "new_password_check": "a brand new password",
+    });
+    app.post_reset_password(&body).await;
+    let response = app.post_reset_password(&body).await;
+    assert_is_redirect_to(&response, "/login/forgot");
+}
+
+#[tokio::test]
+async fn expired_reset_links_are_rejected() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let reset_token = request_reset_token(&app).await;
+    sqlx::query!("update password_reset_tokens set expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "reset_token": &reset_token,
+            "new_password": "a brand new password",
+            "new_password_check": "a brand new password",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login/forgot");
+    let html_page = app
+        .api_client
+        .get(format!("{}/login/forgot", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p><i>This reset link is invalid or has expired.</i></p>"));
+}
+
+#[tokio::test]
+async fn new_password_fields_must_match() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    let reset_token = request_reset_token(&app).await;
+
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "reset_token": &reset_token,
+            "new_password": "a brand new password",
+            "new_password_check": "another new password",
+        }))
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/login/reset?reset_token={reset_token}"),
+    );
+}
+
+#[tokio::test]
+async fn resetting_the_password_logs_out_every_session() {
+    let app = spawn_app().await;
+    set_test_user_email(&app).await;
+    app.test_user.login(&app).await;
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let reset_token = request_reset_token(&app).await;
+    let anonymous_client = reqwest::Client::new();
+    anonymous_client
+        .post(format!("{}/login/reset", &app.address))
+        .form(&serde_json::json!({
+            "reset_token": &reset_token,
+            "new_password": "a brand new password",
+            "new_password_check": "a brand new password",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your session has expired, please log in again.</i></p>"));
+}
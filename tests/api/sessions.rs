@@ -0,0 +1,184 @@
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+
+/// Log the test user in from another browser, returning its client.
+async fn log_in_elsewhere(app: &TestApp) -> reqwest::Client {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .user_agent("Other Browser/1.0")
+        .build()
+        .unwrap();
+    let response = client
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    client
+}
+
+async fn get_dashboard(app: &TestApp, client: &reqwest::Client) -> reqwest::Response {
+    client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn session_ids(app: &TestApp) -> Vec<Uuid> {
+    sqlx::query!(
+        "select session_id from user_sessions where user_id = $1 order by created_at",
+        app.test_user.user_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.session_id)
+    .collect()
+}
+
+#[tokio::test]
+async fn active_sessions_are_listed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    log_in_elsewhere(&app).await;
+
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("Other Browser/1.0"));
+    assert!(html_page.contains("127.0.0.1"));
+    assert!(html_page.contains("This session"));
+    let other_session_id = session_ids(&app).await[1];
+    assert!(html_page.contains(&format!("/admin/sessions/{other_session_id}/revoke")));
+}
+
+/// Let the session go idle for `idle`, then use it. Returns whether its last
+/// activity was brought up to date.
+async fn is_seen_after_going_idle(app: &TestApp, idle: &str) -> bool {
+    sqlx::query(&format!(
+        "update user_sessions set last_seen_at = now() - interval '{idle}'"
+    ))
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.get_admin_dashboard().await;
+    sqlx::query_scalar!(
+        r#"select last_seen_at > now() - interval '10 seconds' as "seen!" from user_sessions"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn the_last_activity_is_recorded_at_most_once_a_minute() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    assert!(!is_seen_after_going_idle(&app, "30 seconds").await);
+    assert!(is_seen_after_going_idle(&app, "2 minutes").await);
+}
+
+#[tokio::test]
+async fn a_revoked_session_is_logged_out() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let other_client = log_in_elsewhere(&app).await;
+    let other_session_id = session_ids(&app).await[1];
+
+    let response = app
+        .post_sessions(&format!("{other_session_id}/revoke"))
+        .await;
+    assert_is_redirect_to(&response, "/admin/sessions");
+    assert!(app
+        .get_sessions_html()
+        .await
+        .contains("<p><i>The session has been logged out.</i></p>"));
+
+    let response = get_dashboard(&app, &other_client).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn sessions_of_other_users_cannot_be_revoked() {
+    let app = spawn_app().await;
+    let other_client = log_in_elsewhere(&app).await;
+    let other_session_id = session_ids(&app).await[0];
+
+    let user = TestUser::generate();
+    user.store(&app.db_pool).await;
+    user.login(&app).await;
+    app.post_sessions(&format!("{other_session_id}/revoke"))
+        .await;
+    assert!(app
+        .get_sessions_html()
+        .await
+        .contains("<p><i>The session does not exist.</i></p>"));
+
+    let response = get_dashboard(&app, &other_client).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn all_other_sessions_can_be_revoked_at_once() {
+    let app = spawn_app().await;
+    let first_client = log_in_elsewhere(&app).await;
+    let second_client = log_in_elsewhere(&app).await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_sessions("revoke-others").await;
+    assert_is_redirect_to(&response, "/admin/sessions");
+    assert!(app
+        .get_sessions_html()
+        .await
+        .contains("<p><i>2 other sessions have been logged out.</i></p>"));
+
+    for client in [first_client, second_client] {
+        let response = get_dashboard(&app, &client).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn changing_the_password_logs_out_the_other_sessions() {
+    let app = spawn_app().await;
+    let other_client = log_in_elsewhere(&app).await;
+    app.test_user.login(&app).await;
+
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>1 other sessions have been logged out.</i></p>"));
+
+    let response = get_dashboard(&app, &other_client).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn logging_out_forgets_the_session() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    assert_eq!(session_ids(&app).await.len(), 1);
+
+    app.post_logout().await;
+    assert!(session_ids(&app).await.is_empty());
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use reqwest::Url;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use wiremock::matchers::{method, path};
+use wiremock::{Match, Mock, Request, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+const SIGNING_KEY: &str = include_str!("oidc_signing_key.pem");
+/// The modulus of `SIGNING_KEY`, base64url encoded as in a JWK.
+const SIGNING_KEY_MODULUS: &str = "t03r-3gwEqfMzXbpUXiydSOPLg3WJ13gMegnRby9PTtdB43ODDYwv4SUaAmzudniH6mQw9ShZ61xMRWa_wqe7Z3eOE-VcAssntmQyU6ZWaWH2ue19WEiJQAs7y1G7NRfWXjfaxn8Mb9fyTCRVpG8PXkAhVFmh1U8tdp3rIFcwIzIe4UU-ux9O-xivhrGYYn374TracxOlPuTtmPB49SdHfwZsBDRP7CITg-0Tq-40ddgeRfD9VUHvzYk1wzTdRED9aYxNivG7zWn6-4tgGfBjNxxEKgVvQhq70Qw6Z6jHsiZcegFEWaVR9pzT9sazEkbX8mzyIgeQDm5tcvdoFZwSw";
+const KEY_ID: &str = "test-key";
+const CLIENT_ID: &str = "zero2prod";
+
+/// Serve the discovery document and the signing keys of the identity provider.
+async fn mount_issuer(app: &TestApp) {
+    let issuer = app.oidc_server.uri();
+    Mock::given(method("GET"))
+        .and(path("/.well-known/openid-configuration"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}/authorize"),
+            "token_endpoint": format!("{issuer}/token"),
+            "jwks_uri": format!("{issuer}/jwks"),
+        })))
+        .mount(&app.oidc_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/jwks"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "keys": [{
+                "kty": "RSA",
+                "kid": KEY_ID,
+                "use": "sig",
+                "alg": "RS256",
+                "n": SIGNING_KEY_MODULUS,
+                "e": "AQAB",
+            }]
+        })))
+        .mount(&app.oidc_server)
+        .await;
+}
+
+/// Start a login, returning the parameters of the authorization request.
+async fn start_login(app: &TestApp) -> HashMap<String, String> {
+    let response = app
+        .api_client
+        .get(format!("{}/login/sso", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 303);
+    let location = Url::parse(response.headers()["Location"].to_str().unwrap()).unwrap();
+    assert_eq!(location.path(), "/authorize");
+    location.query_pairs().into_owned().collect()
+}
+
+/// Only hand out tokens to the code verifier matching the challenge of the
+/// authorization request, like a provider enforcing PKCE.
+struct PkceCodeExchange {
+    code_challenge: String,
+}
+
+impl Match for PkceCodeExchange {
+    fn matches(&self, request: &Request) -> bool {
+        let form: HashMap<String, String> =
+            serde_urlencoded::from_bytes(&request.body).unwrap_or_default();
+        let verifier_matches = form
+            .get("code_verifier")
+            .map(|v| URL_SAFE_NO_PAD.encode(Sha256::digest(v)) == self.code_challenge)
+            .unwrap_or(false);
+        verifier_matches
+            && form.get("grant_type").map(String::as_str) == Some("authorization_code")
+            && form.get("code").map(String::as_str) == Some("the-code")
+            && form.get("client_secret").map(String::as_str) == Some("oidc-client-secret")
+    }
+}
+
+/// An ID token for the login started with `authorization`, with `overrides`
+/// replacing or adding claims.
+fn id_token(app: &TestApp, authorization: &HashMap<String, String>, overrides: Value) -> String {
+    let mut claims = json!({
+        "iss": app.oidc_server.uri(),
+        "aud": CLIENT_ID,
+        "sub": "248289761001",
+        "iat": Utc::now().timestamp(),
+        "exp": Utc::now().timestamp() + 300,
+        "nonce": authorization["nonce"],
+        "email": "ursula@example.com",
+        "email_verified": true,
+    });
+    for (claim, value) in overrides.as_object().unwrap() {
+        claims[claim] = value.clone();
+    }
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(KEY_ID.into());
+    encode(
+        &header,
+        &claims,
+        &EncodingKey::from_rsa_pem(SIGNING_KEY.as_bytes()).unwrap(),
+    )
+    .unwrap()
+}
+
+/// Go through the identity provider with an ID token carrying `overrides`.
+async fn log_in_with(app: &TestApp, overrides: Value) -> reqwest::Response {
+    let authorization = start_login(app).await;
+    let _mock_guard = Mock::given(method("POST"))
+        .and(path("/token"))
+        .and(PkceCodeExchange {
+            code_challenge: authorization["code_challenge"].clone(),
+        })
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "an-access-token",
+            "token_type": "Bearer",
+            "id_token": id_token(app, &authorization, overrides),
+        })))
+        .expect(1)
+        .mount_as_scoped(&app.oidc_server)
+        .await;
+    complete_login(app, &authorization["state"]).await
+}
+
+async fn complete_login(app: &TestApp, state: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/login/sso/callback", &app.address))
+        .query(&[("code", "the-code"), ("state", state)])
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn set_test_user_email(app: &TestApp, email: &str) {
+    sqlx::query!(
+        "update users set email = $1 where user_id = $2",
+        email,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn the_login_page_offers_single_sign_on() {
+    let app = spawn_app().await;
+
+    let html_page = app.get_login_html().await;
+
+    assert!(html_page.contains(r#"<a href="/login/sso">Log in with single sign-on</a>"#));
+}
+
+#[tokio::test]
+async fn single_sign_on_sends_the_user_to_the_identity_provider_with_pkce() {
+    let app = spawn_app().await;
+    mount_issuer(&app).await;
+
+    let authorization = start_login(&app).await;
+
+    assert_eq!(authorization["response_type"], "code");
+    assert_eq!(authorization["client_id"], CLIENT_ID);
+    assert!(authorization["scope"].split(' ').any(|s| s == "openid"));
+    assert!(authorization["redirect_uri"].ends_with("/login/sso/callback"));
+    assert_eq!(authorization["code_challenge_method"], "S256");
+    // A base64url encoded SHA-256 digest.
+    assert_eq!(authorization["code_challenge"].len(), 43);
+    assert!(!authorization["state"].is_empty());
+    assert!(!authorization["nonce"].is_empty());
+}
+
+#[tokio::test]
+async fn a_verified_identity_logs_the_matching_user_in() {
+    let app = spawn_app().await;
+    mount_issuer(&app).await;
+    set_test_user_email(&app, "Ursula@example.com").await;
+
+    let response = log_in_with(&app, json!({})).await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn an_unavailable_identity_provider_is_reported() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/login/sso", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page
+        .contains("<p><i>The identity provider is unavailable, please try again later.</i></p>"));
+}
+
+#[tokio::test]
+async fn a_callback_for_another_login_is_rejected() {
+    let app = spawn_app().await;
+    mount_issuer(&app).await;
+    set_test_user_email(&app, "ursula@example.com").await;
+    start_login(&app).await;
+
+    let response = complete_login(&app, "another-state").await;
+
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Single sign-on failed, please try again.</i></p>"));
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn invalid_id_tokens_are_rejected() {
+    let app = spawn_app().await;
+    mount_issuer(&app).await;
+    set_test_user_email(&app, "ursula@example.com").await;
+    let test_cases = [
+        (
+            json!({"nonce": "another-nonce"}),
+            "issued for another login",
+        ),
+        (
+            json!({"aud": "another-client"}),
+            "issued for another client",
+        ),
+        (
+            json!({"iss": "https://evil.example.com"}),
+            "issued by someone else",
+        ),
+        (json!({"exp": Utc::now().timestamp() - 3600}), "expired"),
+        (json!({"email_verified": false}), "with an unverified email"),
+        (json!({"email": null}), "without the claim"),
+    ];
+
+    for (overrides, description) in test_cases {
+        let response = log_in_with(&app, overrides).await;
+
+        assert_is_redirect_to(&response, "/login");
+        assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+        let html_page = app.get_login_html().await;
+        assert!(
+            html_page.contains("<p><i>Single sign-on failed, please try again.</i></p>"),
+            "A token {description} was not rejected."
+        );
+    }
+}
+
+#[tokio::test]
+async fn identities_without_an_active_account_are_rejected() {
+    let app = spawn_app().await;
+    mount_issuer(&app).await;
+    set_test_user_email(&app, "ursula@example.com").await;
+    let expected_message = "<p><i>No active account matches your identity provider login.</i></p>";
+
+    let response = log_in_with(&app, json!({"email": "nobody@example.com"})).await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains(expected_message));
+
+    sqlx::query!(
+        "update users set disabled_at = now() where user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let response = log_in_with(&app, json!({})).await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains(expected_message));
+}
+
+#[tokio::test]
+async fn a_login_can_only_be_completed_once() {
+    let app = spawn_app().await;
+    mount_issuer(&app).await;
+    set_test_user_email(&app, "ursula@example.com").await;
+    let authorization = start_login(&app).await;
+    Mock::given(method("POST"))
+        .and(path("/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id_token": id_token(&app, &authorization, json!({})),
+        })))
+        .mount(&app.oidc_server)
+        .await;
+    let response = complete_login(&app, &authorization["state"]).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    let response = complete_login(&app, &authorization["state"]).await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
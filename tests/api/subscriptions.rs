@@ -1,7 +1,10 @@
+use std::time::Duration;
+
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
 };
+use zero2prod::wakeup::{Wakeup, OUTBOX_CHANNEL};
 
 use crate::helpers::spawn_app;
 
@@ -102,6 +105,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_outbox().await;
 }
 
 #[tokio::test]
@@ -116,8 +120,103 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_outbox().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let links = app.get_confirmation_links(email_request);
     assert_eq!(links.html, links.plain_text)
 }
+
+#[tokio::test]
+async fn subscribe_does_not_wait_for_the_confirmation_email() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn failed_confirmation_emails_are_retried_later() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+
+    app.dispatch_outbox().await;
+
+    let queued = sqlx::query!("select n_attempts, last_error from outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.n_attempts, 1);
+    assert!(queued.last_error.unwrap().contains("500"));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // Nothing is due until the retry delay is over.
+    app.dispatch_outbox().await;
+    sqlx::query!("update outbox set execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_outbox().await;
+
+    let n_queued = sqlx::query!(r#"select count(*) as "n!" from outbox"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_queued, 0);
+}
+
+#[tokio::test]
+async fn subscribe_fails_if_the_confirmation_email_cannot_be_queued() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    sqlx::query!("alter table outbox drop column payload")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(500, response.status().as_u16());
+    let n_subscribers = sqlx::query!(r#"select count(*) as "n!" from subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_subscribers, 0);
+}
+
+#[tokio::test]
+async fn subscribe_wakes_the_outbox_relay_up() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let mut wakeup = Wakeup::listen(&app.db_pool, OUTBOX_CHANNEL).await;
+
+    app.post_subscriptions(body.into()).await;
+
+    tokio::time::timeout(Duration::from_secs(5), wakeup.wait(Duration::from_secs(60)))
+        .await
+        .expect("The outbox relay was not woken up");
+}
@@ -28,6 +28,7 @@ async fn test_link_returned_by_the_subscribe_returns_a_200_if_called() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_outbox().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let links = app.get_confirmation_links(email_request);
 
@@ -48,6 +49,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_outbox().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let links = app.get_confirmation_links(email_request);
 
@@ -0,0 +1,82 @@
+use reqwest::Method;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+#[tokio::test]
+async fn issue_deliveries_are_part_of_the_trace_of_the_publishing_request() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["issues:publish"]).await;
+
+    let response = app
+        .api_request(Method::POST, "issues", &token)
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .header("traceparent", format!("00-{TRACE_ID}-00f067aa0ba902b7-01"))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+
+    let traceparent =
+        sqlx::query_scalar!(r#"select trace_context->>'traceparent' as "traceparent!" from jobs"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert!(traceparent.starts_with(&format!("00-{TRACE_ID}-")));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let traceparent = email_request.headers["traceparent"].to_str().unwrap();
+    assert!(traceparent.starts_with(&format!("00-{TRACE_ID}-")));
+}
+
+#[tokio::test]
+async fn requests_without_a_trace_context_start_a_trace_of_their_own() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["issues:publish"]).await;
+
+    app.api_request(Method::POST, "issues", &token)
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    let traceparent =
+        sqlx::query_scalar!(r#"select trace_context->>'traceparent' as "traceparent!" from jobs"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert!(traceparent.starts_with("00-"));
+    assert!(!traceparent.contains(TRACE_ID));
+}
@@ -0,0 +1,217 @@
+use zero2prod::authentication::Totp;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn stored_totp(app: &TestApp) -> Totp {
+    let secret = sqlx::query!(
+        "select secret from user_totp where user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .secret;
+    Totp::from_base32(&secret).unwrap()
+}
+
+/// Turn on two-factor authentication for the test user and return the recovery codes.
+async fn enable_two_factor(app: &TestApp) -> Vec<String> {
+    let response = app
+        .post_two_factor_setup("totp", &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("otpauth://totp/Newsletter:"));
+    assert!(html_page.contains("<svg"));
+
+    let code = stored_totp(app).await.code_at(Totp::current_step());
+    let response = app
+        .post_two_factor_setup("totp/confirm", &serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Two-factor authentication is now on."));
+
+    html_page
+        .split("<li><code>")
+        .skip(1)
+        .map(|s| s.split("</code>").next().unwrap().to_owned())
+        .collect()
+}
+
+async fn log_in_with_password(app: &TestApp) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn enrolment_requires_a_valid_code() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.post_two_factor_setup("totp", &serde_json::json!({}))
+        .await;
+    let response = app
+        .post_two_factor_setup("totp/confirm", &serde_json::json!({ "code": "000000" }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>The code is invalid, please try again.</i></p>"));
+
+    // The enrolment is still pending, so the password alone is enough to log in.
+    app.post_logout().await;
+    let response = log_in_with_password(&app).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_second_factor_is_required_once_enrolled() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let recovery_codes = enable_two_factor(&app).await;
+    assert_eq!(recovery_codes.len(), 10);
+    app.post_logout().await;
+
+    let response = log_in_with_password(&app).await;
+    assert_is_redirect_to(&response, "/login/totp");
+
+    // The password alone does not authenticate the session.
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_login_second_factor("000000").await;
+    assert_is_redirect_to(&response, "/login/totp");
+
+    let code = stored_totp(&app).await.code_at(Totp::current_step() + 1);
+    let response = app.post_login_second_factor(&code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn totp_codes_cannot_be_replayed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    // The code that confirmed the enrolment.
+    let last_used_step = sqlx::query!(
+        "select last_used_step from user_totp where user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .last_used_step;
+    let code = stored_totp(&app).await.code_at(last_used_step as u64);
+    log_in_with_password(&app).await;
+    let response = app.post_login_second_factor(&code).await;
+    assert_is_redirect_to(&response, "/login/totp");
+}
+
+#[tokio::test]
+async fn recovery_codes_can_be_used_once() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let recovery_codes = enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    log_in_with_password(&app).await;
+    let response = app.post_login_second_factor(&recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    log_in_with_password(&app).await;
+    let response = app.post_login_second_factor(&recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/login/totp");
+}
+
+#[tokio::test]
+async fn too_many_invalid_codes_lock_the_second_factor() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    log_in_with_password(&app).await;
+    for _ in 0..4 {
+        let response = app.post_login_second_factor("000000").await;
+        assert_is_redirect_to(&response, "/login/totp");
+    }
+    let response = app.post_login_second_factor("000000").await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(
+        html_page.contains("<p><i>Too many invalid codes, please try again in 15 minutes.</i></p>")
+    );
+
+    // Entering the password again does not start the count afresh.
+    let response = log_in_with_password(&app).await;
+    assert_is_redirect_to(&response, "/login/totp");
+    let code = stored_totp(&app).await.code_at(Totp::current_step() + 1);
+    let response = app.post_login_second_factor(&code).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_user_disabled_after_the_password_check_is_not_let_in() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let recovery_codes = enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    log_in_with_password(&app).await;
+    sqlx::query!(
+        "update users set disabled_at = now() where user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let response = app.post_login_second_factor(&recovery_codes[0]).await;
+
+    assert_is_redirect_to(&response, "/login/totp");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn two_factor_authentication_can_be_turned_off_with_the_password() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    enable_two_factor(&app).await;
+
+    let response = app
+        .post_two_factor_setup(
+            "totp/disable",
+            &serde_json::json!({ "current_password": "wrong password" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+    assert!(html_page.contains("Two-factor authentication is on."));
+
+    let response = app
+        .post_two_factor_setup(
+            "totp/disable",
+            &serde_json::json!({ "current_password": &app.test_user.password }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>Two-factor authentication has been turned off.</i></p>"));
+
+    app.post_logout().await;
+    let response = log_in_with_password(&app).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
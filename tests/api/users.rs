@@ -0,0 +1,293 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+
+async fn login_as(app: &TestApp, role: &'static str) -> TestUser {
+    let user = TestUser::generate_with_role(role);
+    user.store(&app.db_pool).await;
+    user.login(app).await;
+    user
+}
+
+/// Invite `email` as `role` and return the token from the signup link.
+async fn invite(app: &TestApp, email: &str, role: &str) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app
+        .post_users(
+            "invitations",
+            &serde_json::json!({ "email": email, "role": role }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let links = app.get_confirmation_links(&email_request);
+    assert_eq!(links.html.path(), "/signup");
+    links
+        .html
+        .query_pairs()
+        .find(|(k, _)| k == "invitation_token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+#[tokio::test]
+async fn only_owners_can_manage_users() {
+    let app = spawn_app().await;
+    login_as(&app, "publisher").await;
+
+    let response = app.get_users().await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app
+        .post_users(
+            "invitations",
+            &serde_json::json!({ "email": "ursula@example.com", "role": "owner" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn viewers_cannot_publish_or_draft() {
+    let app = spawn_app().await;
+    login_as(&app, "viewer").await;
+
+    let response = app.get_publish_newsletter().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_publish_newsletter(&body).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app.post_admin_form("newsletters/drafts/new", &body).await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn editors_can_draft_but_not_publish() {
+    let app = spawn_app().await;
+    login_as(&app, "editor").await;
+
+    let body = serde_json::json!({
+        "title": "Draft title",
+        "text_content": "Draft body",
+        "html_content": "<p>Draft body</p>",
+    });
+    let response = app.post_admin_form("newsletters/drafts/new", &body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("<p><i>The newsletter issue has been saved as a draft.</i></p>"));
+    assert!(html_page.contains("Draft title"));
+
+    let newsletter_issue_id = sqlx::query!("select newsletter_issue_id from newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let response = app
+        .post_publish_draft(&serde_json::json!({ "newsletter_issue_id": newsletter_issue_id }))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn an_invited_user_can_sign_up_once_with_the_invited_role() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let invitation_token = invite(&app, "ursula@example.com", "editor").await;
+
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>An invitation has been sent to ursula@example.com.</i></p>"));
+    assert!(html_page.contains("ursula@example.com as editor"));
+
+    app.post_logout().await;
+    let signup_body = serde_json::json!({
+        "invitation_token": invitation_token,
+        "username": "ursula",
+        "password": "a very long password",
+        "password_check": "a very long password",
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your account has been created, you can now log in.</i></p>"));
+
+    let user = sqlx::query!("select role, email from users where username = 'ursula'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(user.role, "editor");
+    assert_eq!(user.email.as_deref(), Some("ursula@example.com"));
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "ursula",
+            "password": "a very long password",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert!(app
+        .get_admin_dashboard_html()
+        .await
+        .contains("You are signed in as editor."));
+
+    // The link is single-use.
+    app.post_logout().await;
+    let response = app
+        .post_signup(&serde_json::json!({
+            "invitation_token": invitation_token,
+            "username": "mallory",
+            "password": "another long password",
+            "password_check": "another long password",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>This invitation is invalid or has expired.</i></p>"));
+}
+
+#[tokio::test]
+async fn a_taken_username_does_not_use_up_the_invitation() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let invitation_token = invite(&app, "ursula@example.com", "viewer").await;
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "invitation_token": invitation_token,
+            "username": &app.test_user.username,
+            "password": "a very long password",
+            "password_check": "a very long password",
+        }))
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/signup?invitation_token={invitation_token}"),
+    );
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "invitation_token": invitation_token,
+            "username": "ursula",
+            "password": "a very long password",
+            "password_check": "a very long password",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn owners_can_change_roles() {
+    let app = spawn_app().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_users(
+            &format!("{}/role", viewer.user_id),
+            &serde_json::json!({ "role": "publisher" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let role = sqlx::query!("select role from users where user_id = $1", viewer.user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .role;
+    assert_eq!(role, "publisher");
+
+    let response = app
+        .post_users(
+            &format!("{}/role", app.test_user.user_id),
+            &serde_json::json!({ "role": "viewer" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>You cannot change your own role.</i></p>"));
+}
+
+#[tokio::test]
+async fn disabled_users_are_logged_out_and_cannot_log_back_in() {
+    let app = spawn_app().await;
+    let editor = login_as(&app, "editor").await;
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    sqlx::query!(
+        "update users set disabled_at = now() where user_id = $1",
+        editor.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your account has been disabled.</i></p>"));
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &editor.username,
+            "password": &editor.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn owners_can_disable_others_but_not_themselves() {
+    let app = spawn_app().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_users(
+            &format!("{}/disable", editor.user_id),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let response = app
+        .post_users(
+            &format!("{}/disable", app.test_user.user_id),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>You cannot disable your own account.</i></p>"));
+    let n_disabled =
+        sqlx::query!(r#"select count(*) as "n!" from users where disabled_at is not null"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .n;
+    assert_eq!(n_disabled, 1);
+}